@@ -1,3 +1,8 @@
+use std::collections::HashMap;
+
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
+
 use crate::shared::*;
 
 macro_rules! space {
@@ -35,24 +40,59 @@ impl AstDump for AstProgram<'_> {
 pub enum AstStatement<'s> {
     FnDef {
         name: &'s str,
-        args: Vec<&'s str>,
+        args: Vec<AstFnArg<'s>>,
+        ret: Option<AstType<'s>>,
         block: AstBlock<'s>,
     },
+    StructDef {
+        name: &'s str,
+        fields: Vec<&'s str>,
+    },
+    EnumDef {
+        name: &'s str,
+        variants: Vec<AstEnumVariant<'s>>,
+    },
+    ConstDef {
+        name: &'s str,
+        expr: AstExpr<'s>,
+    },
+    Import(&'s str),
+    // Imports are replaced by the definitions of the imported file when loading the program.
+    Module {
+        name: &'s str,
+        statements: Vec<AstStatement<'s>>,
+    },
     BlockLine(AstBlockLine<'s>),
 }
 
 impl AstDump for AstStatement<'_> {
     fn ast_dump(&self, indent: usize) -> String {
         match self {
-            AstStatement::FnDef {
-                name: _,
-                args: _,
-                block,
-            } => format!(
+            AstStatement::FnDef { block, .. } => format!(
                 "{}stmt / fndef\n{}",
                 space!(indent),
                 block.ast_dump(indent + INDENT_INC)
             ),
+            AstStatement::StructDef { .. } => format!("{}stmt / structdef", space!(indent)),
+            AstStatement::EnumDef { .. } => format!("{}stmt / enumdef", space!(indent)),
+            AstStatement::ConstDef { expr, .. } => format!(
+                "{}stmt / constdef\n{}",
+                space!(indent),
+                expr.ast_dump(indent + INDENT_INC)
+            ),
+            AstStatement::Import(_) => format!("{}stmt / import", space!(indent)),
+            AstStatement::Module {
+                name: _,
+                statements,
+            } => format!(
+                "{}stmt / module\n{}",
+                space!(indent),
+                statements
+                    .iter()
+                    .map(|e| e.ast_dump(indent + INDENT_INC))
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            ),
             AstStatement::BlockLine(line) => {
                 format!(
                     "{}stmt\n{}",
@@ -64,6 +104,39 @@ impl AstDump for AstStatement<'_> {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct AstEnumVariant<'s> {
+    pub name: &'s str,
+    pub fields: Vec<&'s str>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AstFnArg<'s> {
+    pub name: &'s str,
+    pub ty: Option<AstType<'s>>,
+    // Constant expression used when the caller omits the argument.
+    pub default: Option<AstExpr<'s>>,
+    // `...rest`: collects the remaining positional arguments into an array. Always the last one.
+    pub variadic: bool,
+}
+
+/**
+ * Optional type annotation, eg: `int`, `[str]` (array), `{int}` (map of int values),
+ * `(int, str)` (tuple) or `Point`.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum AstType<'s> {
+    Int,
+    Float,
+    Str,
+    Bool,
+    Any,
+    Array(Box<AstType<'s>>),
+    Map(Box<AstType<'s>>),
+    Tuple(Vec<AstType<'s>>),
+    Named(&'s str),
+}
+
 #[derive(Debug, Clone)]
 pub struct AstBlock<'s>(pub Vec<AstBlockLine<'s>>);
 
@@ -84,8 +157,17 @@ impl AstDump for AstBlock<'_> {
 #[derive(Debug, Clone)]
 pub enum AstBlockLine<'s> {
     Expr(AstExpr<'s>),
-    Loop(AstBlock<'s>),
-    Break,
+    // `break;` or `break expr;`, which makes the loop evaluate to `expr`. Without a label the
+    // innermost loop is targeted: `break 'outer;`.
+    Break {
+        label: Option<&'s str>,
+        value: Option<AstExpr<'s>>,
+    },
+    Continue(Option<&'s str>),
+    // `throw expr;` unwinds to the innermost `try`.
+    Throw(AstExpr<'s>),
+    // `yield expr;` makes the function a generator, suspending it with `expr` as next value.
+    Yield(AstExpr<'s>),
 }
 
 impl AstDump for AstBlockLine<'_> {
@@ -98,14 +180,33 @@ impl AstDump for AstBlockLine<'_> {
                     expr.ast_dump(indent + INDENT_INC)
                 )
             }
-            AstBlockLine::Loop(block) => {
+            AstBlockLine::Break { value: None, .. } => {
+                format!("{}blockline / break", space!(indent))
+            }
+            AstBlockLine::Continue(_) => format!("{}blockline / continue", space!(indent)),
+            AstBlockLine::Throw(expr) => {
                 format!(
-                    "{}blockline / loop\n{}",
+                    "{}blockline / throw\n{}",
                     space!(indent),
-                    block.ast_dump(indent + INDENT_INC)
+                    expr.ast_dump(indent + INDENT_INC)
+                )
+            }
+            AstBlockLine::Yield(expr) => {
+                format!(
+                    "{}blockline / yield\n{}",
+                    space!(indent),
+                    expr.ast_dump(indent + INDENT_INC)
+                )
+            }
+            AstBlockLine::Break {
+                value: Some(expr), ..
+            } => {
+                format!(
+                    "{}blockline / break\n{}",
+                    space!(indent),
+                    expr.ast_dump(indent + INDENT_INC)
                 )
             }
-            AstBlockLine::Break => format!("{}blockline / break", space!(indent)),
         }
     }
 }
@@ -113,45 +214,153 @@ impl AstDump for AstBlockLine<'_> {
 #[derive(Debug, Clone)]
 pub enum AstExpr<'s> {
     FnCall {
+        module: Option<&'s str>,
         name: &'s str,
         args: Vec<AstExpr<'s>>,
+        named_args: AstNamedArgs<'s>,
     },
     Str(&'s str),
-    Int(i32),
+    StrTemplate(Vec<AstStrPart<'s>>),
+    Int(i64),
+    Float(f64),
     Name(&'s str),
     Boolean(bool),
+    Null,
     Assignment {
         varname: &'s str,
+        ty: Option<AstType<'s>>,
         expr: Box<AstExpr<'s>>,
     },
+    // The locations are those of the operators, for runtime errors.
+    OpAssignment {
+        varname: &'s str,
+        op: Op,
+        expr: Box<AstExpr<'s>>,
+        loc: Loc,
+    },
     BinOp {
         lhs: Box<AstExpr<'s>>,
         op: Op,
         rhs: Box<AstExpr<'s>>,
+        loc: Loc,
+    },
+    // `~expr`
+    BitNot(Box<AstExpr<'s>>),
+    // Evaluates to the value of the `break` that ends it, or null. `'label: loop { ... }`
+    Loop {
+        label: Option<&'s str>,
+        block: AstBlock<'s>,
+    },
+    // `for x in expr { ... }` over a range, a string or an array.
+    For {
+        label: Option<&'s str>,
+        varname: &'s str,
+        iter: Box<AstExpr<'s>>,
+        block: AstBlock<'s>,
+    },
+    // `from..to`, or `from..=to` when inclusive.
+    Range {
+        from: Box<AstExpr<'s>>,
+        to: Box<AstExpr<'s>>,
+        inclusive: bool,
     },
     If {
         cond: Box<AstExpr<'s>>,
         true_block: AstBlock<'s>,
         false_block: Option<AstBlock<'s>>,
     },
+    // A free-standing `{ ... }` block, with its own variable scope.
+    Block(AstBlock<'s>),
+    // `try { ... } catch (e) { ... }` evaluates to the last line of the block that ran.
+    Try {
+        block: AstBlock<'s>,
+        catch_var: &'s str,
+        catch_block: AstBlock<'s>,
+    },
     ParenExpr(Box<AstExpr<'s>>),
+    Tuple(Vec<AstExpr<'s>>),
+    // `(a, b) = expr;`, where `_` discards the value.
+    TupleAssignment {
+        varnames: Vec<&'s str>,
+        expr: Box<AstExpr<'s>>,
+    },
+    Array(Vec<AstExpr<'s>>),
+    Map(Vec<(AstExpr<'s>, AstExpr<'s>)>),
+    Index {
+        expr: Box<AstExpr<'s>>,
+        index: Box<AstExpr<'s>>,
+    },
+    IndexAssignment {
+        expr: Box<AstExpr<'s>>,
+        index: Box<AstExpr<'s>>,
+        value: Box<AstExpr<'s>>,
+    },
+    StructInit {
+        name: &'s str,
+        fields: Vec<(&'s str, AstExpr<'s>)>,
+    },
+    Field {
+        expr: Box<AstExpr<'s>>,
+        field: &'s str,
+    },
+    FieldAssignment {
+        expr: Box<AstExpr<'s>>,
+        field: &'s str,
+        value: Box<AstExpr<'s>>,
+    },
+    EnumVariant {
+        enum_name: &'s str,
+        variant: &'s str,
+        args: Vec<AstExpr<'s>>,
+    },
+    Match {
+        expr: Box<AstExpr<'s>>,
+        arms: Vec<AstMatchArm<'s>>,
+    },
 }
 
 impl AstDump for AstExpr<'_> {
     fn ast_dump(&self, indent: usize) -> String {
         match self {
-            AstExpr::FnCall { .. } => format!("{}expr / fncall", space!(indent)),
+            AstExpr::FnCall {
+                args, named_args, ..
+            } => {
+                let mut out = format!("{}expr / fncall", space!(indent));
+                for arg in args.iter().chain(named_args.iter().map(|(_, arg)| arg)) {
+                    out.push('\n');
+                    out.push_str(&arg.ast_dump(indent + INDENT_INC));
+                }
+                out
+            }
             AstExpr::Str(_) => format!("{}expr / str", space!(indent)),
+            AstExpr::StrTemplate(parts) => {
+                let mut out = format!("{}expr / strtemplate", space!(indent));
+                for part in parts {
+                    if let AstStrPart::Expr(expr) = part {
+                        out.push('\n');
+                        out.push_str(&expr.ast_dump(indent + INDENT_INC));
+                    }
+                }
+                out
+            }
             AstExpr::Int(_) => format!("{}expr / int", space!(indent)),
+            AstExpr::Float(_) => format!("{}expr / float", space!(indent)),
             AstExpr::Name(_) => format!("{}expr / name", space!(indent)),
-            AstExpr::Assignment { varname: _, expr } => {
+            AstExpr::Assignment { expr, .. } => {
                 format!(
                     "{}expr / assign\n{}",
                     space!(indent),
                     (*expr).ast_dump(indent + INDENT_INC)
                 )
             }
-            AstExpr::BinOp { lhs, op: _, rhs } => {
+            AstExpr::OpAssignment { expr, .. } => {
+                format!(
+                    "{}expr / opassign\n{}",
+                    space!(indent),
+                    (*expr).ast_dump(indent + INDENT_INC)
+                )
+            }
+            AstExpr::BinOp { lhs, rhs, .. } => {
                 format!(
                     "{}expr / binop\n{}\n{}",
                     space!(indent),
@@ -159,6 +368,36 @@ impl AstDump for AstExpr<'_> {
                     (*rhs).ast_dump(indent + INDENT_INC)
                 )
             }
+            AstExpr::BitNot(expr) => {
+                format!(
+                    "{}expr / bitnot\n{}",
+                    space!(indent),
+                    (*expr).ast_dump(indent + INDENT_INC)
+                )
+            }
+            AstExpr::Loop { block, .. } => {
+                format!(
+                    "{}expr / loop\n{}",
+                    space!(indent),
+                    block.ast_dump(indent + INDENT_INC)
+                )
+            }
+            AstExpr::For { iter, block, .. } => {
+                format!(
+                    "{}expr / for\n{}\n{}",
+                    space!(indent),
+                    iter.ast_dump(indent + INDENT_INC),
+                    block.ast_dump(indent + INDENT_INC)
+                )
+            }
+            AstExpr::Range { from, to, .. } => {
+                format!(
+                    "{}expr / range\n{}\n{}",
+                    space!(indent),
+                    (*from).ast_dump(indent + INDENT_INC),
+                    (*to).ast_dump(indent + INDENT_INC)
+                )
+            }
             AstExpr::If {
                 cond: _,
                 true_block,
@@ -175,7 +414,25 @@ impl AstDump for AstExpr<'_> {
                     false_block_str,
                 )
             }
+            AstExpr::Block(block) => {
+                format!(
+                    "{}expr / block\n{}",
+                    space!(indent),
+                    block.ast_dump(indent + INDENT_INC)
+                )
+            }
+            AstExpr::Try {
+                block, catch_block, ..
+            } => {
+                format!(
+                    "{}expr / try\n{}\n{}",
+                    space!(indent),
+                    block.ast_dump(indent + INDENT_INC),
+                    catch_block.ast_dump(indent + INDENT_INC)
+                )
+            }
             AstExpr::Boolean(_) => format!("{}expr / bool", space!(indent)),
+            AstExpr::Null => format!("{}expr / null", space!(indent)),
             AstExpr::ParenExpr(inner) => {
                 format!(
                     "{}expr / parenexpr\n{}",
@@ -183,6 +440,683 @@ impl AstDump for AstExpr<'_> {
                     (*inner).ast_dump(indent + INDENT_INC)
                 )
             }
+            AstExpr::Tuple(items) => {
+                let mut out = format!("{}expr / tuple", space!(indent));
+                for item in items {
+                    out.push('\n');
+                    out.push_str(&item.ast_dump(indent + INDENT_INC));
+                }
+                out
+            }
+            AstExpr::TupleAssignment { expr, .. } => format!(
+                "{}expr / tupleassign\n{}",
+                space!(indent),
+                expr.ast_dump(indent + INDENT_INC)
+            ),
+            AstExpr::Array(items) => {
+                let mut out = format!("{}expr / array", space!(indent));
+                for item in items {
+                    out.push('\n');
+                    out.push_str(&item.ast_dump(indent + INDENT_INC));
+                }
+                out
+            }
+            AstExpr::Map(entries) => {
+                let mut out = format!("{}expr / map", space!(indent));
+                for (key, value) in entries {
+                    out.push('\n');
+                    out.push_str(&key.ast_dump(indent + INDENT_INC));
+                    out.push('\n');
+                    out.push_str(&value.ast_dump(indent + INDENT_INC));
+                }
+                out
+            }
+            AstExpr::Index { expr, index } => {
+                format!(
+                    "{}expr / index\n{}\n{}",
+                    space!(indent),
+                    (*expr).ast_dump(indent + INDENT_INC),
+                    (*index).ast_dump(indent + INDENT_INC)
+                )
+            }
+            AstExpr::IndexAssignment { expr, index, value } => {
+                format!(
+                    "{}expr / indexassign\n{}\n{}\n{}",
+                    space!(indent),
+                    (*expr).ast_dump(indent + INDENT_INC),
+                    (*index).ast_dump(indent + INDENT_INC),
+                    (*value).ast_dump(indent + INDENT_INC)
+                )
+            }
+            AstExpr::StructInit { name: _, fields } => {
+                let mut out = format!("{}expr / structinit", space!(indent));
+                for (_, value) in fields {
+                    out.push('\n');
+                    out.push_str(&value.ast_dump(indent + INDENT_INC));
+                }
+                out
+            }
+            AstExpr::Field { expr, field: _ } => {
+                format!(
+                    "{}expr / field\n{}",
+                    space!(indent),
+                    (*expr).ast_dump(indent + INDENT_INC)
+                )
+            }
+            AstExpr::FieldAssignment {
+                expr,
+                field: _,
+                value,
+            } => {
+                format!(
+                    "{}expr / fieldassign\n{}\n{}",
+                    space!(indent),
+                    (*expr).ast_dump(indent + INDENT_INC),
+                    (*value).ast_dump(indent + INDENT_INC)
+                )
+            }
+            AstExpr::EnumVariant { args, .. } => {
+                let mut out = format!("{}expr / enumvariant", space!(indent));
+                for arg in args {
+                    out.push('\n');
+                    out.push_str(&arg.ast_dump(indent + INDENT_INC));
+                }
+                out
+            }
+            AstExpr::Match { expr, arms } => {
+                let mut out = format!(
+                    "{}expr / match\n{}",
+                    space!(indent),
+                    (*expr).ast_dump(indent + INDENT_INC)
+                );
+                for arm in arms {
+                    out.push('\n');
+                    out.push_str(&arm.ast_dump(indent + INDENT_INC));
+                }
+                out
+            }
         }
     }
 }
+
+#[derive(Debug, Clone)]
+pub struct AstMatchArm<'s> {
+    pub pattern: AstPattern<'s>,
+    pub block: AstBlock<'s>,
+}
+
+impl AstDump for AstMatchArm<'_> {
+    fn ast_dump(&self, indent: usize) -> String {
+        format!(
+            "{}arm / {}\n{}",
+            space!(indent),
+            self.pattern.kind(),
+            self.block.ast_dump(indent + INDENT_INC)
+        )
+    }
+}
+
+/**
+ * `f(name: expr)`, after the positional arguments.
+ */
+pub type AstNamedArgs<'s> = Vec<(&'s str, AstExpr<'s>)>;
+
+#[derive(Debug, Clone)]
+pub enum AstStrPart<'s> {
+    Literal(String),
+    Expr(AstExpr<'s>),
+}
+
+#[derive(Debug, Clone)]
+pub enum AstPattern<'s> {
+    Wildcard,
+    Binding(&'s str),
+    Int(i64),
+    Str(&'s str),
+    Boolean(bool),
+    Variant {
+        enum_name: &'s str,
+        variant: &'s str,
+        bindings: Vec<&'s str>,
+    },
+}
+
+impl AstPattern<'_> {
+    fn kind(&self) -> &'static str {
+        match self {
+            AstPattern::Wildcard => "wildcard",
+            AstPattern::Binding(_) => "binding",
+            AstPattern::Int(_) => "int",
+            AstPattern::Str(_) => "str",
+            AstPattern::Boolean(_) => "bool",
+            AstPattern::Variant { .. } => "variant",
+        }
+    }
+}
+
+/**
+ * Enum name -> variants with their payload size, in declaration order.
+ */
+pub type EnumDefs = HashMap<String, Vec<(String, usize)>>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConstValue {
+    Int(i64),
+    // Int constants that overflow are promoted, as at runtime.
+    BigInt(BigInt),
+    Float(f64),
+    Str(String),
+    Bool(bool),
+}
+
+impl ConstValue {
+    /**
+     * Ints are promoted to floats when mixed with them.
+     */
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            ConstValue::Int(v) => Some(*v as f64),
+            ConstValue::BigInt(v) => v.to_f64(),
+            ConstValue::Float(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    fn as_big_int(&self) -> Option<BigInt> {
+        match self {
+            ConstValue::Int(v) => Some(BigInt::from(*v)),
+            ConstValue::BigInt(v) => Some(v.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl From<IntResult> for ConstValue {
+    fn from(value: IntResult) -> ConstValue {
+        match value {
+            IntResult::Int(v) => ConstValue::Int(v),
+            IntResult::Big(v) => ConstValue::BigInt(v),
+        }
+    }
+}
+
+/**
+ * Constant name -> value.
+ */
+pub type ConstDefs = HashMap<String, ConstValue>;
+
+/**
+ * Evaluates every `const` of the program (including modules) in declaration order, so constants
+ * can only refer to constants defined before them.
+ */
+pub fn collect_constants(statements: &[AstStatement]) -> Result<ConstDefs, Error> {
+    let mut constants = HashMap::new();
+    collect_constants_into(statements, &mut constants)?;
+    Ok(constants)
+}
+
+fn collect_constants_into(
+    statements: &[AstStatement],
+    constants: &mut ConstDefs,
+) -> Result<(), Error> {
+    for statement in statements {
+        match statement {
+            AstStatement::ConstDef { name, expr } => {
+                if constants.contains_key(*name) {
+                    return Err(format!("Constant '{}' is already defined", name).into());
+                }
+                let value = const_eval(expr, constants)?;
+                constants.insert(name.to_string(), value);
+            }
+            AstStatement::Module { statements, .. } => {
+                collect_constants_into(statements, constants)?
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/**
+ * Argument of a function as seen by its callers, with the default value evaluated.
+ */
+#[derive(Debug, Clone)]
+pub struct FnParam {
+    pub name: String,
+    pub default: Option<ConstValue>,
+    pub variadic: bool,
+}
+
+pub fn fn_params(
+    fn_name: &str,
+    args: &[AstFnArg],
+    constants: &ConstDefs,
+) -> Result<Vec<FnParam>, Error> {
+    let mut params = vec![];
+    for arg in args {
+        let default = match &arg.default {
+            Some(expr) => Some(const_eval(expr, constants).map_err(|err| {
+                format!(
+                    "Default value of argument '{}' of '{}' must be a constant: {}",
+                    arg.name, fn_name, err
+                )
+            })?),
+            None => None,
+        };
+        params.push(FnParam {
+            name: arg.name.to_string(),
+            default,
+            variadic: arg.variadic,
+        });
+    }
+    Ok(params)
+}
+
+#[derive(Debug)]
+pub enum CallArg<T> {
+    Given(T),
+    Default(ConstValue),
+    // The arguments collected by a variadic parameter.
+    Rest(Vec<T>),
+}
+
+/**
+ * Matches the positional and named arguments of a call to the parameters of the function, so the
+ * backends can pass them in parameter order.
+ */
+pub fn resolve_call_args<T>(
+    fn_name: &str,
+    params: &[FnParam],
+    args: Vec<T>,
+    named_args: Vec<(&str, T)>,
+) -> Result<Vec<CallArg<T>>, Error> {
+    let (params, rest_param) = match params.split_last() {
+        Some((rest_param, params)) if rest_param.variadic => (params, Some(rest_param)),
+        _ => (params, None),
+    };
+
+    let mut args = args;
+    let rest = match rest_param {
+        Some(_) if args.len() > params.len() => args.split_off(params.len()),
+        Some(_) => vec![],
+        None if args.len() > params.len() => {
+            return Err(format!(
+                "Function '{}' expects {} arguments, got {}",
+                fn_name,
+                params.len(),
+                args.len()
+            )
+            .into())
+        }
+        None => vec![],
+    };
+
+    let mut resolved: Vec<Option<T>> = args.into_iter().map(Some).collect();
+    resolved.resize_with(params.len(), || None);
+    for (name, arg) in named_args {
+        if rest_param.is_some_and(|rest_param| rest_param.name == name) {
+            return Err(format!(
+                "Variadic argument '{}' of '{}' cannot be given by name",
+                name, fn_name
+            )
+            .into());
+        }
+        let pos = params
+            .iter()
+            .position(|param| param.name == name)
+            .ok_or(format!("Function '{}' has no argument '{}'", fn_name, name))?;
+        if resolved[pos].is_some() {
+            return Err(format!(
+                "Argument '{}' of '{}' is given more than once",
+                name, fn_name
+            )
+            .into());
+        }
+        resolved[pos] = Some(arg);
+    }
+
+    let mut call_args = resolved
+        .into_iter()
+        .zip(params)
+        .map(|(arg, param)| match (arg, &param.default) {
+            (Some(arg), _) => Ok(CallArg::Given(arg)),
+            (None, Some(default)) => Ok(CallArg::Default(default.clone())),
+            (None, None) => {
+                Err(format!("Missing argument '{}' of '{}'", param.name, fn_name).into())
+            }
+        })
+        .collect::<Result<Vec<CallArg<T>>, Error>>()?;
+    if rest_param.is_some() {
+        call_args.push(CallArg::Rest(rest));
+    }
+
+    Ok(call_args)
+}
+
+/**
+ * Compile time evaluation of literals, constants and operators.
+ */
+pub fn const_eval(expr: &AstExpr, constants: &ConstDefs) -> Result<ConstValue, Error> {
+    match expr {
+        AstExpr::Int(v) => Ok(ConstValue::Int(*v)),
+        AstExpr::Float(v) => Ok(ConstValue::Float(*v)),
+        AstExpr::Str(s) => Ok(ConstValue::Str(s.to_string())),
+        AstExpr::Boolean(b) => Ok(ConstValue::Bool(*b)),
+        AstExpr::Name(name) => constants
+            .get(*name)
+            .cloned()
+            .ok_or(format!("'{}' is not a constant", name).into()),
+        AstExpr::ParenExpr(expr) => const_eval(expr, constants),
+        AstExpr::BitNot(expr) => match const_eval(expr, constants)? {
+            ConstValue::Int(v) => Ok(ConstValue::Int(!v)),
+            ConstValue::BigInt(v) => Err(bitwise_range_error(&v)),
+            other => Err(format!("Incompatible constant type: ~{:?}", other).into()),
+        },
+        AstExpr::BinOp { lhs, op, rhs, .. } => {
+            let lhs = const_eval(lhs, constants)?;
+            let rhs = const_eval(rhs, constants)?;
+
+            Ok(match (op, lhs, rhs) {
+                (
+                    Op::Div | Op::Mod,
+                    ConstValue::Int(_) | ConstValue::BigInt(_),
+                    ConstValue::Int(0),
+                ) => return Err("Division by zero in constant".into()),
+                (op, lhs @ ConstValue::Float(_), rhs) | (op, lhs, rhs @ ConstValue::Float(_)) => {
+                    const_eval_float(op, lhs, rhs)?
+                }
+                (Op::Add, ConstValue::Str(a), ConstValue::Str(b)) => ConstValue::Str(a + &b),
+                (op, lhs @ ConstValue::BigInt(_), rhs) | (op, lhs, rhs @ ConstValue::BigInt(_)) => {
+                    const_eval_big_int(op, lhs, rhs)?
+                }
+                (op, ConstValue::Int(a), ConstValue::Int(b)) if op.is_arith() => {
+                    int_arith(op, a, b)?.expect("Arithmetic operator").into()
+                }
+                (Op::BitAnd, ConstValue::Int(a), ConstValue::Int(b)) => ConstValue::Int(a & b),
+                (Op::BitOr, ConstValue::Int(a), ConstValue::Int(b)) => ConstValue::Int(a | b),
+                (Op::BitXor, ConstValue::Int(a), ConstValue::Int(b)) => ConstValue::Int(a ^ b),
+                (Op::Shl, ConstValue::Int(a), ConstValue::Int(b)) => {
+                    ConstValue::Int(int_shl(a, b)?)
+                }
+                (Op::Shr, ConstValue::Int(a), ConstValue::Int(b)) => {
+                    ConstValue::Int(int_shr(a, b)?)
+                }
+                (Op::Eq, a, b) => ConstValue::Bool(a == b),
+                (Op::Lt, ConstValue::Int(a), ConstValue::Int(b)) => ConstValue::Bool(a < b),
+                (Op::Lte, ConstValue::Int(a), ConstValue::Int(b)) => ConstValue::Bool(a <= b),
+                (Op::Gt, ConstValue::Int(a), ConstValue::Int(b)) => ConstValue::Bool(a > b),
+                (Op::Gte, ConstValue::Int(a), ConstValue::Int(b)) => ConstValue::Bool(a >= b),
+                (Op::Lt, ConstValue::Str(a), ConstValue::Str(b)) => ConstValue::Bool(a < b),
+                (Op::Lte, ConstValue::Str(a), ConstValue::Str(b)) => ConstValue::Bool(a <= b),
+                (Op::Gt, ConstValue::Str(a), ConstValue::Str(b)) => ConstValue::Bool(a > b),
+                (Op::Gte, ConstValue::Str(a), ConstValue::Str(b)) => ConstValue::Bool(a >= b),
+                (op, lhs, rhs) => {
+                    return Err(format!(
+                        "Incompatible constant types: {:?} {:?} {:?}",
+                        lhs, op, rhs
+                    )
+                    .into())
+                }
+            })
+        }
+        _ => Err("Constant expressions may only use literals, constants and operators".into()),
+    }
+}
+
+fn const_eval_big_int(op: &Op, lhs: ConstValue, rhs: ConstValue) -> Result<ConstValue, Error> {
+    if let (Some(a), Some(b)) = (lhs.as_big_int(), rhs.as_big_int()) {
+        if op.is_bitwise() {
+            let big = if a.to_i64().is_none() { &a } else { &b };
+            return Err(bitwise_range_error(big));
+        }
+        if let Some(v) = big_arith(op, &a, &b)? {
+            return Ok(v.into());
+        }
+        if let Some(b) = compare(op, a, b) {
+            return Ok(ConstValue::Bool(b));
+        }
+    }
+    Err(format!("Incompatible constant types: {:?} {:?} {:?}", lhs, op, rhs).into())
+}
+
+fn const_eval_float(op: &Op, lhs: ConstValue, rhs: ConstValue) -> Result<ConstValue, Error> {
+    if let (Some(a), Some(b)) = (lhs.as_float(), rhs.as_float()) {
+        if let Some(v) = float_arith(op, a, b) {
+            return Ok(ConstValue::Float(v));
+        }
+        if let Some(b) = compare(op, a, b) {
+            return Ok(ConstValue::Bool(b));
+        }
+    }
+    Err(format!("Incompatible constant types: {:?} {:?} {:?}", lhs, op, rhs).into())
+}
+
+/**
+ * A function is a generator when its body yields. Yields are only looked for in statements, the
+ * lines of blocks nested in `if`, loops, block expressions, `try` and `match` arms.
+ */
+pub fn is_generator(block: &AstBlock) -> bool {
+    block.0.iter().any(|line| match line {
+        AstBlockLine::Yield(_) => true,
+        AstBlockLine::Expr(AstExpr::If {
+            true_block,
+            false_block,
+            ..
+        }) => is_generator(true_block) || false_block.as_ref().is_some_and(is_generator),
+        AstBlockLine::Expr(AstExpr::Loop { block, .. })
+        | AstBlockLine::Expr(AstExpr::For { block, .. })
+        | AstBlockLine::Expr(AstExpr::Block(block)) => is_generator(block),
+        AstBlockLine::Expr(AstExpr::Try {
+            block, catch_block, ..
+        }) => is_generator(block) || is_generator(catch_block),
+        AstBlockLine::Expr(AstExpr::Match { arms, .. }) => {
+            arms.iter().any(|arm| is_generator(&arm.block))
+        }
+        _ => false,
+    })
+}
+
+/**
+ * Verifies that yields are only on the lines `is_generator` looks at, the only places where a
+ * generator can be suspended.
+ */
+pub fn check_yield_positions(block: &AstBlock) -> Result<(), Error> {
+    for line in &block.0 {
+        match line {
+            AstBlockLine::Expr(AstExpr::If {
+                cond,
+                true_block,
+                false_block,
+            }) => {
+                check_no_yield(cond)?;
+                check_yield_positions(true_block)?;
+                if let Some(false_block) = false_block {
+                    check_yield_positions(false_block)?;
+                }
+            }
+            AstBlockLine::Expr(AstExpr::Loop { block, .. })
+            | AstBlockLine::Expr(AstExpr::Block(block)) => check_yield_positions(block)?,
+            AstBlockLine::Expr(AstExpr::For { iter, block, .. }) => {
+                check_no_yield(iter)?;
+                check_yield_positions(block)?;
+            }
+            AstBlockLine::Expr(AstExpr::Try {
+                block, catch_block, ..
+            }) => {
+                check_yield_positions(block)?;
+                check_yield_positions(catch_block)?;
+            }
+            AstBlockLine::Expr(AstExpr::Match { expr, arms }) => {
+                check_no_yield(expr)?;
+                for arm in arms {
+                    check_yield_positions(&arm.block)?;
+                }
+            }
+            AstBlockLine::Expr(expr)
+            | AstBlockLine::Yield(expr)
+            | AstBlockLine::Throw(expr)
+            | AstBlockLine::Break {
+                value: Some(expr), ..
+            } => check_no_yield(expr)?,
+            AstBlockLine::Break { value: None, .. } | AstBlockLine::Continue(_) => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn check_no_yield(expr: &AstExpr) -> Result<(), Error> {
+    if expr_yields(expr) {
+        return Err("Yield can only be used as a statement, not inside an expression".into());
+    }
+
+    Ok(())
+}
+
+fn expr_yields(expr: &AstExpr) -> bool {
+    let block_yields = |block: &AstBlock| {
+        block.0.iter().any(|line| match line {
+            AstBlockLine::Yield(_) => true,
+            AstBlockLine::Expr(expr)
+            | AstBlockLine::Throw(expr)
+            | AstBlockLine::Break {
+                value: Some(expr), ..
+            } => expr_yields(expr),
+            AstBlockLine::Break { value: None, .. } | AstBlockLine::Continue(_) => false,
+        })
+    };
+
+    match expr {
+        AstExpr::FnCall {
+            args, named_args, ..
+        } => args
+            .iter()
+            .chain(named_args.iter().map(|(_, arg)| arg))
+            .any(expr_yields),
+        AstExpr::StrTemplate(parts) => parts
+            .iter()
+            .any(|part| matches!(part, AstStrPart::Expr(expr) if expr_yields(expr))),
+        AstExpr::Str(_)
+        | AstExpr::Int(_)
+        | AstExpr::Float(_)
+        | AstExpr::Name(_)
+        | AstExpr::Boolean(_)
+        | AstExpr::Null => false,
+        AstExpr::Assignment { expr, .. }
+        | AstExpr::OpAssignment { expr, .. }
+        | AstExpr::TupleAssignment { expr, .. }
+        | AstExpr::BitNot(expr)
+        | AstExpr::ParenExpr(expr)
+        | AstExpr::Field { expr, .. } => expr_yields(expr),
+        AstExpr::BinOp { lhs, rhs, .. }
+        | AstExpr::Range {
+            from: lhs, to: rhs, ..
+        }
+        | AstExpr::Index {
+            expr: lhs,
+            index: rhs,
+        }
+        | AstExpr::FieldAssignment {
+            expr: lhs,
+            value: rhs,
+            ..
+        } => expr_yields(lhs) || expr_yields(rhs),
+        AstExpr::IndexAssignment { expr, index, value } => {
+            expr_yields(expr) || expr_yields(index) || expr_yields(value)
+        }
+        AstExpr::Loop { block, .. } | AstExpr::Block(block) => block_yields(block),
+        AstExpr::For { iter, block, .. } => expr_yields(iter) || block_yields(block),
+        AstExpr::If {
+            cond,
+            true_block,
+            false_block,
+        } => {
+            expr_yields(cond)
+                || block_yields(true_block)
+                || false_block.as_ref().is_some_and(block_yields)
+        }
+        AstExpr::Try {
+            block, catch_block, ..
+        } => block_yields(block) || block_yields(catch_block),
+        AstExpr::Tuple(items)
+        | AstExpr::Array(items)
+        | AstExpr::EnumVariant { args: items, .. } => items.iter().any(expr_yields),
+        AstExpr::Map(entries) => entries
+            .iter()
+            .any(|(key, value)| expr_yields(key) || expr_yields(value)),
+        AstExpr::StructInit { fields, .. } => fields.iter().any(|(_, value)| expr_yields(value)),
+        AstExpr::Match { expr, arms } => {
+            expr_yields(expr) || arms.iter().any(|arm| block_yields(&arm.block))
+        }
+    }
+}
+
+/**
+ * Verifies that the arms of a match cover every possible value. Only a wildcard (or binding), both
+ * booleans or every variant of a single enum are considered complete.
+ */
+pub fn check_match_exhaustive(arms: &[AstMatchArm], enums: &EnumDefs) -> Result<(), Error> {
+    let mut enum_name = None;
+    let mut covered_variants = vec![];
+    let mut covered_bools = vec![];
+    let mut has_catch_all = false;
+
+    for arm in arms {
+        match &arm.pattern {
+            AstPattern::Wildcard | AstPattern::Binding(_) => has_catch_all = true,
+            AstPattern::Boolean(b) => covered_bools.push(*b),
+            AstPattern::Variant {
+                enum_name: pattern_enum_name,
+                variant,
+                bindings,
+            } => {
+                if enum_name.get_or_insert(*pattern_enum_name) != pattern_enum_name {
+                    return Err("Match patterns mix variants of different enums".into());
+                }
+
+                let variants = enums
+                    .get(*pattern_enum_name)
+                    .ok_or(format!("Missing enum '{}'", pattern_enum_name))?;
+                let (_, size) =
+                    variants
+                        .iter()
+                        .find(|(name, _)| name == variant)
+                        .ok_or(format!(
+                            "Enum '{}' has no variant '{}'",
+                            pattern_enum_name, variant
+                        ))?;
+                if *size != bindings.len() {
+                    return Err(format!(
+                        "Pattern {}::{} expects {} bindings, got {}",
+                        pattern_enum_name,
+                        variant,
+                        size,
+                        bindings.len()
+                    )
+                    .into());
+                }
+
+                covered_variants.push(*variant);
+            }
+            AstPattern::Int(_) | AstPattern::Str(_) => {}
+        }
+    }
+
+    if has_catch_all || (covered_bools.contains(&true) && covered_bools.contains(&false)) {
+        return Ok(());
+    }
+
+    if let Some(enum_name) = enum_name {
+        for (variant, _) in &enums[enum_name] {
+            if !covered_variants.contains(&variant.as_str()) {
+                return Err(
+                    format!("Non-exhaustive match: missing {}::{}", enum_name, variant).into(),
+                );
+            }
+        }
+        return Ok(());
+    }
+
+    Err("Non-exhaustive match: missing wildcard arm".into())
+}
@@ -1,4 +1,11 @@
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::rc::Rc;
+
+use indexmap::IndexMap;
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
 
 use crate::ast::*;
 use crate::shared::*;
@@ -6,45 +13,391 @@ use crate::shared::*;
 macro_rules! ctrl_exec {
     ($interpret:expr) => {
         match $interpret {
-            CtrlResult::Break => return Ok(CtrlResult::Break),
+            CtrlResult::Break(label, value) => return Ok(CtrlResult::Break(label, value)),
+            CtrlResult::Continue(label) => return Ok(CtrlResult::Continue(label)),
+            CtrlResult::Throw(value) => return Ok(CtrlResult::Throw(value)),
             CtrlResult::Other(v) => v,
         }
     };
 }
 
+// Functions are keyed by their module (`None` for the main program) and name.
+type FnKey<'s> = (Option<&'s str>, &'s str);
+
+struct Function<'s> {
+    args: Vec<&'s str>,
+    // To match call arguments to `args`.
+    params: Vec<FnParam>,
+    block: AstBlock<'s>,
+    // Calls return a generator instead of running the block.
+    generator: bool,
+}
+
 struct Scope<'s> {
-    functions: HashMap<&'s str, (Vec<&'s str>, AstBlock<'s>)>,
+    functions: HashMap<FnKey<'s>, Function<'s>>,
+    structs: HashMap<&'s str, Vec<&'s str>>,
+    enums: EnumDefs,
     variables: HashMap<&'s str, ExprResult>,
+    // Module of the function running in this frame.
+    module: Option<&'s str>,
 }
 
 impl<'s> Scope<'s> {
     fn new() -> Scope<'s> {
         Scope {
             functions: HashMap::new(),
+            structs: HashMap::new(),
+            enums: HashMap::new(),
             variables: HashMap::new(),
+            module: None,
         }
     }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum ExprResult {
-    Int(i32),
+    Int(i64),
+    // Ints that do not fit in an i64, never holds one that does.
+    BigInt(Rc<BigInt>),
+    Float(f64),
     Str(String),
     Bool(bool),
+    Array(Rc<RefCell<Vec<ExprResult>>>),
+    // Iterated in insertion order.
+    Map(Rc<RefCell<IndexMap<MapKey, ExprResult>>>),
+    Record(Rc<RefCell<Record>>),
+    Enum(Rc<EnumValue>),
+    // Tuples are immutable, so they are copied around.
+    Tuple(Vec<ExprResult>),
+    Range { from: i64, to: i64, inclusive: bool },
+    Generator(Rc<GeneratorRef>),
     Null,
 }
 
+/**
+ * Reference to a generator of the interpreter, whose slot is released for reuse once the last
+ * reference is dropped.
+ */
+pub struct GeneratorRef {
+    id: usize,
+    released: Rc<RefCell<Vec<usize>>>,
+}
+
+impl Drop for GeneratorRef {
+    fn drop(&mut self) {
+        self.released.borrow_mut().push(self.id);
+    }
+}
+
+impl PartialEq for GeneratorRef {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl fmt::Debug for GeneratorRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.id)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnumValue {
+    pub enum_name: String,
+    pub variant: String,
+    pub values: Vec<ExprResult>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub name: String,
+    // In declaration order.
+    pub fields: Vec<(String, ExprResult)>,
+}
+
+impl Record {
+    fn field_pos(&self, field: &str) -> Result<usize, Error> {
+        self.fields
+            .iter()
+            .position(|(name, _)| name == field)
+            .ok_or(format!("Struct '{}' has no field '{}'", self.name, field).into())
+    }
+}
+
+impl ExprResult {
+    fn new_array(items: Vec<ExprResult>) -> ExprResult {
+        ExprResult::Array(Rc::new(RefCell::new(items)))
+    }
+
+    fn new_map(entries: IndexMap<MapKey, ExprResult>) -> ExprResult {
+        ExprResult::Map(Rc::new(RefCell::new(entries)))
+    }
+
+    fn as_float(&self) -> Option<f64> {
+        match self {
+            ExprResult::Int(v) => Some(*v as f64),
+            ExprResult::BigInt(v) => v.to_f64(),
+            ExprResult::Float(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    fn as_big_int(&self) -> Option<BigInt> {
+        match self {
+            ExprResult::Int(v) => Some(BigInt::from(*v)),
+            ExprResult::BigInt(v) => Some(v.as_ref().clone()),
+            _ => None,
+        }
+    }
+
+    fn is_truthy(&self) -> bool {
+        match self {
+            ExprResult::Bool(b) => *b,
+            ExprResult::Null => false,
+            ExprResult::Int(v) => *v != 0,
+            ExprResult::BigInt(_) => true,
+            ExprResult::Float(v) => *v != 0.0,
+            ExprResult::Str(s) => !s.is_empty(),
+            ExprResult::Array(items) => !items.borrow().is_empty(),
+            ExprResult::Map(entries) => !entries.borrow().is_empty(),
+            ExprResult::Record(_) => true,
+            ExprResult::Enum(_) => true,
+            ExprResult::Tuple(_) => true,
+            ExprResult::Range {
+                from,
+                to,
+                inclusive,
+            } => from < to || (*inclusive && from == to),
+            ExprResult::Generator(_) => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MapKey {
+    Int(i64),
+    Str(String),
+}
+
+impl TryFrom<ExprResult> for MapKey {
+    type Error = Error;
+
+    fn try_from(value: ExprResult) -> Result<MapKey, Error> {
+        match value {
+            ExprResult::Int(v) => Ok(MapKey::Int(v)),
+            ExprResult::Str(s) => Ok(MapKey::Str(s)),
+            other => Err(format!("Invalid map key: {:?}", other).into()),
+        }
+    }
+}
+
+impl From<MapKey> for ExprResult {
+    fn from(key: MapKey) -> ExprResult {
+        match key {
+            MapKey::Int(v) => ExprResult::Int(v),
+            MapKey::Str(s) => ExprResult::Str(s),
+        }
+    }
+}
+
+impl From<IntResult> for ExprResult {
+    fn from(value: IntResult) -> ExprResult {
+        match value {
+            IntResult::Int(v) => ExprResult::Int(v),
+            IntResult::Big(v) => ExprResult::BigInt(Rc::new(v)),
+        }
+    }
+}
+
+impl From<&ConstValue> for ExprResult {
+    fn from(value: &ConstValue) -> ExprResult {
+        match value {
+            ConstValue::Int(v) => ExprResult::Int(*v),
+            ConstValue::BigInt(v) => ExprResult::BigInt(Rc::new(v.clone())),
+            ConstValue::Float(v) => ExprResult::Float(*v),
+            ConstValue::Str(s) => ExprResult::Str(s.clone()),
+            ConstValue::Bool(b) => ExprResult::Bool(*b),
+        }
+    }
+}
+
+impl fmt::Display for MapKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MapKey::Int(v) => write!(f, "{}", v),
+            MapKey::Str(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl fmt::Display for ExprResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExprResult::Null => write!(f, "null"),
+            ExprResult::Int(v) => write!(f, "{}", v),
+            ExprResult::BigInt(v) => write!(f, "{}", v),
+            // Debug formatting keeps the fraction of whole floats, e.g. "2.0".
+            ExprResult::Float(v) => write!(f, "{:?}", v),
+            ExprResult::Str(s) => write!(f, "{}", s),
+            ExprResult::Bool(b) => write!(f, "{}", b),
+            ExprResult::Array(items) => write!(
+                f,
+                "[{}]",
+                items
+                    .borrow()
+                    .iter()
+                    .map(|item| item.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            ExprResult::Map(entries) => write!(
+                f,
+                "{{{}}}",
+                entries
+                    .borrow()
+                    .iter()
+                    .map(|(key, value)| format!("{}: {}", key, value))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            ExprResult::Record(record) => {
+                let record = record.borrow();
+                write!(
+                    f,
+                    "{} {{ {} }}",
+                    record.name,
+                    record
+                        .fields
+                        .iter()
+                        .map(|(name, value)| format!("{}: {}", name, value))
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                )
+            }
+            ExprResult::Enum(value) => {
+                write!(f, "{}::{}", value.enum_name, value.variant)?;
+                if !value.values.is_empty() {
+                    write!(
+                        f,
+                        "({})",
+                        value
+                            .values
+                            .iter()
+                            .map(|value| value.to_string())
+                            .collect::<Vec<String>>()
+                            .join(", ")
+                    )?;
+                }
+                Ok(())
+            }
+            ExprResult::Tuple(items) => {
+                let items = items
+                    .iter()
+                    .map(|item| item.to_string())
+                    .collect::<Vec<String>>();
+                match items.len() {
+                    1 => write!(f, "({},)", items[0]),
+                    _ => write!(f, "({})", items.join(", ")),
+                }
+            }
+            ExprResult::Range {
+                from,
+                to,
+                inclusive,
+            } => write!(f, "{}{}{}", from, if *inclusive { "..=" } else { ".." }, to),
+            ExprResult::Generator(_) => write!(f, "<generator>"),
+        }
+    }
+}
+
 enum CtrlResult<T> {
-    Break,
+    // The targeted loop label (`None` for the innermost loop) and the value of `break expr;`,
+    // null for a plain `break;`.
+    Break(Option<String>, ExprResult),
+    Continue(Option<String>),
+    // A value thrown by `throw`, unwinding to the innermost `try`.
+    Throw(ExprResult),
     Other(T),
 }
 
+impl<T> CtrlResult<T> {
+    fn map<U>(self, f: impl FnOnce(T) -> U) -> CtrlResult<U> {
+        match self {
+            CtrlResult::Break(label, value) => CtrlResult::Break(label, value),
+            CtrlResult::Continue(label) => CtrlResult::Continue(label),
+            CtrlResult::Throw(value) => CtrlResult::Throw(value),
+            CtrlResult::Other(other) => CtrlResult::Other(f(other)),
+        }
+    }
+}
+
 type CtrlOrExprResult = CtrlResult<ExprResult>;
 type CtrlOrMaybeExprResult = CtrlResult<Option<ExprResult>>;
 
+/**
+ * Generators run on their own stack of frames instead of recursing, so they can stop at a `yield`
+ * and continue from there on the next resume.
+ */
+enum Generator<'s> {
+    Suspended {
+        scope: Box<Scope<'s>>,
+        frames: Vec<GeneratorFrame<'s>>,
+    },
+    Running,
+    Done,
+}
+
+enum GeneratorFrame<'s> {
+    // Lines left to run, and the variables to keep at the end of a block expression.
+    Block {
+        lines: VecDeque<AstBlockLine<'s>>,
+        outer: Option<HashSet<&'s str>>,
+    },
+    Loop {
+        label: Option<&'s str>,
+        block: AstBlock<'s>,
+    },
+    For {
+        label: Option<&'s str>,
+        varname: &'s str,
+        items: ForItems,
+        block: AstBlock<'s>,
+    },
+    // Below the frame of the `try` block, and the interpreter frame count to unwind to.
+    Try {
+        catch_var: &'s str,
+        catch_block: AstBlock<'s>,
+        frame_count: usize,
+    },
+}
+
+impl<'s> GeneratorFrame<'s> {
+    fn block(block: AstBlock<'s>, outer: Option<HashSet<&'s str>>) -> GeneratorFrame<'s> {
+        GeneratorFrame::Block {
+            lines: block.0.into(),
+            outer,
+        }
+    }
+}
+
+enum ForItems {
+    Values(std::vec::IntoIter<ExprResult>),
+    // Counts up lazily, `next` is None once the counter overflows.
+    Range {
+        next: Option<i64>,
+        end: i64,
+        inclusive: bool,
+    },
+    Generator(Rc<GeneratorRef>),
+}
+
 pub struct Interpreter<'s> {
     global_frame: Scope<'s>,
     frames: Vec<Scope<'s>>,
+    constants: ConstDefs,
+    generators: Vec<Generator<'s>>,
+    // Slots of generators that are no longer referenced.
+    released_generators: Rc<RefCell<Vec<usize>>>,
 }
 
 impl<'s> Interpreter<'s> {
@@ -52,32 +405,83 @@ impl<'s> Interpreter<'s> {
         Interpreter {
             global_frame: Scope::new(),
             frames: vec![Scope::new()],
+            constants: HashMap::new(),
+            generators: vec![],
+            released_generators: Rc::new(RefCell::new(vec![])),
         }
     }
 
     pub fn interpret(&mut self, program: AstProgram<'s>) -> Result<Option<ExprResult>, Error> {
+        self.constants
+            .extend(collect_constants(&program.statements)?);
+
         let mut last_result = None;
         for statement in program.statements {
             match statement {
-                AstStatement::FnDef { name, args, block } => {
-                    self.interpret_fn_def(name, args, block)
-                }
                 AstStatement::BlockLine(line) => {
                     last_result = match self.interpret_block_line(line)? {
-                        CtrlResult::Break => {
+                        CtrlResult::Break(..) => {
                             return Err("Break from the program out of loop context".into())
                         }
+                        CtrlResult::Continue(_) => {
+                            return Err("Continue from the program out of loop context".into())
+                        }
+                        CtrlResult::Throw(value) => {
+                            return Err(format!("Uncaught exception: {}", value).into())
+                        }
                         CtrlResult::Other(other) => other,
                     }
                 }
+                AstStatement::Module { name, statements } => {
+                    for statement in statements {
+                        self.interpret_definition(Some(name), statement)?;
+                    }
+                }
+                definition => self.interpret_definition(None, definition)?,
             };
         }
 
         Ok(last_result)
     }
 
-    fn interpret_fn_def(&mut self, name: &'s str, args: Vec<&'s str>, block: AstBlock<'s>) {
-        self.global_frame.functions.insert(name, (args, block));
+    fn interpret_definition(
+        &mut self,
+        module: Option<&'s str>,
+        statement: AstStatement<'s>,
+    ) -> Result<(), Error> {
+        match statement {
+            AstStatement::FnDef {
+                name, args, block, ..
+            } => {
+                let function = Function {
+                    args: args.iter().map(|arg| arg.name).collect(),
+                    params: fn_params(name, &args, &self.constants)?,
+                    generator: is_generator(&block),
+                    block,
+                };
+                self.global_frame.functions.insert((module, name), function);
+            }
+            AstStatement::StructDef { name, fields } => {
+                self.global_frame.structs.insert(name, fields);
+            }
+            AstStatement::EnumDef { name, variants } => {
+                self.global_frame.enums.insert(
+                    name.to_string(),
+                    variants
+                        .iter()
+                        .map(|variant| (variant.name.to_string(), variant.fields.len()))
+                        .collect(),
+                );
+            }
+            // Evaluated before running the program.
+            AstStatement::ConstDef { .. } => {}
+            AstStatement::Import(path) => {
+                return Err(format!("Unresolved import '{}'", path).into())
+            }
+            _ => return Err("Modules may only contain definitions".into()),
+        };
+
+        Ok(())
     }
 
     fn interpret_block_line(
@@ -85,569 +489,2807 @@ impl<'s> Interpreter<'s> {
         line: AstBlockLine<'s>,
     ) -> Result<CtrlOrMaybeExprResult, Error> {
         match line {
-            AstBlockLine::Expr(expr) => match self.interpret_expr(expr)? {
-                CtrlResult::Break => Ok(CtrlResult::Break),
-                CtrlResult::Other(other) => Ok(CtrlResult::Other(Some(other))),
-            },
-            AstBlockLine::Loop(block) => {
-                self.interpret_loop(block)?;
-                Ok(CtrlResult::Other(None))
-            }
-            AstBlockLine::Break => Ok(CtrlResult::Break),
+            AstBlockLine::Expr(expr) => Ok(self.interpret_expr(expr)?.map(Some)),
+            AstBlockLine::Break { label, value } => self.interpret_break(label, value),
+            AstBlockLine::Continue(label) => Ok(CtrlResult::Continue(label.map(String::from))),
+            AstBlockLine::Throw(expr) => self.interpret_throw(expr),
+            AstBlockLine::Yield(_) => Err("Yield outside of a generator".into()),
         }
     }
 
-    fn interpret_loop(&mut self, block: AstBlock<'s>) -> Result<(), Error> {
-        loop {
-            match self.interpret_block(block.clone())? {
-                CtrlResult::Break => return Ok(()),
-                _ => {}
-            }
-        }
+    fn interpret_throw(&mut self, expr: AstExpr<'s>) -> Result<CtrlOrMaybeExprResult, Error> {
+        Ok(CtrlResult::Throw(ctrl_exec!(self.interpret_expr(expr)?)))
     }
 
-    fn interpret_expr(&mut self, expr: AstExpr<'s>) -> Result<CtrlOrExprResult, Error> {
-        match expr {
-            AstExpr::Int(v) => Ok(CtrlResult::Other(ExprResult::Int(v))),
-            AstExpr::Str(s) => Ok(CtrlResult::Other(ExprResult::Str(s.to_string()))),
-            AstExpr::Boolean(b) => Ok(CtrlResult::Other(ExprResult::Bool(b))),
-            AstExpr::FnCall { name, args } => self.interpret_expr_fn_call(name, args),
-            AstExpr::Name(name) => self.variable_get(name),
-            AstExpr::Assignment { varname, expr } => {
-                let result = ctrl_exec!(self.interpret_expr(*expr)?);
-                self.variable_set(varname, result.clone())?;
-                Ok(CtrlResult::Other(result))
-            }
-            AstExpr::BinOp { lhs, op, rhs } => self.interpret_expr_binop(lhs, op, rhs),
-            AstExpr::If {
-                cond,
-                true_block,
-                false_block,
-            } => self.interpret_expr_if(*cond, true_block, false_block),
-            AstExpr::ParenExpr(inner_expr) => self.interpret_expr(*inner_expr),
-        }
+    fn interpret_break(
+        &mut self,
+        label: Option<&str>,
+        value: Option<AstExpr<'s>>,
+    ) -> Result<CtrlOrMaybeExprResult, Error> {
+        let value = match value {
+            Some(expr) => ctrl_exec!(self.interpret_expr(expr)?),
+            None => ExprResult::Null,
+        };
+        Ok(CtrlResult::Break(label.map(String::from), value))
     }
 
-    fn interpret_expr_if(
+    /**
+     * Arrays are iterated over a copy of their items, so the block may modify them.
+     */
+    fn interpret_for(
         &mut self,
-        cond: AstExpr<'s>,
-        true_block: AstBlock<'s>,
-        false_block: Option<AstBlock<'s>>,
+        label: Option<&str>,
+        varname: &'s str,
+        iter: AstExpr<'s>,
+        block: AstBlock<'s>,
     ) -> Result<CtrlOrExprResult, Error> {
-        let cond_result = ctrl_exec!(self.interpret_expr(cond)?);
-
-        let bool_result = match cond_result {
-            ExprResult::Bool(b) => b,
-            ExprResult::Null => false,
-            ExprResult::Int(v) => v != 0,
-            ExprResult::Str(s) => !s.is_empty(),
-        };
+        let mut items = for_items(ctrl_exec!(self.interpret_expr(iter)?))?;
 
-        if bool_result {
-            self.interpret_block(true_block)
-        } else {
-            match false_block {
-                Some(block) => self.interpret_block(block),
-                _ => Ok(CtrlResult::Other(ExprResult::Null)),
+        while let Some(item) = ctrl_exec!(self.next_for_item(&mut items)?) {
+            self.variable_set(varname, item)?;
+            if let Some(result) = self.interpret_loop_iteration(label, block.clone())? {
+                return Ok(result);
             }
         }
+
+        Ok(CtrlResult::Other(ExprResult::Null))
     }
 
-    fn interpret_expr_binop(
+    fn next_for_item(&mut self, items: &mut ForItems) -> Result<CtrlOrMaybeExprResult, Error> {
+        match items {
+            ForItems::Values(values) => Ok(CtrlResult::Other(values.next())),
+            ForItems::Range {
+                next,
+                end,
+                inclusive,
+            } => match *next {
+                Some(n) if n < *end || (*inclusive && n == *end) => {
+                    *next = n.checked_add(1);
+                    Ok(CtrlResult::Other(Some(ExprResult::Int(n))))
+                }
+                _ => Ok(CtrlResult::Other(None)),
+            },
+            ForItems::Generator(generator) => self.resume_generator(generator.id),
+        }
+    }
+
+    fn interpret_loop(
         &mut self,
-        lhs: Box<AstExpr<'s>>,
-        op: Op,
-        rhs: Box<AstExpr<'s>>,
+        label: Option<&str>,
+        block: AstBlock<'s>,
     ) -> Result<CtrlOrExprResult, Error> {
-        let lhs_result = ctrl_exec!(self.interpret_expr(*lhs)?);
-        let rhs_result = ctrl_exec!(self.interpret_expr(*rhs)?);
-
-        let result = match (op, lhs_result, rhs_result) {
-            (Op::Add, ExprResult::Int(a), ExprResult::Int(b)) => ExprResult::Int(a + b),
-            (Op::Sub, ExprResult::Int(a), ExprResult::Int(b)) => ExprResult::Int(a - b),
-            (Op::Mul, ExprResult::Int(a), ExprResult::Int(b)) => ExprResult::Int(a * b),
-            (Op::Div, ExprResult::Int(a), ExprResult::Int(b)) => ExprResult::Int(a / b),
-            (Op::Mod, ExprResult::Int(a), ExprResult::Int(b)) => ExprResult::Int(a % b),
-
-            (Op::Eq, ExprResult::Int(a), ExprResult::Int(b)) => ExprResult::Bool(a == b),
-            (Op::Eq, ExprResult::Str(a), ExprResult::Str(b)) => ExprResult::Bool(a == b),
-            (Op::Eq, ExprResult::Bool(a), ExprResult::Bool(b)) => ExprResult::Bool(a == b),
-            (Op::Eq, ExprResult::Null, ExprResult::Null) => ExprResult::Bool(true),
-            (Op::Eq, _, _) => ExprResult::Bool(false),
-
-            (Op::Lt, ExprResult::Int(a), ExprResult::Int(b)) => ExprResult::Bool(a < b),
-            (Op::Lte, ExprResult::Int(a), ExprResult::Int(b)) => ExprResult::Bool(a <= b),
-            (Op::Gt, ExprResult::Int(a), ExprResult::Int(b)) => ExprResult::Bool(a > b),
-            (Op::Gte, ExprResult::Int(a), ExprResult::Int(b)) => ExprResult::Bool(a >= b),
-
-            (op, lhs, rhs) => {
-                return Err(
-                    format!("Incompatible binop types: {:?} {:?} {:?}", lhs, op, rhs).into(),
-                )
+        loop {
+            if let Some(result) = self.interpret_loop_iteration(label, block.clone())? {
+                return Ok(result);
             }
-        };
-
-        Ok(CtrlResult::Other(result))
+        }
     }
 
-    fn interpret_expr_fn_call(
+    /**
+     * Runs the block of a loop labeled `label` once. `Some` ends the loop: with the value of a
+     * `break` targeting it, or passing on a `break` or `continue` of an outer loop.
+     */
+    fn interpret_loop_iteration(
         &mut self,
-        name: &'s str,
-        call_args: Vec<AstExpr<'s>>,
-    ) -> Result<CtrlOrExprResult, Error> {
-        match name {
-            "print" => return self.interpret_expr_fn_call_print(call_args),
-            _ => {}
-        };
-
-        let (args_names, block) = self
-            .global_frame
-            .functions
-            .get(name)
-            .ok_or::<String>("Missing function".into())?;
-
-        let block = block.clone();
-        let args_names = args_names.clone();
-
-        let mut new_frame = Scope::new();
-
-        // Setup frame.
-        if call_args.len() != args_names.len() {
-            return Err("Argument lenght mismatch".into());
-        }
-        for i in 0..call_args.len() {
-            let var_value = ctrl_exec!(self.interpret_expr(call_args[i].clone())?);
-            new_frame.variables.insert(args_names[i], var_value);
+        label: Option<&str>,
+        block: AstBlock<'s>,
+    ) -> Result<Option<CtrlOrExprResult>, Error> {
+        let targets = |target: &Option<String>| target.is_none() || target.as_deref() == label;
+        match self.interpret_block(block)? {
+            CtrlResult::Break(target, value) if targets(&target) => {
+                Ok(Some(CtrlResult::Other(value)))
+            }
+            CtrlResult::Continue(target) if targets(&target) => Ok(None),
+            CtrlResult::Other(_) => Ok(None),
+            ctrl => Ok(Some(ctrl)),
         }
+    }
 
-        self.frames.push(new_frame);
+    fn new_generator(&mut self, scope: Scope<'s>, block: AstBlock<'s>) -> ExprResult {
+        let generator = Generator::Suspended {
+            scope: Box::new(scope),
+            frames: vec![GeneratorFrame::block(block, None)],
+        };
+        let released = self.released_generators.borrow_mut().pop();
+        let id = match released {
+            Some(id) => {
+                self.generators[id] = generator;
+                id
+            }
+            None => {
+                self.generators.push(generator);
+                self.generators.len() - 1
+            }
+        };
+        ExprResult::Generator(Rc::new(GeneratorRef {
+            id,
+            released: self.released_generators.clone(),
+        }))
+    }
 
-        let block_result = self.interpret_block(block)?;
+    /**
+     * Runs the generator until its next `yield`, `None` once it has finished. The generator
+     * function's scope is put back on the frames while it runs.
+     */
+    fn resume_generator(&mut self, id: usize) -> Result<CtrlOrMaybeExprResult, Error> {
+        let (scope, mut frames) =
+            match std::mem::replace(&mut self.generators[id], Generator::Running) {
+                Generator::Suspended { scope, frames } => (scope, frames),
+                Generator::Running => return Err("Generator is already running".into()),
+                Generator::Done => {
+                    self.generators[id] = Generator::Done;
+                    return Ok(CtrlResult::Other(None));
+                }
+            };
 
-        // Remove frame.
-        self.frames.pop();
+        let frame_count = self.frames.len();
+        self.frames.push(*scope);
+        let result = self.run_generator(&mut frames);
+        self.frames.truncate(frame_count + 1);
+        let scope = self.frames.pop().expect("Missing generator frame");
 
-        match block_result {
-            CtrlResult::Break => Err("Break from a function outside of a loop".into()),
-            _ => Ok(block_result),
-        }
+        self.generators[id] = match result {
+            Ok(CtrlResult::Other(Some(_))) => Generator::Suspended {
+                scope: Box::new(scope),
+                frames,
+            },
+            _ => Generator::Done,
+        };
+        result
     }
 
-    fn interpret_block(&mut self, block: AstBlock<'s>) -> Result<CtrlOrExprResult, Error> {
-        let mut last_result = ExprResult::Null;
-        let lines = block.0;
+    fn run_generator(
+        &mut self,
+        frames: &mut Vec<GeneratorFrame<'s>>,
+    ) -> Result<CtrlOrMaybeExprResult, Error> {
+        loop {
+            let ctrl = match self.step_generator(frames) {
+                Ok(None) => continue,
+                Ok(Some(ctrl)) => ctrl,
+                Err(err) if err.is::<RuntimeError>() => {
+                    match self.catch_in_generator(frames, ExprResult::Str(err.to_string()))? {
+                        Some(_) => return Err(err),
+                        None => continue,
+                    }
+                }
+                Err(err) => return Err(err),
+            };
 
-        for line in lines {
-            last_result = ctrl_exec!(self.interpret_block_line(line)?).unwrap_or(ExprResult::Null);
+            match ctrl {
+                CtrlResult::Other(value) => return Ok(CtrlResult::Other(value)),
+                CtrlResult::Break(label, _) => self.jump_in_generator(frames, label, true)?,
+                CtrlResult::Continue(label) => self.jump_in_generator(frames, label, false)?,
+                CtrlResult::Throw(value) => {
+                    if let Some(value) = self.catch_in_generator(frames, value)? {
+                        return Ok(CtrlResult::Throw(value));
+                    }
+                }
+            }
         }
-
-        Ok(CtrlResult::Other(last_result))
     }
 
-    fn interpret_expr_fn_call_print(
+    /**
+     * Advances the generator by one line or frame. `None` means it can go on, a `Some` holding a
+     * value is a `yield` and `Some(Other(None))` the end of the function.
+     */
+    fn step_generator(
         &mut self,
-        args: Vec<AstExpr<'s>>,
-    ) -> Result<CtrlOrExprResult, Error> {
-        if args.len() != 1 {
-            return Err("Function 'print' expects 1 argument".into());
+        frames: &mut Vec<GeneratorFrame<'s>>,
+    ) -> Result<Option<CtrlOrMaybeExprResult>, Error> {
+        let Some(frame) = frames.last_mut() else {
+            return Ok(Some(CtrlResult::Other(None)));
+        };
+
+        match frame {
+            GeneratorFrame::Block { lines, .. } => match lines.pop_front() {
+                Some(line) => return self.step_generator_line(line, frames),
+                None => self.pop_generator_frame(frames),
+            },
+            GeneratorFrame::Loop { block, .. } => {
+                let block = block.clone();
+                frames.push(GeneratorFrame::block(block, None));
+            }
+            GeneratorFrame::For {
+                varname,
+                items,
+                block,
+                ..
+            } => match self.next_for_item(items)? {
+                CtrlResult::Other(Some(item)) => {
+                    let block = block.clone();
+                    self.variable_set(varname, item)?;
+                    frames.push(GeneratorFrame::block(block, None));
+                }
+                CtrlResult::Other(None) => self.pop_generator_frame(frames),
+                ctrl => return Ok(Some(ctrl)),
+            },
+            // The `try` block ended without errors.
+            GeneratorFrame::Try { .. } => self.pop_generator_frame(frames),
         }
+        Ok(None)
+    }
 
-        match ctrl_exec!(self.interpret_expr(args[0].clone())?) {
-            ExprResult::Null => print!("null"),
-            ExprResult::Int(v) => print!("{}", v),
-            ExprResult::Str(s) => print!("{}", s),
-            ExprResult::Bool(b) => print!("{}", b),
+    /**
+     * Lines that may yield are run as frames, everything else is interpreted as usual.
+     */
+    fn step_generator_line(
+        &mut self,
+        line: AstBlockLine<'s>,
+        frames: &mut Vec<GeneratorFrame<'s>>,
+    ) -> Result<Option<CtrlOrMaybeExprResult>, Error> {
+        let frame = match line {
+            AstBlockLine::Yield(expr) => return Ok(Some(self.interpret_expr(expr)?.map(Some))),
+            AstBlockLine::Expr(AstExpr::If {
+                cond,
+                true_block,
+                false_block,
+            }) => match self.interpret_expr(*cond)? {
+                CtrlResult::Other(cond) if cond.is_truthy() => {
+                    GeneratorFrame::block(true_block, None)
+                }
+                CtrlResult::Other(_) => match false_block {
+                    Some(false_block) => GeneratorFrame::block(false_block, None),
+                    None => return Ok(None),
+                },
+                ctrl => return Ok(Some(ctrl.map(|_| None))),
+            },
+            AstBlockLine::Expr(AstExpr::Loop { label, block }) => {
+                GeneratorFrame::Loop { label, block }
+            }
+            AstBlockLine::Expr(AstExpr::For {
+                label,
+                varname,
+                iter,
+                block,
+            }) => match self.interpret_expr(*iter)? {
+                CtrlResult::Other(iter) => GeneratorFrame::For {
+                    label,
+                    varname,
+                    items: for_items(iter)?,
+                    block,
+                },
+                ctrl => return Ok(Some(ctrl.map(|_| None))),
+            },
+            AstBlockLine::Expr(AstExpr::Match { expr, arms }) => {
+                match self.interpret_expr(*expr)? {
+                    CtrlResult::Other(value) => {
+                        GeneratorFrame::block(self.match_arm_block(&value, arms)?, None)
+                    }
+                    ctrl => return Ok(Some(ctrl.map(|_| None))),
+                }
+            }
+            AstBlockLine::Expr(AstExpr::Block(block)) => {
+                GeneratorFrame::block(block, Some(self.variable_names()?))
+            }
+            AstBlockLine::Expr(AstExpr::Try {
+                block,
+                catch_var,
+                catch_block,
+            }) => {
+                frames.push(GeneratorFrame::Try {
+                    catch_var,
+                    catch_block,
+                    frame_count: self.frames.len(),
+                });
+                GeneratorFrame::block(block, None)
+            }
+            line => {
+                return Ok(match self.interpret_block_line(line)? {
+                    CtrlResult::Other(_) => None,
+                    ctrl => Some(ctrl.map(|_| None)),
+                })
+            }
         };
 
-        Ok(CtrlResult::Other(ExprResult::Null))
+        frames.push(frame);
+        Ok(None)
     }
 
-    fn variable_get(&self, name: &'s str) -> Result<CtrlOrExprResult, Error> {
-        let top_frame = self
-            .frames
-            .last()
-            .ok_or::<String>("No more frames".into())?;
+    /**
+     * Unwinds to the targeted loop, ending it for `break`.
+     */
+    fn jump_in_generator(
+        &mut self,
+        frames: &mut Vec<GeneratorFrame<'s>>,
+        label: Option<String>,
+        is_break: bool,
+    ) -> Result<(), Error> {
+        loop {
+            match frames.last() {
+                Some(
+                    GeneratorFrame::Loop {
+                        label: loop_label, ..
+                    }
+                    | GeneratorFrame::For {
+                        label: loop_label, ..
+                    },
+                ) if label.is_none() || label.as_deref() == *loop_label => {
+                    if is_break {
+                        self.pop_generator_frame(frames);
+                    }
+                    return Ok(());
+                }
+                Some(_) => self.pop_generator_frame(frames),
+                None if is_break => return Err("Break from a function outside of a loop".into()),
+                None => return Err("Continue from a function outside of a loop".into()),
+            }
+        }
+    }
 
-        if top_frame.variables.contains_key(name) {
-            return Ok(CtrlResult::Other(top_frame.variables[name].clone()));
+    /**
+     * Unwinds to the innermost `try` and runs its `catch` block. Gives back the value when there
+     * is no `try` left.
+     */
+    fn catch_in_generator(
+        &mut self,
+        frames: &mut Vec<GeneratorFrame<'s>>,
+        value: ExprResult,
+    ) -> Result<Option<ExprResult>, Error> {
+        loop {
+            match frames.last() {
+                Some(GeneratorFrame::Try { .. }) => break,
+                Some(_) => self.pop_generator_frame(frames),
+                None => return Ok(Some(value)),
+            }
         }
 
-        Err("Variable not found".into())
+        if let Some(GeneratorFrame::Try {
+            catch_var,
+            catch_block,
+            frame_count,
+        }) = frames.pop()
+        {
+            self.frames.truncate(frame_count);
+            self.variable_set(catch_var, value)?;
+            frames.push(GeneratorFrame::block(catch_block, None));
+        }
+        Ok(None)
     }
 
-    fn variable_set(&mut self, name: &'s str, value: ExprResult) -> Result<(), Error> {
-        let top_frame = self
-            .frames
-            .last_mut()
-            .ok_or::<String>("No more frames".into())?;
+    fn pop_generator_frame(&mut self, frames: &mut Vec<GeneratorFrame<'s>>) {
+        if let Some(GeneratorFrame::Block {
+            outer: Some(outer), ..
+        }) = frames.pop()
+        {
+            self.keep_variables(&outer);
+        }
+    }
+
+    fn interpret_expr(&mut self, expr: AstExpr<'s>) -> Result<CtrlOrExprResult, Error> {
+        match expr {
+            AstExpr::Int(v) => Ok(CtrlResult::Other(ExprResult::Int(v))),
+            AstExpr::Float(v) => Ok(CtrlResult::Other(ExprResult::Float(v))),
+            AstExpr::Str(s) => Ok(CtrlResult::Other(ExprResult::Str(s.to_string()))),
+            AstExpr::StrTemplate(parts) => self.interpret_expr_str_template(parts),
+            AstExpr::Boolean(b) => Ok(CtrlResult::Other(ExprResult::Bool(b))),
+            AstExpr::Null => Ok(CtrlResult::Other(ExprResult::Null)),
+            AstExpr::FnCall {
+                module,
+                name,
+                args,
+                named_args,
+            } => self.interpret_expr_fn_call(module, name, args, named_args),
+            AstExpr::Name(name) => self.variable_get(name),
+            AstExpr::Assignment { varname, expr, .. } => {
+                self.interpret_expr_assignment(varname, *expr)
+            }
+            AstExpr::OpAssignment {
+                varname,
+                op,
+                expr,
+                loc,
+            } => self.interpret_expr_op_assignment(varname, op, *expr, loc),
+            AstExpr::BinOp { lhs, op, rhs, loc } => self.interpret_expr_binop(*lhs, op, *rhs, loc),
+            AstExpr::BitNot(expr) => self.interpret_expr_bit_not(*expr),
+            AstExpr::Loop { label, block } => self.interpret_loop(label, block),
+            AstExpr::For {
+                label,
+                varname,
+                iter,
+                block,
+            } => self.interpret_for(label, varname, *iter, block),
+            AstExpr::Range {
+                from,
+                to,
+                inclusive,
+            } => self.interpret_expr_range(*from, *to, inclusive),
+            AstExpr::If {
+                cond,
+                true_block,
+                false_block,
+            } => self.interpret_expr_if(*cond, true_block, false_block),
+            AstExpr::Block(block) => self.interpret_expr_block(block),
+            AstExpr::Try {
+                block,
+                catch_var,
+                catch_block,
+            } => self.interpret_expr_try(block, catch_var, catch_block),
+            AstExpr::ParenExpr(inner_expr) => self.interpret_expr(*inner_expr),
+            AstExpr::Tuple(items) => self.interpret_expr_tuple(items),
+            AstExpr::TupleAssignment { varnames, expr } => {
+                self.interpret_expr_tuple_assignment(varnames, *expr)
+            }
+            AstExpr::Array(items) => self.interpret_expr_array(items),
+            AstExpr::Map(entries) => self.interpret_expr_map(entries),
+            AstExpr::Index { expr, index } => self.interpret_expr_index(*expr, *index),
+            AstExpr::IndexAssignment { expr, index, value } => {
+                self.interpret_expr_index_assignment(*expr, *index, *value)
+            }
+            AstExpr::StructInit { name, fields } => self.interpret_expr_struct_init(name, fields),
+            AstExpr::Field { expr, field } => self.interpret_expr_field(*expr, field),
+            AstExpr::FieldAssignment { expr, field, value } => {
+                self.interpret_expr_field_assignment(*expr, field, *value)
+            }
+            AstExpr::EnumVariant {
+                enum_name,
+                variant,
+                args,
+            } => self.interpret_expr_enum_variant(enum_name, variant, args),
+            AstExpr::Match { expr, arms } => self.interpret_expr_match(*expr, arms),
+        }
+    }
+
+    // Expressions are evaluated in separate functions, as every local of `interpret_expr` adds to
+    // the stack used by each level of recursion.
+
+    fn interpret_expr_assignment(
+        &mut self,
+        varname: &'s str,
+        expr: AstExpr<'s>,
+    ) -> Result<CtrlOrExprResult, Error> {
+        let result = ctrl_exec!(self.interpret_expr(expr)?);
+        self.variable_set(varname, result.clone())?;
+        Ok(CtrlResult::Other(result))
+    }
+
+    fn interpret_expr_op_assignment(
+        &mut self,
+        varname: &'s str,
+        op: Op,
+        expr: AstExpr<'s>,
+        loc: Loc,
+    ) -> Result<CtrlOrExprResult, Error> {
+        let result =
+            ctrl_exec!(self.interpret_expr_binop(AstExpr::Name(varname), op, expr, loc)?);
+        self.variable_set(varname, result.clone())?;
+        Ok(CtrlResult::Other(result))
+    }
+
+    fn interpret_expr_tuple(&mut self, items: Vec<AstExpr<'s>>) -> Result<CtrlOrExprResult, Error> {
+        let values = ctrl_exec!(self.interpret_exprs(items)?);
+        Ok(CtrlResult::Other(ExprResult::Tuple(values)))
+    }
+
+    fn interpret_expr_array(&mut self, items: Vec<AstExpr<'s>>) -> Result<CtrlOrExprResult, Error> {
+        let values = ctrl_exec!(self.interpret_exprs(items)?);
+        Ok(CtrlResult::Other(ExprResult::new_array(values)))
+    }
+
+    /**
+     * Evaluates expressions in order, eg. the items of an array.
+     */
+    fn interpret_exprs(
+        &mut self,
+        exprs: Vec<AstExpr<'s>>,
+    ) -> Result<CtrlResult<Vec<ExprResult>>, Error> {
+        let mut values = vec![];
+        for expr in exprs {
+            values.push(ctrl_exec!(self.interpret_expr(expr)?));
+        }
+        Ok(CtrlResult::Other(values))
+    }
+
+    fn interpret_expr_str_template(
+        &mut self,
+        parts: Vec<AstStrPart<'s>>,
+    ) -> Result<CtrlOrExprResult, Error> {
+        let mut out = String::new();
+        for part in parts {
+            match part {
+                AstStrPart::Literal(literal) => out.push_str(&literal),
+                AstStrPart::Expr(expr) => {
+                    out.push_str(&ctrl_exec!(self.interpret_expr(expr)?).to_string())
+                }
+            }
+        }
+        Ok(CtrlResult::Other(ExprResult::Str(out)))
+    }
+
+    fn interpret_expr_map(
+        &mut self,
+        entries: Vec<(AstExpr<'s>, AstExpr<'s>)>,
+    ) -> Result<CtrlOrExprResult, Error> {
+        let mut values = IndexMap::new();
+        for (key, value) in entries {
+            let key = MapKey::try_from(ctrl_exec!(self.interpret_expr(key)?))?;
+            let value = ctrl_exec!(self.interpret_expr(value)?);
+            values.insert(key, value);
+        }
+        Ok(CtrlResult::Other(ExprResult::new_map(values)))
+    }
+
+    fn interpret_expr_field(
+        &mut self,
+        expr: AstExpr<'s>,
+        field: &'s str,
+    ) -> Result<CtrlOrExprResult, Error> {
+        match ctrl_exec!(self.interpret_expr(expr)?) {
+            ExprResult::Record(record) => {
+                let record = record.borrow();
+                let pos = record.field_pos(field)?;
+                Ok(CtrlResult::Other(record.fields[pos].1.clone()))
+            }
+            other => Err(format!("Cannot read field '{}' of {:?}", field, other).into()),
+        }
+    }
+
+    fn interpret_expr_field_assignment(
+        &mut self,
+        expr: AstExpr<'s>,
+        field: &'s str,
+        value: AstExpr<'s>,
+    ) -> Result<CtrlOrExprResult, Error> {
+        let record = ctrl_exec!(self.interpret_expr(expr)?);
+        let value = ctrl_exec!(self.interpret_expr(value)?);
+
+        match record {
+            ExprResult::Record(record) => {
+                let mut record = record.borrow_mut();
+                let pos = record.field_pos(field)?;
+                record.fields[pos].1 = value.clone();
+                Ok(CtrlResult::Other(value))
+            }
+            other => Err(format!("Cannot set field '{}' of {:?}", field, other).into()),
+        }
+    }
+
+    fn interpret_expr_enum_variant(
+        &mut self,
+        enum_name: &'s str,
+        variant: &'s str,
+        args: Vec<AstExpr<'s>>,
+    ) -> Result<CtrlOrExprResult, Error> {
+        let size = self
+            .global_frame
+            .enums
+            .get(enum_name)
+            .ok_or(format!("Missing enum '{}'", enum_name))?
+            .iter()
+            .find(|(name, _)| name == variant)
+            .map(|(_, size)| *size)
+            .ok_or(format!("Enum '{}' has no variant '{}'", enum_name, variant))?;
+
+        if size != args.len() {
+            return Err(format!(
+                "Variant {}::{} expects {} values, got {}",
+                enum_name,
+                variant,
+                size,
+                args.len()
+            )
+            .into());
+        }
+
+        let mut values = vec![];
+        for arg in args {
+            values.push(ctrl_exec!(self.interpret_expr(arg)?));
+        }
+
+        Ok(CtrlResult::Other(ExprResult::Enum(Rc::new(EnumValue {
+            enum_name: enum_name.to_string(),
+            variant: variant.to_string(),
+            values,
+        }))))
+    }
+
+    fn interpret_expr_match(
+        &mut self,
+        expr: AstExpr<'s>,
+        arms: Vec<AstMatchArm<'s>>,
+    ) -> Result<CtrlOrExprResult, Error> {
+        let value = ctrl_exec!(self.interpret_expr(expr)?);
+        let block = self.match_arm_block(&value, arms)?;
+        self.interpret_block(block)
+    }
+
+    /**
+     * Binds the variables of the first arm matching `value` and gives back its block.
+     */
+    fn match_arm_block(
+        &mut self,
+        value: &ExprResult,
+        arms: Vec<AstMatchArm<'s>>,
+    ) -> Result<AstBlock<'s>, Error> {
+        for arm in arms {
+            let bindings = match (arm.pattern, value) {
+                (AstPattern::Wildcard, _) => vec![],
+                (AstPattern::Binding(name), value) => vec![(name, value.clone())],
+                (AstPattern::Int(a), ExprResult::Int(b)) if a == *b => vec![],
+                (AstPattern::Str(a), ExprResult::Str(b)) if a == b => vec![],
+                (AstPattern::Boolean(a), ExprResult::Bool(b)) if a == *b => vec![],
+                (
+                    AstPattern::Variant {
+                        enum_name,
+                        variant,
+                        bindings,
+                    },
+                    ExprResult::Enum(value),
+                ) if value.enum_name == enum_name && value.variant == variant => bindings
+                    .into_iter()
+                    .zip(value.values.iter().cloned())
+                    .filter(|(name, _)| *name != "_")
+                    .collect(),
+                _ => continue,
+            };
+
+            for (name, value) in bindings {
+                self.variable_set(name, value)?;
+            }
+
+            return Ok(arm.block);
+        }
+
+        Err(runtime_error(format!("No match arm for {:?}", value)))
+    }
+
+    fn interpret_expr_struct_init(
+        &mut self,
+        name: &'s str,
+        fields: Vec<(&'s str, AstExpr<'s>)>,
+    ) -> Result<CtrlOrExprResult, Error> {
+        let declared_fields = self
+            .global_frame
+            .structs
+            .get(name)
+            .ok_or(format!("Missing struct '{}'", name))?
+            .clone();
+
+        if fields.len() != declared_fields.len() {
+            return Err(format!(
+                "Struct '{}' expects {} fields, got {}",
+                name,
+                declared_fields.len(),
+                fields.len()
+            )
+            .into());
+        }
+
+        let mut values = HashMap::new();
+        for (field, expr) in fields {
+            if !declared_fields.contains(&field) {
+                return Err(format!("Struct '{}' has no field '{}'", name, field).into());
+            }
+            let value = ctrl_exec!(self.interpret_expr(expr)?);
+            values.insert(field, value);
+        }
+
+        let mut record = Record {
+            name: name.to_string(),
+            fields: vec![],
+        };
+        for field in declared_fields {
+            let value = values
+                .remove(field)
+                .ok_or(format!("Missing field '{}' for struct '{}'", field, name))?;
+            record.fields.push((field.to_string(), value));
+        }
+
+        Ok(CtrlResult::Other(ExprResult::Record(Rc::new(
+            RefCell::new(record),
+        ))))
+    }
+
+    fn interpret_expr_index(
+        &mut self,
+        expr: AstExpr<'s>,
+        index: AstExpr<'s>,
+    ) -> Result<CtrlOrExprResult, Error> {
+        let container = ctrl_exec!(self.interpret_expr(expr)?);
+        let index = ctrl_exec!(self.interpret_expr(index)?);
+
+        match (container, index) {
+            (ExprResult::Array(items), ExprResult::Int(i)) => {
+                let items = items.borrow();
+                let pos = array_pos(i, items.len())?;
+                Ok(CtrlResult::Other(items[pos].clone()))
+            }
+            (ExprResult::Map(entries), key) => {
+                let key = MapKey::try_from(key)?;
+                entries
+                    .borrow()
+                    .get(&key)
+                    .cloned()
+                    .map(CtrlResult::Other)
+                    .ok_or(runtime_error(format!("Missing map key: {}", key)))
+            }
+            (container, index) => {
+                Err(format!("Cannot index {:?} with {:?}", container, index).into())
+            }
+        }
+    }
+
+    fn interpret_expr_index_assignment(
+        &mut self,
+        expr: AstExpr<'s>,
+        index: AstExpr<'s>,
+        value: AstExpr<'s>,
+    ) -> Result<CtrlOrExprResult, Error> {
+        let container = ctrl_exec!(self.interpret_expr(expr)?);
+        let index = ctrl_exec!(self.interpret_expr(index)?);
+        let value = ctrl_exec!(self.interpret_expr(value)?);
+
+        match (container, index) {
+            (ExprResult::Array(items), ExprResult::Int(i)) => {
+                let mut items = items.borrow_mut();
+                let pos = array_pos(i, items.len())?;
+                items[pos] = value.clone();
+                Ok(CtrlResult::Other(value))
+            }
+            (ExprResult::Map(entries), key) => {
+                let key = MapKey::try_from(key)?;
+                entries.borrow_mut().insert(key, value.clone());
+                Ok(CtrlResult::Other(value))
+            }
+            (container, index) => {
+                Err(format!("Cannot index {:?} with {:?}", container, index).into())
+            }
+        }
+    }
+
+    fn interpret_expr_tuple_assignment(
+        &mut self,
+        varnames: Vec<&'s str>,
+        expr: AstExpr<'s>,
+    ) -> Result<CtrlOrExprResult, Error> {
+        let result = ctrl_exec!(self.interpret_expr(expr)?);
+        let values = match &result {
+            ExprResult::Tuple(values) if values.len() == varnames.len() => values,
+            other => {
+                return Err(format!(
+                    "Cannot destructure {} into {} variables",
+                    other,
+                    varnames.len()
+                )
+                .into())
+            }
+        };
+
+        for (varname, value) in varnames.into_iter().zip(values) {
+            if varname != "_" {
+                self.variable_set(varname, value.clone())?;
+            }
+        }
+
+        Ok(CtrlResult::Other(result))
+    }
+
+    /**
+     * Variables first assigned in the block are dropped at its end, assignments to outer
+     * variables are kept.
+     */
+    fn interpret_expr_block(&mut self, block: AstBlock<'s>) -> Result<CtrlOrExprResult, Error> {
+        let outer = self.variable_names()?;
+        let result = self.interpret_block(block);
+        self.keep_variables(&outer);
+        result
+    }
+
+    /**
+     * Catches both thrown values and runtime errors, the latter as their message. Frames of the
+     * functions the error unwound from are dropped.
+     */
+    fn interpret_expr_try(
+        &mut self,
+        block: AstBlock<'s>,
+        catch_var: &'s str,
+        catch_block: AstBlock<'s>,
+    ) -> Result<CtrlOrExprResult, Error> {
+        let frame_count = self.frames.len();
+        let caught = match self.interpret_block(block) {
+            Ok(CtrlResult::Throw(value)) => value,
+            Ok(result) => return Ok(result),
+            Err(err) if err.is::<RuntimeError>() => {
+                self.frames.truncate(frame_count);
+                ExprResult::Str(err.to_string())
+            }
+            Err(err) => return Err(err),
+        };
+
+        self.variable_set(catch_var, caught)?;
+        self.interpret_block(catch_block)
+    }
+
+    fn interpret_expr_if(
+        &mut self,
+        cond: AstExpr<'s>,
+        true_block: AstBlock<'s>,
+        false_block: Option<AstBlock<'s>>,
+    ) -> Result<CtrlOrExprResult, Error> {
+        let cond_result = ctrl_exec!(self.interpret_expr(cond)?);
+
+        if cond_result.is_truthy() {
+            self.interpret_block(true_block)
+        } else {
+            match false_block {
+                Some(block) => self.interpret_block(block),
+                _ => Ok(CtrlResult::Other(ExprResult::Null)),
+            }
+        }
+    }
+
+    fn interpret_expr_binop(
+        &mut self,
+        lhs: AstExpr<'s>,
+        op: Op,
+        rhs: AstExpr<'s>,
+        loc: Loc,
+    ) -> Result<CtrlOrExprResult, Error> {
+        let lhs_result = ctrl_exec!(self.interpret_expr(lhs)?);
+        // `??` only evaluates its right side when the left side is null.
+        if op == Op::Coalesce {
+            return match lhs_result {
+                ExprResult::Null => self.interpret_expr(rhs),
+                other => Ok(CtrlResult::Other(other)),
+            };
+        }
+        let rhs_result = ctrl_exec!(self.interpret_expr(rhs)?);
+
+        Ok(CtrlResult::Other(binop_value(
+            op, lhs_result, rhs_result, loc,
+        )?))
+    }
+
+    fn interpret_expr_bit_not(&mut self, expr: AstExpr<'s>) -> Result<CtrlOrExprResult, Error> {
+        match ctrl_exec!(self.interpret_expr(expr)?) {
+            ExprResult::Int(v) => Ok(CtrlResult::Other(ExprResult::Int(!v))),
+            ExprResult::BigInt(v) => Err(bitwise_range_error(&v)),
+            other => Err(format!("Incompatible type for ~: {:?}", other).into()),
+        }
+    }
+
+    fn interpret_expr_range(
+        &mut self,
+        from: AstExpr<'s>,
+        to: AstExpr<'s>,
+        inclusive: bool,
+    ) -> Result<CtrlOrExprResult, Error> {
+        let from = ctrl_exec!(self.interpret_expr(from)?);
+        let to = ctrl_exec!(self.interpret_expr(to)?);
+        match (from, to) {
+            (ExprResult::Int(from), ExprResult::Int(to)) => {
+                Ok(CtrlResult::Other(ExprResult::Range {
+                    from,
+                    to,
+                    inclusive,
+                }))
+            }
+            (from, to) => {
+                Err(format!("Range bounds must be integers, got {:?} and {:?}", from, to).into())
+            }
+        }
+    }
+
+    fn interpret_expr_fn_call(
+        &mut self,
+        module: Option<&'s str>,
+        name: &'s str,
+        call_args: Vec<AstExpr<'s>>,
+        named_args: AstNamedArgs<'s>,
+    ) -> Result<CtrlOrExprResult, Error> {
+        if module.is_none() && is_builtin(name) {
+            if !named_args.is_empty() {
+                return Err(format!("Function '{}' does not take named arguments", name).into());
+            }
+            return self.interpret_expr_fn_call_builtin(name, call_args);
+        }
+
+        let key = self.resolve_fn(module, name)?;
+        let function = &self.global_frame.functions[&key];
+
+        // Arguments are evaluated in the order of the parameters.
+        let call_args = resolve_call_args(name, &function.params, call_args, named_args)?;
+        let block = function.block.clone();
+        let args_names = function.args.clone();
+        let generator = function.generator;
+
+        let new_frame = ctrl_exec!(self.interpret_fn_call_frame(key.0, args_names, call_args)?);
+
+        if generator {
+            return Ok(CtrlResult::Other(self.new_generator(new_frame, block)));
+        }
+
+        self.frames.push(new_frame);
+
+        let block_result = self.interpret_block(block)?;
+
+        // Remove frame.
+        self.frames.pop();
+
+        match block_result {
+            CtrlResult::Break(..) => Err("Break from a function outside of a loop".into()),
+            CtrlResult::Continue(_) => Err("Continue from a function outside of a loop".into()),
+            _ => Ok(block_result),
+        }
+    }
+
+    fn interpret_fn_call_frame(
+        &mut self,
+        module: Option<&'s str>,
+        args_names: Vec<&'s str>,
+        call_args: Vec<CallArg<AstExpr<'s>>>,
+    ) -> Result<CtrlResult<Scope<'s>>, Error> {
+        let mut new_frame = Scope::new();
+        new_frame.module = module;
+
+        for (arg_name, call_arg) in args_names.into_iter().zip(call_args) {
+            let var_value = match call_arg {
+                CallArg::Given(expr) => ctrl_exec!(self.interpret_expr(expr)?),
+                CallArg::Default(value) => ExprResult::from(&value),
+                CallArg::Rest(exprs) => {
+                    ExprResult::new_array(ctrl_exec!(self.interpret_exprs(exprs)?))
+                }
+            };
+            new_frame.variables.insert(arg_name, var_value);
+        }
+
+        Ok(CtrlResult::Other(new_frame))
+    }
+
+    fn interpret_expr_fn_call_builtin(
+        &mut self,
+        name: &'s str,
+        call_args: Vec<AstExpr<'s>>,
+    ) -> Result<CtrlOrExprResult, Error> {
+        match name {
+            "print" | "println" => self.interpret_expr_fn_call_print(name, call_args),
+            "len" => self.interpret_expr_fn_call_len(call_args),
+            "push" => self.interpret_expr_fn_call_push(call_args),
+            "pop" => self.interpret_expr_fn_call_pop(call_args),
+            "keys" => self.interpret_expr_fn_call_keys(call_args),
+            "next" => self.interpret_expr_fn_call_next(call_args),
+            "has" => self.interpret_expr_fn_call_has(call_args),
+            _ => self.interpret_expr_fn_call_string(name, call_args),
+        }
+    }
+
+    /**
+     * Unqualified calls inside a module prefer the module's own functions.
+     */
+    fn resolve_fn(&self, module: Option<&'s str>, name: &'s str) -> Result<FnKey<'s>, Error> {
+        let current_module = self.frames.last().and_then(|frame| frame.module);
+
+        let candidates = match module {
+            Some(_) => vec![(module, name)],
+            None => vec![(current_module, name), (None, name)],
+        };
+
+        candidates
+            .into_iter()
+            .find(|key| self.global_frame.functions.contains_key(key))
+            .ok_or(match module {
+                Some(module) => format!("Missing function '{}::{}'", module, name).into(),
+                None => "Missing function".into(),
+            })
+    }
+
+    fn interpret_block(&mut self, block: AstBlock<'s>) -> Result<CtrlOrExprResult, Error> {
+        let mut last_result = ExprResult::Null;
+        let lines = block.0;
+
+        for line in lines {
+            last_result = ctrl_exec!(self.interpret_block_line(line)?).unwrap_or(ExprResult::Null);
+        }
+
+        Ok(CtrlResult::Other(last_result))
+    }
+
+    /**
+     * `print` and `println` take any number of values and separate them with spaces.
+     */
+    fn interpret_expr_fn_call_print(
+        &mut self,
+        name: &str,
+        args: Vec<AstExpr<'s>>,
+    ) -> Result<CtrlOrExprResult, Error> {
+        let values = ctrl_exec!(self.interpret_exprs(args)?);
+        let line = values
+            .iter()
+            .map(|value| value.to_string())
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        if name == "println" {
+            println!("{}", line);
+        } else {
+            print!("{}", line);
+        }
+
+        Ok(CtrlResult::Other(ExprResult::Null))
+    }
+
+    fn interpret_expr_fn_call_len(
+        &mut self,
+        args: Vec<AstExpr<'s>>,
+    ) -> Result<CtrlOrExprResult, Error> {
+        if args.len() != 1 {
+            return Err("Function 'len' expects 1 argument".into());
+        }
+
+        match ctrl_exec!(self.interpret_expr(args[0].clone())?) {
+            ExprResult::Array(items) => Ok(CtrlResult::Other(ExprResult::Int(
+                items.borrow().len() as i64,
+            ))),
+            ExprResult::Map(entries) => Ok(CtrlResult::Other(ExprResult::Int(
+                entries.borrow().len() as i64,
+            ))),
+            ExprResult::Str(s) => Ok(CtrlResult::Other(ExprResult::Int(s.chars().count() as i64))),
+            other => Err(format!(
+                "Function 'len' expects an array, a map or a string, got {:?}",
+                other
+            )
+            .into()),
+        }
+    }
+
+    fn interpret_expr_fn_call_push(
+        &mut self,
+        args: Vec<AstExpr<'s>>,
+    ) -> Result<CtrlOrExprResult, Error> {
+        if args.len() != 2 {
+            return Err("Function 'push' expects 2 arguments".into());
+        }
+
+        let array = ctrl_exec!(self.interpret_expr(args[0].clone())?);
+        let value = ctrl_exec!(self.interpret_expr(args[1].clone())?);
+
+        match array {
+            ExprResult::Array(items) => {
+                let mut items = items.borrow_mut();
+                items.push(value);
+                Ok(CtrlResult::Other(ExprResult::Int(items.len() as i64)))
+            }
+            other => Err(format!("Function 'push' expects an array, got {:?}", other).into()),
+        }
+    }
+
+    fn interpret_expr_fn_call_pop(
+        &mut self,
+        args: Vec<AstExpr<'s>>,
+    ) -> Result<CtrlOrExprResult, Error> {
+        if args.len() != 1 {
+            return Err("Function 'pop' expects 1 argument".into());
+        }
+
+        match ctrl_exec!(self.interpret_expr(args[0].clone())?) {
+            ExprResult::Array(items) => items
+                .borrow_mut()
+                .pop()
+                .map(CtrlResult::Other)
+                .ok_or(runtime_error("Pop from empty array")),
+            other => Err(format!("Function 'pop' expects an array, got {:?}", other).into()),
+        }
+    }
+
+    /**
+     * The next value of a generator, null once it has finished.
+     */
+    fn interpret_expr_fn_call_next(
+        &mut self,
+        args: Vec<AstExpr<'s>>,
+    ) -> Result<CtrlOrExprResult, Error> {
+        if args.len() != 1 {
+            return Err("Function 'next' expects 1 argument".into());
+        }
+
+        match ctrl_exec!(self.interpret_expr(args[0].clone())?) {
+            ExprResult::Generator(generator) => Ok(self
+                .resume_generator(generator.id)?
+                .map(|value| value.unwrap_or(ExprResult::Null))),
+            other => Err(format!("Function 'next' expects a generator, got {:?}", other).into()),
+        }
+    }
+
+    fn interpret_expr_fn_call_keys(
+        &mut self,
+        args: Vec<AstExpr<'s>>,
+    ) -> Result<CtrlOrExprResult, Error> {
+        if args.len() != 1 {
+            return Err("Function 'keys' expects 1 argument".into());
+        }
+
+        match ctrl_exec!(self.interpret_expr(args[0].clone())?) {
+            ExprResult::Map(entries) => Ok(CtrlResult::Other(ExprResult::new_array(
+                entries
+                    .borrow()
+                    .keys()
+                    .cloned()
+                    .map(ExprResult::from)
+                    .collect(),
+            ))),
+            other => Err(format!("Function 'keys' expects a map, got {:?}", other).into()),
+        }
+    }
+
+    fn interpret_expr_fn_call_has(
+        &mut self,
+        args: Vec<AstExpr<'s>>,
+    ) -> Result<CtrlOrExprResult, Error> {
+        if args.len() != 2 {
+            return Err("Function 'has' expects 2 arguments".into());
+        }
+
+        let map = ctrl_exec!(self.interpret_expr(args[0].clone())?);
+        let key = MapKey::try_from(ctrl_exec!(self.interpret_expr(args[1].clone())?))?;
+
+        match map {
+            ExprResult::Map(entries) => Ok(CtrlResult::Other(ExprResult::Bool(
+                entries.borrow().contains_key(&key),
+            ))),
+            other => Err(format!("Function 'has' expects a map, got {:?}", other).into()),
+        }
+    }
+
+    fn interpret_expr_fn_call_string(
+        &mut self,
+        name: &str,
+        args: Vec<AstExpr<'s>>,
+    ) -> Result<CtrlOrExprResult, Error> {
+        let mut values = vec![];
+        for arg in args {
+            values.push(ctrl_exec!(self.interpret_expr(arg)?));
+        }
+
+        let result = match name {
+            "sqrt" | "floor" | "round" => call_math_builtin(name, values)?,
+            "is_null" => match values.as_slice() {
+                [value] => ExprResult::Bool(matches!(value, ExprResult::Null)),
+                _ => return Err("Function 'is_null' expects 1 argument".into()),
+            },
+            _ => call_string_builtin(name, values)?,
+        };
+
+        Ok(CtrlResult::Other(result))
+    }
+
+    fn variable_get(&self, name: &'s str) -> Result<CtrlOrExprResult, Error> {
+        let top_frame = self
+            .frames
+            .last()
+            .ok_or::<String>("No more frames".into())?;
+
+        if top_frame.variables.contains_key(name) {
+            return Ok(CtrlResult::Other(top_frame.variables[name].clone()));
+        }
+
+        if let Some(value) = self.constants.get(name) {
+            return Ok(CtrlResult::Other(value.into()));
+        }
+
+        Err("Variable not found".into())
+    }
+
+    fn variable_names(&self) -> Result<HashSet<&'s str>, Error> {
+        let top_frame = self
+            .frames
+            .last()
+            .ok_or::<String>("No more frames".into())?;
+
+        Ok(top_frame.variables.keys().copied().collect())
+    }
+
+    /**
+     * Drops the variables of the top frame that are not in `names`, at the end of a block scope.
+     */
+    fn keep_variables(&mut self, names: &HashSet<&'s str>) {
+        if let Some(top_frame) = self.frames.last_mut() {
+            top_frame.variables.retain(|name, _| names.contains(name));
+        }
+    }
+
+    fn variable_set(&mut self, name: &'s str, value: ExprResult) -> Result<(), Error> {
+        if self.constants.contains_key(name) {
+            return Err(format!("Cannot assign to constant '{}'", name).into());
+        }
+
+        let top_frame = self
+            .frames
+            .last_mut()
+            .ok_or::<String>("No more frames".into())?;
 
         top_frame.variables.insert(name, value);
         Ok(())
     }
-}
+}
+
+fn for_items(value: ExprResult) -> Result<ForItems, Error> {
+    let values: Vec<ExprResult> = match value {
+        ExprResult::Range {
+            from,
+            to,
+            inclusive,
+        } => {
+            return Ok(ForItems::Range {
+                next: Some(from),
+                end: to,
+                inclusive,
+            })
+        }
+        ExprResult::Str(s) => s.chars().map(|c| ExprResult::Str(c.to_string())).collect(),
+        ExprResult::Array(items) => items.borrow().clone(),
+        ExprResult::Generator(id) => return Ok(ForItems::Generator(id)),
+        other => return Err(format!("Cannot iterate over {:?}", other).into()),
+    };
+    Ok(ForItems::Values(values.into_iter()))
+}
+
+fn is_builtin(name: &str) -> bool {
+    matches!(
+        name,
+        "print"
+            | "println"
+            | "len"
+            | "push"
+            | "pop"
+            | "keys"
+            | "next"
+            | "has"
+            | "substr"
+            | "index_of"
+            | "upper"
+            | "lower"
+            | "split"
+            | "trim"
+            | "to_int"
+            | "str"
+            | "sqrt"
+            | "floor"
+            | "round"
+            | "is_null"
+    )
+}
+
+/**
+ * String builtins. Positions and lengths count characters, not bytes.
+ */
+fn call_string_builtin(name: &str, args: Vec<ExprResult>) -> Result<ExprResult, Error> {
+    let arity = match name {
+        "substr" => 3,
+        "index_of" | "split" => 2,
+        _ => 1,
+    };
+    if args.len() != arity {
+        let plural = if arity == 1 { "" } else { "s" };
+        return Err(format!("Function '{}' expects {} argument{}", name, arity, plural).into());
+    }
+
+    if name == "str" {
+        return Ok(ExprResult::Str(args[0].to_string()));
+    }
+
+    let s = match &args[0] {
+        ExprResult::Str(s) => s,
+        other => {
+            return Err(format!("Function '{}' expects a string, got {:?}", name, other).into())
+        }
+    };
+
+    Ok(match (name, &args[1..]) {
+        ("upper", []) => ExprResult::Str(s.to_uppercase()),
+        ("lower", []) => ExprResult::Str(s.to_lowercase()),
+        ("trim", []) => ExprResult::Str(s.trim().to_string()),
+        ("to_int", []) => IntResult::from(
+            s.parse::<BigInt>()
+                .map_err(|_| runtime_error(format!("Cannot convert '{}' to int", s)))?,
+        )
+        .into(),
+        ("index_of", [ExprResult::Str(needle)]) => ExprResult::Int(
+            s.find(needle.as_str())
+                .map(|pos| s[..pos].chars().count() as i64)
+                .unwrap_or(-1),
+        ),
+        ("split", [ExprResult::Str(sep)]) => {
+            // An empty separator splits into characters.
+            let parts: Vec<String> = if sep.is_empty() {
+                s.chars().map(|c| c.to_string()).collect()
+            } else {
+                s.split(sep.as_str()).map(|part| part.to_string()).collect()
+            };
+            ExprResult::new_array(parts.into_iter().map(ExprResult::Str).collect())
+        }
+        ("substr", [ExprResult::Int(start), ExprResult::Int(length)]) => {
+            let char_count = s.chars().count() as i64;
+            if *start < 0 || *length < 0 || *length > char_count - *start {
+                return Err(runtime_error(format!(
+                    "Substring out of bounds: {}+{} (length {})",
+                    start, length, char_count
+                )));
+            }
+            ExprResult::Str(
+                s.chars()
+                    .skip(*start as usize)
+                    .take(*length as usize)
+                    .collect(),
+            )
+        }
+        (name, rest) => return Err(format!("Invalid arguments for '{}': {:?}", name, rest).into()),
+    })
+}
+
+/**
+ * Arithmetic errors are runtime errors of the program, reported at the operator.
+ */
+fn binop_value(op: Op, lhs: ExprResult, rhs: ExprResult, loc: Loc) -> Result<ExprResult, Error> {
+    if let Some(result) = number_binop(&op, &lhs, &rhs).map_err(|err| loc.wrap(err))? {
+        return Ok(result);
+    }
+
+    Ok(match (op, lhs, rhs) {
+        (Op::Add, ExprResult::Str(a), ExprResult::Str(b)) => ExprResult::Str(a + &b),
+
+        (Op::BitAnd, ExprResult::Int(a), ExprResult::Int(b)) => ExprResult::Int(a & b),
+        (Op::BitOr, ExprResult::Int(a), ExprResult::Int(b)) => ExprResult::Int(a | b),
+        (Op::BitXor, ExprResult::Int(a), ExprResult::Int(b)) => ExprResult::Int(a ^ b),
+        (Op::Shl, ExprResult::Int(a), ExprResult::Int(b)) => {
+            ExprResult::Int(int_shl(a, b).map_err(|err| loc.wrap(err))?)
+        }
+        (Op::Shr, ExprResult::Int(a), ExprResult::Int(b)) => {
+            ExprResult::Int(int_shr(a, b).map_err(|err| loc.wrap(err))?)
+        }
+
+        (Op::Eq, ExprResult::Int(a), ExprResult::Int(b)) => ExprResult::Bool(a == b),
+        (Op::Eq, ExprResult::Str(a), ExprResult::Str(b)) => ExprResult::Bool(a == b),
+        (Op::Eq, ExprResult::Bool(a), ExprResult::Bool(b)) => ExprResult::Bool(a == b),
+        (Op::Eq, ExprResult::Null, ExprResult::Null) => ExprResult::Bool(true),
+        (Op::Eq, ExprResult::Enum(a), ExprResult::Enum(b)) => ExprResult::Bool(a == b),
+        (Op::Eq, ExprResult::Tuple(a), ExprResult::Tuple(b)) => ExprResult::Bool(a == b),
+        (Op::Eq, a @ ExprResult::Range { .. }, b @ ExprResult::Range { .. }) => {
+            ExprResult::Bool(a == b)
+        }
+        (Op::Eq, _, _) => ExprResult::Bool(false),
+
+        (Op::Lt, ExprResult::Int(a), ExprResult::Int(b)) => ExprResult::Bool(a < b),
+        (Op::Lte, ExprResult::Int(a), ExprResult::Int(b)) => ExprResult::Bool(a <= b),
+        (Op::Gt, ExprResult::Int(a), ExprResult::Int(b)) => ExprResult::Bool(a > b),
+        (Op::Gte, ExprResult::Int(a), ExprResult::Int(b)) => ExprResult::Bool(a >= b),
+
+        (Op::Lt, ExprResult::Str(a), ExprResult::Str(b)) => ExprResult::Bool(a < b),
+        (Op::Lte, ExprResult::Str(a), ExprResult::Str(b)) => ExprResult::Bool(a <= b),
+        (Op::Gt, ExprResult::Str(a), ExprResult::Str(b)) => ExprResult::Bool(a > b),
+        (Op::Gte, ExprResult::Str(a), ExprResult::Str(b)) => ExprResult::Bool(a >= b),
+
+        (op, lhs, rhs) => {
+            return Err(format!("Incompatible binop types: {:?} {:?} {:?}", lhs, op, rhs).into())
+        }
+    })
+}
+
+/**
+ * Arithmetic on two ints, and binops with a float or a bigint operand. Ints are promoted to
+ * bigints on overflow, and to floats when mixed with them. `None` when the operands are not
+ * numbers, or for the int comparisons and bitwise operators.
+ */
+fn number_binop(op: &Op, lhs: &ExprResult, rhs: &ExprResult) -> Result<Option<ExprResult>, Error> {
+    match (lhs, rhs) {
+        (ExprResult::Int(a), ExprResult::Int(b)) => {
+            Ok(int_arith(op, *a, *b)?.map(ExprResult::from))
+        }
+        (ExprResult::Float(_), _) | (_, ExprResult::Float(_)) => Ok(float_binop(op, lhs, rhs)),
+        (ExprResult::BigInt(_), _) | (_, ExprResult::BigInt(_)) => big_int_binop(op, lhs, rhs),
+        _ => Ok(None),
+    }
+}
+
+fn float_binop(op: &Op, lhs: &ExprResult, rhs: &ExprResult) -> Option<ExprResult> {
+    let (a, b) = (lhs.as_float()?, rhs.as_float()?);
+
+    float_arith(op, a, b)
+        .map(ExprResult::Float)
+        .or_else(|| compare(op, a, b).map(ExprResult::Bool))
+}
+
+fn big_int_binop(op: &Op, lhs: &ExprResult, rhs: &ExprResult) -> Result<Option<ExprResult>, Error> {
+    let (Some(a), Some(b)) = (lhs.as_big_int(), rhs.as_big_int()) else {
+        return Ok(None);
+    };
+
+    if op.is_bitwise() {
+        let big = if a.to_i64().is_none() { &a } else { &b };
+        return Err(bitwise_range_error(big));
+    }
+    if let Some(result) = big_arith(op, &a, &b)? {
+        return Ok(Some(result.into()));
+    }
+    Ok(compare(op, a, b).map(ExprResult::Bool))
+}
+
+/**
+ * Math builtins, ints are accepted wherever a float is expected.
+ */
+fn call_math_builtin(name: &str, args: Vec<ExprResult>) -> Result<ExprResult, Error> {
+    let value = match args.as_slice() {
+        [arg] => arg.as_float(),
+        _ => None,
+    }
+    .ok_or(format!("Invalid arguments for '{}': {:?}", name, args))?;
+
+    Ok(match name {
+        "sqrt" => ExprResult::Float(value.sqrt()),
+        "floor" => float_to_int(value.floor())?.into(),
+        _ => float_to_int(value.round())?.into(),
+    })
+}
+
+fn array_pos(index: i64, len: usize) -> Result<usize, Error> {
+    if index < 0 || index as usize >= len {
+        return Err(runtime_error(format!(
+            "Index out of bounds: {} (length {})",
+            index, len
+        )));
+    }
+
+    Ok(index as usize)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::interpreter::*;
+    use crate::lexer::*;
+    use crate::parser::*;
+    use crate::source_reader::*;
+
+    #[test]
+    fn test_empty_program() {
+        assert_eq!(None, interpret_this(""));
+    }
+
+    #[test]
+    fn test_expression_value() {
+        assert_eq!(Some(ExprResult::Int(7)), interpret_this("1; 3; 7;"));
+    }
+
+    #[test]
+    fn test_empty_fn_call() {
+        assert_eq!(
+            Some(ExprResult::Null),
+            interpret_this(
+                r#"
+                fn main() {
+                }
+                main();
+        "#
+            )
+        );
+    }
+
+    #[test]
+    fn test_fn_call() {
+        assert_eq!(
+            Some(ExprResult::Str("ok".into())),
+            interpret_this(
+                r#"
+                fn main() {
+                    "ok";
+                }
+                main();
+        "#
+            )
+        );
+    }
+
+    #[test]
+    fn test_multiple_fn_call() {
+        assert_eq!(
+            Some(ExprResult::Int(9)),
+            interpret_this(
+                r#"
+                fn main(v) {
+                    proxy(v);
+                }
+                fn proxy(x) {
+                    x;
+                }
+                main(9);
+        "#
+            )
+        );
+    }
+
+    #[test]
+    fn test_fn_call_with_fn_call_arg() {
+        assert_eq!(
+            Some(ExprResult::Int(6)),
+            interpret_this(
+                r#"
+                fn main(v) {
+                    v;
+                }
+                fn fixed() {
+                    6;
+                }
+                main(fixed());
+        "#
+            )
+        );
+    }
+
+    #[test]
+    fn test_binop_simple() {
+        assert_eq!(
+            Some(ExprResult::Int(17)),
+            interpret_this(
+                r#"
+                a = 12;
+                b = 5;
+                a + b;
+        "#
+            )
+        );
+
+        assert_eq!(Some(ExprResult::Int(3)), interpret_this("13 % 10;"));
+    }
+
+    #[test]
+    fn test_binop_complex() {
+        assert_eq!(
+            Some(ExprResult::Int(62)),
+            interpret_this(
+                r#"
+                fn id(v) { v; }
+                a = 12;
+                b = 5;
+                a + b * 100 / id(10);
+        "#
+            )
+        );
+    }
+
+    #[test]
+    fn test_if() {
+        assert_eq!(
+            Some(ExprResult::Int(2)),
+            interpret_this(
+                r#"
+                if (1) {
+                    2;
+                } else {
+                    3;
+                }
+        "#
+            )
+        );
+
+        assert_eq!(
+            Some(ExprResult::Int(3)),
+            interpret_this(
+                r#"
+                if (0) {
+                    2;
+                } else {
+                    3;
+                }
+        "#
+            )
+        );
+
+        assert_eq!(
+            Some(ExprResult::Int(2)),
+            interpret_this(
+                r#"
+                if ("ok") {
+                    2;
+                } else {
+                    3;
+                }
+        "#
+            )
+        );
+
+        assert_eq!(
+            Some(ExprResult::Int(3)),
+            interpret_this(
+                r#"
+                if ("") {
+                    2;
+                } else {
+                    3;
+                }
+        "#
+            )
+        );
+
+        assert_eq!(
+            Some(ExprResult::Int(3)),
+            interpret_this(
+                r#"
+                fn empty() {}
+
+                if (empty()) {
+                    2;
+                } else {
+                    3;
+                }
+        "#
+            )
+        );
+
+        assert_eq!(
+            Some(ExprResult::Int(2)),
+            interpret_this(
+                r#"
+                if (true) {
+                    2;
+                } else {
+                    3;
+                }
+        "#
+            )
+        );
+
+        assert_eq!(
+            Some(ExprResult::Int(3)),
+            interpret_this(
+                r#"
+                if (false) {
+                    2;
+                } else {
+                    3;
+                }
+        "#
+            )
+        );
+    }
+
+    #[test]
+    fn test_recursion() {
+        assert_eq!(
+            Some(ExprResult::Int(3628800)),
+            interpret_this(
+                r#"
+                fn factor(n) {
+                    if (n) {
+                        factor(n - 1) * n;
+                    } else {
+                        1;
+                    }
+                }
+
+                factor(10);
+        "#
+            )
+        );
+    }
+
+    #[test]
+    fn test_recursion_with_boolean() {
+        assert_eq!(
+            Some(ExprResult::Int(3628800)),
+            interpret_this(
+                r#"
+                fn factor(n) {
+                    if (n == 1) {
+                        n;
+                    } else {
+                        factor(n - 1) * n;
+                    }
+                }
+
+                factor(10);
+        "#
+            )
+        );
+    }
+
+    #[test]
+    fn test_factor_with_loop() {
+        assert_eq!(
+            Some(ExprResult::Int(3628800)),
+            interpret_this(
+                r#"
+                n = 1;
+                i = 2;
+                factor = 10;
+
+                loop {
+                    n = n * i;
+
+                    if (i < factor) {
+                        i = i + 1;
+                    } else {
+                        break;
+                    }
+                }
+
+                n;
+        "#
+            )
+        );
+    }
+
+    #[test]
+    fn test_compare() {
+        assert_eq!(
+            Some(ExprResult::Bool(true)),
+            interpret_this("2 + 2 * 2 - 6 == 6 / 6 - 3 / 3;")
+        );
+        assert_eq!(Some(ExprResult::Bool(false)), interpret_this("3 == 2;"));
+
+        assert_eq!(Some(ExprResult::Bool(true)), interpret_this("1 < 2;"));
+        assert_eq!(Some(ExprResult::Bool(false)), interpret_this("3 < 2;"));
+
+        assert_eq!(Some(ExprResult::Bool(true)), interpret_this("1 <= 2;"));
+        assert_eq!(Some(ExprResult::Bool(true)), interpret_this("2 <= 2;"));
+        assert_eq!(Some(ExprResult::Bool(false)), interpret_this("3 <= 2;"));
+
+        assert_eq!(Some(ExprResult::Bool(false)), interpret_this("1 >= 2;"));
+        assert_eq!(Some(ExprResult::Bool(true)), interpret_this("2 >= 2;"));
+        assert_eq!(Some(ExprResult::Bool(true)), interpret_this("3 >= 2;"));
+    }
+
+    #[test]
+    fn test_arithmetic_precedence() {
+        assert_eq!(Some(ExprResult::Int(10)), interpret_this("2 * 3 + 4;"));
+        assert_eq!(Some(ExprResult::Int(14)), interpret_this("2 + 3 * 4;"));
+        assert_eq!(
+            Some(ExprResult::Int(3)),
+            interpret_this("2 + 2 * 2 / 2 - 2 / 2;")
+        );
+        assert_eq!(
+            Some(ExprResult::Int(14)),
+            interpret_this("2 * 2 * 2 + 2 + 2 + 2;")
+        );
+    }
+
+    #[test]
+    fn test_bitwise_ops() {
+        assert_eq!(
+            Some(ExprResult::new_array(vec![
+                ExprResult::Int(512),
+                ExprResult::Int(18),
+                ExprResult::Int(-16),
+                ExprResult::Int(7),
+                ExprResult::Int(14),
+                ExprResult::Int(2),
+                ExprResult::Int(6),
+                ExprResult::Int(-1),
+                ExprResult::Int(24),
+            ])),
+            interpret_this(
+                r#"
+                a = 3;
+                a <<= 3;
+                [2 ** 3 ** 2, 2 * 3 ** 2, ~3 << 2, 12 & 7 ^ 2 | 1, 1 << 3 | 3 << 1, ~1 & 3,
+                 1 + 2 << 1, ~0 >> 1, a];
+            "#
+            )
+        );
+        assert_eq!(Some(ExprResult::Int(5)), interpret_this("10 - 3 - 2;"));
+        assert_eq!(Some(ExprResult::Int(3)), interpret_this("100 / 10 / 3;"));
+        assert_eq!(
+            "Shift amount out of range: 64 at 1:3",
+            interpret_error("1 << 64;")
+        );
+        assert_eq!(
+            "Negative exponent: -1 at 1:3",
+            interpret_error("2 ** (0 - 1);")
+        );
+    }
+
+    #[test]
+    fn test_parens() {
+        assert_eq!(Some(ExprResult::Int(10)), interpret_this("(2 * 3) + 4;"));
+        assert_eq!(Some(ExprResult::Int(10)), interpret_this("4 + (2 * 3);"));
+        assert_eq!(Some(ExprResult::Int(14)), interpret_this("2 * (3 + 4);"));
+        assert_eq!(Some(ExprResult::Int(14)), interpret_this("(3 + 4) * 2;"));
+    }
+
+    #[test]
+    fn test_op_precedence() {
+        assert_eq!(Some(ExprResult::Bool(true)), interpret_this("1 + 1 == 2;"));
+        assert_eq!(Some(ExprResult::Bool(true)), interpret_this("2 == 1 + 1;"));
+        assert_eq!(
+            Some(ExprResult::Bool(true)),
+            interpret_this("2 * 2 + 2 == 3 / 3 + 5;")
+        );
+    }
+
+    #[test]
+    fn test_loop() {
+        assert_eq!(
+            Some(ExprResult::Bool(true)),
+            interpret_this("loop { break; } 1 == 1;")
+        )
+    }
+
+    #[test]
+    fn test_for_in() {
+        assert_eq!(
+            Some(ExprResult::new_array(vec![
+                ExprResult::Int(10),
+                ExprResult::Int(15),
+                ExprResult::Str("c-b-a-".to_string()),
+                ExprResult::Int(6),
+                ExprResult::Int(3),
+            ])),
+            interpret_this(
+                r#"
+                a = 0;
+                for i in 0..5 { a += i; }
+                b = 0;
+                for i in 1..=5 { b += i; i = 100; }
+                s = "";
+                for c in "abc" { s = c + "-" + s; }
+                items = [1, 2, 3];
+                sum = 0;
+                for item in items { push(items, item); sum += item; }
+                for i in 3..1 { sum = 0; }
+                n = 0;
+                for i in 0..10 { if (i == 3) { break; } n += 1; }
+                [a, b, s, sum, n];
+            "#
+            )
+        );
+        assert_eq!(
+            Some(ExprResult::Int(4)),
+            interpret_this(
+                "n = 0; for i in 0..1000000000000 { n = i; if (i == 2) { break; } } for i in 9223372036854775806..=9223372036854775807 { n += 1; } n;"
+            )
+        );
+        assert_eq!(
+            Some(ExprResult::Str("0..3 1..=2".to_string())),
+            interpret_this(r#"r = 0..3; "{r} {1..=2}";"#)
+        );
+        assert_eq!(
+            "Cannot iterate over Int(1)",
+            interpret_error("for i in 1 { i; }")
+        );
+        assert_eq!(
+            "Range bounds must be integers, got Int(0) and Str(\"a\")",
+            interpret_error(r#"0.."a";"#)
+        );
+    }
+
+    #[test]
+    fn test_generators() {
+        assert_eq!(
+            Some(ExprResult::new_array(vec![
+                ExprResult::new_array(vec![
+                    ExprResult::Int(3),
+                    ExprResult::Int(4),
+                    ExprResult::Int(5),
+                ]),
+                ExprResult::new_array(vec![
+                    ExprResult::Int(0),
+                    ExprResult::Int(1),
+                    ExprResult::Int(2),
+                ]),
+                ExprResult::Int(8),
+                ExprResult::Int(1),
+                ExprResult::Str("stop".to_string()),
+                ExprResult::Int(3),
+                ExprResult::Null,
+                ExprResult::Int(11),
+            ])),
+            interpret_this(
+                r#"
+                fn count_up(from, to) {
+                    i = from;
+                    loop {
+                        if (i > to) { break; }
+                        yield i;
+                        i += 1;
+                    }
+                }
+                fn naturals() {
+                    n = 0;
+                    loop {
+                        yield n;
+                        n += 1;
+                    }
+                }
+                fn evens(numbers) {
+                    for n in numbers {
+                        if (n % 2 == 1) { continue; }
+                        yield n;
+                    }
+                }
+                fn guarded() {
+                    try {
+                        yield 1;
+                        throw "stop";
+                        yield 2;
+                    } catch (e) {
+                        yield e;
+                    }
+                    yield 3;
+                }
+                fn scoped() {
+                    a = 1;
+                    { b = 10; yield a + b; }
+                    yield b;
+                }
+                collected = [];
+                for x in count_up(3, 5) { push(collected, x); }
+                numbers = naturals();
+                first = [next(numbers), next(numbers), next(numbers)];
+                found = 0;
+                for n in evens(naturals()) {
+                    if (n > 6) {
+                        found = n;
+                        break;
+                    }
+                }
+                g = guarded();
+                s = scoped();
+                [collected, first, found, next(g), next(g), next(g), next(g), next(s)];
+            "#
+            )
+        );
+        assert_eq!(
+            Some(ExprResult::new_array(vec![
+                ExprResult::Int(10),
+                ExprResult::Int(11),
+                ExprResult::Null,
+                ExprResult::Int(5),
+            ])),
+            interpret_this(
+                r#"
+                fn pick(x) {
+                    match (x) {
+                        1 => { yield 10; yield 11; }
+                        n => { yield n; }
+                    }
+                }
+                p = pick(1);
+                [next(p), next(p), next(p), next(pick(5))];
+            "#
+            )
+        );
+        for (source, err) in [
+            ("yield 1;", "Yield outside of a generator"),
+            (
+                "next(1);",
+                "Function 'next' expects a generator, got Int(1)",
+            ),
+            (
+                "fn g() { throw 5; yield 1; } next(g());",
+                "Uncaught exception: 5",
+            ),
+            (
+                "fn g() { yield 1; { b = 2; yield b; } yield b; } for x in g() { x; }",
+                "Variable not found",
+            ),
+        ] {
+            assert_eq!(err, interpret_error(source));
+        }
+    }
+
+    #[test]
+    fn test_generator_slots_are_reused() {
+        let input = "fn g() { yield 1; } for i in 0..50 { x = g(); next(x); } next(g());";
+        let reader = Box::new(StrReader::new(input));
+        let lexemes = Lexer::new(reader).read_any().unwrap();
+        let ast_root = Parser::new(lexemes.into()).build_ast().unwrap();
+        let mut interpreter = Interpreter::new();
+        assert_eq!(
+            Some(ExprResult::Int(1)),
+            interpreter.interpret(ast_root).unwrap()
+        );
+        assert!(interpreter.generators.len() <= 2);
+    }
+
+    #[test]
+    fn test_try_catch() {
+        assert_eq!(
+            Some(ExprResult::new_array(vec![
+                ExprResult::Int(1),
+                ExprResult::Str("missing".to_string()),
+                ExprResult::Str("too big: 20".to_string()),
+                ExprResult::Int(43),
+                ExprResult::Int(3),
+                ExprResult::Int(4),
+                ExprResult::Str("Index out of bounds: 5 (length 1)".to_string()),
+            ])),
+            interpret_this(
+                r#"
+                fn get_or(m, key, default) {
+                    try { m[key]; } catch (e) { default; }
+                }
+                fn check(n) {
+                    if (n > 10) { throw "too big: {n}"; }
+                    n;
+                }
+                fn deep(n) {
+                    if (n == 0) { throw 42; }
+                    deep(n - 1);
+                }
+                m = {"a": 1};
+                a = get_or(m, "a", 0);
+                b = get_or(m, "b", "missing");
+                c = try { check(20); } catch (e) { e; };
+                d = try { deep(5); } catch (e) { e + 1; };
+                sum = 0;
+                for i in 0..5 {
+                    try {
+                        if (i == 3) { throw i; }
+                        sum += i;
+                    } catch (stop) { break; }
+                }
+                err = try { [1][5]; } catch (e) { e; };
+                [a, b, c, d, sum, check(4), err];
+            "#
+            )
+        );
+        assert_eq!("Uncaught exception: 1", interpret_error("throw 1;"));
+        assert_eq!(
+            Some(ExprResult::Str("Division by zero at 1:9".to_string())),
+            interpret_this("try { 1 / 0; } catch (e) { e; }")
+        );
+        assert_eq!(
+            "Variable not found",
+            interpret_error("try { x; } catch (e) { 1; }")
+        );
+        assert_eq!(
+            "Variable not found",
+            interpret_error("fn g() { try { yield x; } catch (e) { yield 1; } } next(g());")
+        );
+        assert_eq!(
+            "Uncaught exception: oops",
+            interpret_error("fn f() { throw \"oops\"; } try { 1; } catch (e) { 2; } f();")
+        );
+    }
 
-#[cfg(test)]
-mod test {
-    use crate::interpreter::*;
-    use crate::lexer::*;
-    use crate::parser::*;
-    use crate::source_reader::*;
+    #[test]
+    fn test_float() {
+        assert_eq!(
+            Some(ExprResult::new_array(vec![
+                ExprResult::Float(3.0),
+                ExprResult::Float(0.5),
+                ExprResult::Int(2),
+                ExprResult::Bool(true),
+                ExprResult::Float(1.5),
+                ExprResult::Int(3),
+                ExprResult::Int(-3),
+                ExprResult::Int(3),
+                ExprResult::Float(4.0),
+                ExprResult::Str("2.0".to_string()),
+            ])),
+            interpret_this(
+                r#"
+                const HALF = 1.0 / 2;
+                x = 2.5;
+                x += HALF;
+                [x, HALF, 5 / 2, 1 < 1.5, 7.5 % 2, floor(3.7), floor(0 - 2.5), round(2.5), sqrt(16), str(2.0)];
+            "#
+            )
+        );
+        assert_eq!(
+            "Incompatible binop types: Float(1.0) BitAnd Int(1)",
+            interpret_error("1.0 & 1;")
+        );
+        assert_eq!(
+            "Invalid arguments for 'sqrt': [Str(\"a\")]",
+            interpret_error("sqrt(\"a\");")
+        );
+    }
 
     #[test]
-    fn test_empty_program() {
-        assert_eq!(None, interpret_this(""));
+    fn test_null() {
+        assert_eq!(
+            Some(ExprResult::new_array(vec![
+                ExprResult::Null,
+                ExprResult::Int(3),
+                ExprResult::Int(1),
+                ExprResult::Bool(true),
+                ExprResult::Bool(false),
+                ExprResult::Bool(true),
+                ExprResult::Int(5),
+            ])),
+            interpret_this(
+                r#"
+                fn maybe(n) { if (n > 0) { n; } }
+                calls = 0;
+                fn count() { calls += 1; }
+                x = null;
+                [x, x ?? maybe(0) ?? 3, maybe(1) ?? count(), is_null(maybe(0)), is_null(0), x == null, calls + 5];
+            "#
+            )
+        );
+        assert_eq!(
+            "Incompatible binop types: Null Add Int(1)",
+            interpret_error("null + 1;")
+        );
     }
 
     #[test]
-    fn test_expression_value() {
-        assert_eq!(Some(ExprResult::Int(7)), interpret_this("1; 3; 7;"));
+    fn test_big_int() {
+        assert_eq!(
+            Some(ExprResult::new_array(vec![
+                ExprResult::Str("15511210043330985984000000".to_string()),
+                ExprResult::Int(25),
+                ExprResult::Bool(true),
+                ExprResult::Bool(true),
+                ExprResult::Int(i64::MAX),
+                ExprResult::Str("100000000000000000000".to_string()),
+                ExprResult::Str("1267650600228229401496703205376".to_string()),
+                ExprResult::Float(1e20),
+            ])),
+            interpret_this(
+                r#"
+                fn fact(n) { if (n <= 1) { 1; } else { n * fact(n - 1); } }
+                big = fact(25);
+                max = 9223372036854775807;
+                [
+                    str(big), big / fact(24), big > max, max + 1 == max * 2 / 2 + 1,
+                    max + 1 - 1, str(floor(10.0 ** 20)), str(to_int("1024") ** 10),
+                    to_int("100000000000000000000") * 1.0
+                ];
+            "#
+            )
+        );
+        assert_eq!(
+            Some(ExprResult::new_array(vec![
+                ExprResult::Str("1180591620717411303424".to_string()),
+                ExprResult::Int(2),
+                ExprResult::Bool(true),
+            ])),
+            interpret_this(
+                "const BIG = 2 ** 70; [str(BIG), BIG / 2 ** 69, BIG > 9223372036854775807];"
+            )
+        );
+        assert_eq!(
+            "Bitwise operand out of range: 9223372036854775808 at 1:32",
+            interpret_error("a = 9223372036854775807 + 1; a & 1;")
+        );
+        assert_eq!(
+            "Bitwise operand out of range: 1180591620717411303424",
+            interpret_error("const BIG = 2 ** 70 | 1;")
+        );
     }
 
     #[test]
-    fn test_empty_fn_call() {
+    fn test_else_if_and_block() {
         assert_eq!(
-            Some(ExprResult::Null),
+            Some(ExprResult::new_array(vec![
+                ExprResult::Str("neg".to_string()),
+                ExprResult::Str("zero".to_string()),
+                ExprResult::Str("pos".to_string()),
+                ExprResult::Int(5),
+                ExprResult::Int(2),
+            ])),
             interpret_this(
                 r#"
-                fn main() {
+                fn sign(n) {
+                    if (n < 0) { "neg"; } else if (n == 0) { "zero"; } else { "pos"; }
                 }
-                main();
-        "#
+                x = 1;
+                y = {
+                    x = 2;
+                    z = 3;
+                    x + z;
+                };
+                [sign(0 - 5), sign(0), sign(7), y, x];
+            "#
             )
         );
+        assert_eq!("Variable not found", interpret_error("{ z = 1; } z;"));
     }
 
     #[test]
-    fn test_fn_call() {
+    fn test_loop_labels() {
         assert_eq!(
-            Some(ExprResult::Str("ok".into())),
+            Some(ExprResult::new_array(vec![
+                ExprResult::Int(63),
+                ExprResult::Int(6),
+                ExprResult::Int(12),
+            ])),
             interpret_this(
                 r#"
-                fn main() {
-                    "ok";
+                sum = 0;
+                pairs = 0;
+                'outer: for i in 0..5 {
+                    for j in 0..5 {
+                        if (j == 2) { continue 'outer; }
+                        if (i == 3) { break 'outer; }
+                        sum += i * 10 + j;
+                        pairs += 1;
+                    }
                 }
-                main();
-        "#
+                found = 'search: loop {
+                    i = 0;
+                    loop {
+                        i += 1;
+                        if (i % 2 == 1) { continue; }
+                        if (i > 10) { break 'search i; }
+                    }
+                };
+                [sum, pairs, found];
+            "#
             )
         );
     }
 
     #[test]
-    fn test_multiple_fn_call() {
+    fn test_loop_break_value() {
         assert_eq!(
-            Some(ExprResult::Int(9)),
+            Some(ExprResult::new_array(vec![
+                ExprResult::Int(16),
+                ExprResult::Int(7),
+                ExprResult::Str("b".to_string()),
+                ExprResult::Null,
+                ExprResult::Null,
+                ExprResult::Int(12),
+            ])),
             interpret_this(
                 r#"
-                fn main(v) {
-                    proxy(v);
+                fn first_power_over(limit) {
+                    p = 1;
+                    loop {
+                        if (p > limit) { break p; }
+                        p *= 2;
+                    }
                 }
-                fn proxy(x) {
-                    x;
+                fn find(items, wanted) {
+                    for item in items { if (item == wanted) { break item; } }
                 }
-                main(9);
-        "#
+                a = loop { break 7; };
+                pairs = 0;
+                loop {
+                    inner = loop { break 3; };
+                    pairs += inner * 4;
+                    break;
+                }
+                [first_power_over(10), a, find(["a", "b"], "b"), find([], 1), loop { break; }, pairs];
+            "#
+            )
+        );
+    }
+
+    #[test]
+    fn test_op_assignment() {
+        assert_eq!(
+            Some(ExprResult::Int(4)),
+            interpret_this("a = 10; a += 4; a -= 2; a *= 3; a /= 4; a %= 5; a;")
+        );
+        assert_eq!(
+            Some(ExprResult::Int(12)),
+            interpret_this("a = 2; b = 4; a += b * 2 + 2; a;")
+        );
+        assert_eq!("Variable not found", interpret_error("a += 1;"));
+    }
+
+    #[test]
+    fn test_type_annotations() {
+        assert_eq!(
+            Some(ExprResult::Int(7)),
+            interpret_this("fn add(a: int, b: int) -> int { a + b; } let x: int = 3; add(x, 4);")
+        );
+    }
+
+    #[test]
+    fn test_constants() {
+        assert_eq!(
+            Some(ExprResult::Int(26)),
+            interpret_this(
+                r#"
+                const SIZE = 4;
+                const AREA = SIZE * (SIZE + 1);
+                const BIG = AREA > 10;
+                fn area() { AREA; }
+                if (BIG) { area() + SIZE + 2; }
+            "#
+            )
+        );
+        assert_eq!(
+            "Cannot assign to constant 'SIZE'",
+            interpret_error("const SIZE = 4; SIZE = 5;")
+        );
+        assert_eq!(
+            "Cannot assign to constant 'SIZE'",
+            interpret_error("const SIZE = 4; SIZE += 1;")
+        );
+        assert_eq!(
+            "'x' is not a constant",
+            interpret_error("x = 1; const SIZE = x;")
+        );
+        assert_eq!(
+            "Constant expressions may only use literals, constants and operators",
+            interpret_error("fn f() { 1; } const SIZE = f();")
+        );
+        assert_eq!(
+            "Constant 'SIZE' is already defined",
+            interpret_error("const SIZE = 1; const SIZE = 2;")
+        );
+        assert_eq!(
+            "Division by zero in constant",
+            interpret_error("const SIZE = 1 / 0;")
+        );
+    }
+
+    #[test]
+    fn test_string_ops() {
+        assert_eq!(
+            Some(ExprResult::Str("n = 42!".into())),
+            interpret_this(r#"s = "n = " + str(40 + 2); s += "!"; s;"#)
+        );
+        assert_eq!(
+            Some(ExprResult::new_array(vec![
+                ExprResult::Bool(true),
+                ExprResult::Bool(true),
+                ExprResult::Bool(false),
+                ExprResult::Bool(true),
+            ])),
+            interpret_this(r#"["abc" < "abd", "b" > "abc", "a" >= "b", "a" <= "a"];"#)
+        );
+        assert_eq!(
+            "Incompatible binop types: Str(\"a\") Add Int(1)",
+            interpret_error(r#""a" + 1;"#)
+        );
+    }
+
+    #[test]
+    fn test_default_and_named_args() {
+        assert_eq!(
+            Some(ExprResult::new_array(vec![
+                ExprResult::Int(123),
+                ExprResult::Int(103),
+                ExprResult::Int(705),
+                ExprResult::Int(528),
+            ])),
+            interpret_this(
+                r#"
+                const TEN = 10;
+                fn f(a, b = TEN * 2, c = 3) { a * 100 + b + c; }
+                [f(1), f(1, 0), f(c: 3, b: 2, a: 7), f(5, c: 8)];
+            "#
+            )
+        );
+        assert_eq!(
+            "Missing argument 'a' of 'f'",
+            interpret_error("fn f(a, b = 1) { a; } f(b: 2);")
+        );
+        assert_eq!(
+            "Default value of argument 'b' of 'f' must be a constant: Division by zero in constant",
+            interpret_error("fn f(a, b = 1 / 0) { a; } f(1);")
+        );
+    }
+
+    #[test]
+    fn test_pipe() {
+        assert_eq!(
+            Some(ExprResult::new_array(vec![
+                ExprResult::Int(21),
+                ExprResult::Str("A".to_string()),
+                ExprResult::Int(8),
+            ])),
+            interpret_this(
+                r#"
+                fn f(a, b = 10, c = 0) { a * b + c; }
+                n = 1;
+                [n + 1 |> f() |> f(1, c: 1), "a b" |> upper() |> substr(0, 1), [1, 2] |> len() |> f(4)];
+            "#
+            )
+        );
+    }
+
+    #[test]
+    fn test_variadic_args() {
+        assert_eq!(
+            Some(ExprResult::new_array(vec![
+                ExprResult::Int(1),
+                ExprResult::Int(7),
+                ExprResult::Int(3),
+                ExprResult::Null,
+            ])),
+            interpret_this(
+                r#"
+                fn sum(base, ...xs) {
+                    i = 0;
+                    loop { if (i >= len(xs)) { break; } base += xs[i]; i += 1; }
+                    base;
+                }
+                fn count(...xs) { len(xs); }
+                [sum(1), sum(1, 2, 4), count(1, "a", [2]), println("sum:", sum(1, 2), [3])];
+            "#
+            )
+        );
+        assert_eq!(
+            "Variadic argument 'xs' of 'f' cannot be given by name",
+            interpret_error("fn f(a, ...xs) { a; } f(1, xs: 2);")
+        );
+        assert_eq!(
+            "Missing argument 'a' of 'f'",
+            interpret_error("fn f(a, ...xs) { a; } f();")
+        );
+    }
+
+    #[test]
+    fn test_tuple() {
+        assert_eq!(
+            Some(ExprResult::new_array(vec![
+                ExprResult::Int(3),
+                ExprResult::Int(1),
+                ExprResult::Int(2),
+                ExprResult::Int(1),
+                ExprResult::Bool(true),
+            ])),
+            interpret_this(
+                r#"
+                fn divmod(a, b) { (a / b, a % b); }
+                (q, r) = divmod(7, 2);
+                (x, y) = (1, 2);
+                (x, y) = (y, x);
+                [q, r, x, y, divmod(9, 5) == (1, 4)];
+            "#
+            )
+        );
+        assert_eq!(
+            Some(ExprResult::Str("(1, [2]) (3,)".into())),
+            interpret_this(r#"t = (1, [2]); "{t} {(3,)}";"#)
+        );
+        assert_eq!(
+            "Cannot destructure (1, 2) into 3 variables",
+            interpret_error("(a, b, c) = (1, 2);")
+        );
+        assert_eq!(
+            "Cannot destructure 1 into 2 variables",
+            interpret_error("(a, b) = 1;")
+        );
+    }
+
+    #[test]
+    fn test_string_interpolation() {
+        assert_eq!(
+            Some(ExprResult::Str("3 + 4 = 7, [1, 2] {ok}".into())),
+            interpret_this(r#"a = 3; b = 4; xs = [1, 2]; "{a} + {b} = {a + b}, {xs} {{{"ok"}}}";"#)
+        );
+        assert_eq!(
+            Some(ExprResult::Str("n: 2".into())),
+            interpret_this(r#"fn f(n) { "n: {n}"; } f(len({"a": 1, "b": 2}));"#)
+        );
+    }
+
+    #[test]
+    fn test_string_builtins() {
+        assert_eq!(
+            Some(ExprResult::new_array(vec![
+                ExprResult::Int(5),
+                ExprResult::Str("éll".into()),
+                ExprResult::Int(2),
+                ExprResult::Int(-1),
+                ExprResult::Str("HÉLLO".into()),
+                ExprResult::Str("héllo".into()),
+                ExprResult::Str("x y".into()),
+                ExprResult::Int(-12),
+                ExprResult::Str("[1, true]".into()),
+            ])),
+            interpret_this(
+                r#"
+                s = "hÉllo";
+                [
+                    len(s),
+                    lower(substr(s, 1, 3)),
+                    index_of(s, "ll"),
+                    index_of(s, "x"),
+                    upper(s),
+                    lower(s),
+                    trim("  x y "),
+                    to_int("-12"),
+                    str([1, true])
+                ];
+            "#
+            )
+        );
+        assert_eq!(
+            Some(ExprResult::new_array(vec![
+                ExprResult::Str("a".into()),
+                ExprResult::Str("".into()),
+                ExprResult::Str("b".into()),
+            ])),
+            interpret_this(r#"split("a,,b", ",");"#)
+        );
+        assert_eq!(
+            Some(ExprResult::Int(3)),
+            interpret_this(r#"len(split("abc", ""));"#)
+        );
+        assert_eq!(
+            "Cannot convert '1x' to int",
+            interpret_error(r#"to_int("1x");"#)
+        );
+        assert_eq!(
+            "Substring out of bounds: 2+2 (length 3)",
+            interpret_error(r#"substr("abc", 2, 2);"#)
+        );
+        assert_eq!(
+            "Substring out of bounds: 1+9223372036854775807 (length 3)",
+            interpret_error(r#"substr("abc", 1, 9223372036854775807);"#)
+        );
+        assert_eq!(
+            "Function 'upper' expects a string, got Int(1)",
+            interpret_error("upper(1);")
+        );
+        assert_eq!(
+            "Function 'split' expects 2 arguments",
+            interpret_error(r#"split("a");"#)
+        );
+    }
+
+    #[test]
+    fn test_fizzbuzz() {
+        assert_eq!(
+            Some(ExprResult::Int(100)),
+            interpret_this(
+                r#"
+                fn fizzbuzz(i, limit) {
+                    print(i);
+                    print(" ");
+                    if (i % 3 == 0) {
+                        print("fizz");
+                    }
+                    if (i % 5 == 0) {
+                        print("buzz");
+                    }
+                    print(" ");
+
+                    if (i < limit) {
+                        fizzbuzz(i + 1, limit);
+                    } else {
+                        i;
+                    }
+                }
+
+                fizzbuzz(1, 100);
+            "#
             )
         );
     }
 
     #[test]
-    fn test_fn_call_with_fn_call_arg() {
+    fn test_array() {
+        assert_eq!(
+            Some(ExprResult::new_array(vec![
+                ExprResult::Int(1),
+                ExprResult::Str("two".into()),
+                ExprResult::Bool(true)
+            ])),
+            interpret_this("[1, \"two\", true];")
+        );
+        assert_eq!(Some(ExprResult::new_array(vec![])), interpret_this("[];"));
+    }
+
+    #[test]
+    fn test_array_index() {
+        assert_eq!(
+            Some(ExprResult::Int(7)),
+            interpret_this("a = [3, 4, [5, 6]]; a[0] + a[1 + 1][1] - 2;")
+        );
+        assert_eq!(
+            Some(ExprResult::Int(21)),
+            interpret_this("a = [1, 2]; b = a; b[1] = 20; a[0] + a[1];")
+        );
+    }
+
+    #[test]
+    fn test_array_builtins() {
         assert_eq!(
-            Some(ExprResult::Int(6)),
-            interpret_this(
-                r#"
-                fn main(v) {
-                    v;
-                }
-                fn fixed() {
-                    6;
-                }
-                main(fixed());
-        "#
-            )
+            Some(ExprResult::Int(3)),
+            interpret_this("a = []; push(a, 1); push(a, 2); push(a, 3); len(a);")
+        );
+        assert_eq!(
+            Some(ExprResult::Int(12)),
+            interpret_this("a = [5, 7]; pop(a) + pop(a) + len(a);")
         );
     }
 
     #[test]
-    fn test_binop_simple() {
+    fn test_array_bounds_check() {
         assert_eq!(
-            Some(ExprResult::Int(17)),
-            interpret_this(
-                r#"
-                a = 12;
-                b = 5;
-                a + b;
-        "#
-            )
+            "Index out of bounds: 2 (length 2)",
+            interpret_error("a = [1, 2]; a[2];")
         );
-
-        assert_eq!(Some(ExprResult::Int(3)), interpret_this("13 % 10;"));
+        assert_eq!(
+            "Index out of bounds: -1 (length 2)",
+            interpret_error("a = [1, 2]; a[0 - 1] = 3;")
+        );
+        assert_eq!("Pop from empty array", interpret_error("pop([]);"));
     }
 
     #[test]
-    fn test_binop_complex() {
+    fn test_map() {
         assert_eq!(
-            Some(ExprResult::Int(62)),
+            Some(ExprResult::Int(5)),
             interpret_this(
                 r#"
-                fn id(v) { v; }
-                a = 12;
-                b = 5;
-                a + b * 100 / id(10);
+                m = {"a": 1, 7: 2};
+                m["b"] = 3;
+                m["a"] = m["a"] + 1;
+                m["a"] + m["b"];
         "#
             )
         );
+        assert_eq!(Some(ExprResult::Int(0)), interpret_this("len({});"));
+        assert_eq!(
+            Some(ExprResult::Int(3)),
+            interpret_this("k = \"b\"; v = 3; m = {k: v}; m[\"b\"];")
+        );
     }
 
     #[test]
-    fn test_if() {
+    fn test_map_builtins() {
         assert_eq!(
-            Some(ExprResult::Int(2)),
-            interpret_this(
-                r#"
-                if (1) {
-                    2;
-                } else {
-                    3;
-                }
-        "#
-            )
+            Some(ExprResult::new_array(vec![
+                ExprResult::Str("b".into()),
+                ExprResult::Int(3),
+                ExprResult::Str("a".into())
+            ])),
+            interpret_this("m = {\"b\": 1, 3: 2}; m[\"a\"] = 0; keys(m);")
+        );
+        assert_eq!(
+            Some(ExprResult::Bool(true)),
+            interpret_this("m = {\"b\": 1}; has(m, \"b\");")
+        );
+        assert_eq!(
+            Some(ExprResult::Bool(false)),
+            interpret_this("m = {\"b\": 1}; has(m, 1);")
         );
-
         assert_eq!(
             Some(ExprResult::Int(3)),
-            interpret_this(
-                r#"
-                if (0) {
-                    2;
-                } else {
-                    3;
-                }
-        "#
-            )
+            interpret_this("len({1: 1, 2: 2, \"3\": 3});")
         );
+    }
 
+    #[test]
+    fn test_map_counting() {
         assert_eq!(
-            Some(ExprResult::Int(2)),
+            Some(ExprResult::new_map(IndexMap::from([
+                (MapKey::Int(3), ExprResult::Int(3)),
+                (MapKey::Int(1), ExprResult::Int(2)),
+                (MapKey::Int(2), ExprResult::Int(1)),
+            ]))),
             interpret_this(
                 r#"
-                if ("ok") {
-                    2;
-                } else {
-                    3;
+                items = [3, 1, 3, 2, 1, 3];
+                counts = {};
+                loop {
+                    if (len(items) == 0) {
+                        break;
+                    }
+                    item = pop(items);
+                    if (has(counts, item)) {
+                        counts[item] = counts[item] + 1;
+                    } else {
+                        counts[item] = 1;
+                    }
                 }
+                counts;
         "#
             )
         );
+    }
+
+    #[test]
+    fn test_map_errors() {
+        assert_eq!("Missing map key: x", interpret_error("{}[\"x\"];"));
+        assert_eq!("Invalid map key: Bool(true)", interpret_error("{}[true];"));
+    }
 
+    #[test]
+    fn test_struct() {
         assert_eq!(
-            Some(ExprResult::Int(3)),
+            Some(ExprResult::Int(7)),
             interpret_this(
                 r#"
-                if ("") {
-                    2;
-                } else {
-                    3;
-                }
+                struct Point { x, y }
+                p = Point { y: 2, x: 1 };
+                p.x = p.y + 3;
+                p.x + p.y;
         "#
             )
         );
-
         assert_eq!(
-            Some(ExprResult::Int(3)),
+            Some(ExprResult::Str(
+                "Line { from: Point { x: 1, y: 9 }, to: Point { x: 3, y: 4 } }".into()
+            )),
             interpret_this(
                 r#"
-                fn empty() {}
-
-                if (empty()) {
-                    2;
-                } else {
-                    3;
+                struct Point { x, y }
+                struct Line { from, to }
+                fn move_up(p) {
+                    p.y = 9;
                 }
+                start = Point { x: 1, y: 2 };
+                line = Line { from: start, to: Point { x: 3, y: 4 } };
+                move_up(start);
+                line;
         "#
             )
+            .map(|line| ExprResult::Str(line.to_string()))
         );
+    }
 
+    #[test]
+    fn test_struct_errors() {
         assert_eq!(
-            Some(ExprResult::Int(2)),
-            interpret_this(
-                r#"
-                if (true) {
-                    2;
-                } else {
-                    3;
-                }
-        "#
-            )
+            "Struct 'Point' has no field 'z'",
+            interpret_error("struct Point { x, y } p = Point { x: 1, y: 2 }; p.z;")
         );
-
         assert_eq!(
-            Some(ExprResult::Int(3)),
-            interpret_this(
-                r#"
-                if (false) {
-                    2;
-                } else {
-                    3;
-                }
-        "#
-            )
+            "Struct 'Point' has no field 'z'",
+            interpret_error("struct Point { x, y } Point { x: 1, z: 2 };")
+        );
+        assert_eq!(
+            "Missing field 'y' for struct 'Point'",
+            interpret_error("struct Point { x, y } Point { x: 1, x: 2 };")
+        );
+        assert_eq!(
+            "Struct 'Point' expects 2 fields, got 1",
+            interpret_error("struct Point { x, y } Point { x: 1 };")
         );
+        assert_eq!("Missing struct 'Point'", interpret_error("Point { x: 1 };"));
     }
 
     #[test]
-    fn test_recursion() {
+    fn test_enum_and_match() {
         assert_eq!(
-            Some(ExprResult::Int(3628800)),
+            Some(ExprResult::Int(89)),
             interpret_this(
                 r#"
-                fn factor(n) {
-                    if (n) {
-                        factor(n - 1) * n;
-                    } else {
-                        1;
+                enum Shape { Circle(r), Rect(w, h), Empty }
+                fn area(s) {
+                    match (s) {
+                        Shape::Circle(r) => { 3 * r * r; }
+                        Shape::Rect(w, h) => { w * h; }
+                        Shape::Empty => { 0; }
                     }
                 }
-
-                factor(10);
+                area(Shape::Circle(5)) + area(Shape::Rect(2, 7)) + area(Shape::Empty);
         "#
             )
         );
-    }
-
-    #[test]
-    fn test_recursion_with_boolean() {
         assert_eq!(
-            Some(ExprResult::Int(3628800)),
+            Some(ExprResult::Str("two".into())),
             interpret_this(
                 r#"
-                fn factor(n) {
-                    if (n == 1) {
-                        n;
-                    } else {
-                        factor(n - 1) * n;
-                    }
+                match (1 + 1) {
+                    1 => { "one"; }
+                    2 => { "two"; }
+                    _ => { "many"; }
                 }
-
-                factor(10);
         "#
             )
         );
-    }
-
-    #[test]
-    fn test_factor_with_loop() {
         assert_eq!(
-            Some(ExprResult::Int(3628800)),
+            Some(ExprResult::Int(6)),
             interpret_this(
                 r#"
-                n = 1;
-                i = 2;
-                factor = 10;
-
-                loop {
-                    n = n * i;
-
-                    if (i < factor) {
-                        i = i + 1;
-                    } else {
-                        break;
+                enum Opt { Some(v), None }
+                match (Opt::Some(3)) {
+                    Opt::None => { 0; }
+                    other => {
+                        match (other) {
+                            Opt::Some(v) => { v * 2; }
+                            _ => { 1; }
+                        }
                     }
                 }
-
-                n;
         "#
             )
         );
-    }
-
-    #[test]
-    fn test_compare() {
-        assert_eq!(
-            Some(ExprResult::Bool(true)),
-            interpret_this("2 + 2 * 2 - 6 == 6 / 6 - 3 / 3;")
-        );
-        assert_eq!(Some(ExprResult::Bool(false)), interpret_this("3 == 2;"));
-
-        assert_eq!(Some(ExprResult::Bool(true)), interpret_this("1 < 2;"));
-        assert_eq!(Some(ExprResult::Bool(false)), interpret_this("3 < 2;"));
-
-        assert_eq!(Some(ExprResult::Bool(true)), interpret_this("1 <= 2;"));
-        assert_eq!(Some(ExprResult::Bool(true)), interpret_this("2 <= 2;"));
-        assert_eq!(Some(ExprResult::Bool(false)), interpret_this("3 <= 2;"));
-
-        assert_eq!(Some(ExprResult::Bool(false)), interpret_this("1 >= 2;"));
-        assert_eq!(Some(ExprResult::Bool(true)), interpret_this("2 >= 2;"));
-        assert_eq!(Some(ExprResult::Bool(true)), interpret_this("3 >= 2;"));
-    }
-
-    #[test]
-    fn test_arithmetic_precedence() {
-        assert_eq!(Some(ExprResult::Int(10)), interpret_this("2 * 3 + 4;"));
-        assert_eq!(Some(ExprResult::Int(14)), interpret_this("2 + 3 * 4;"));
-        assert_eq!(
-            Some(ExprResult::Int(3)),
-            interpret_this("2 + 2 * 2 / 2 - 2 / 2;")
-        );
-        assert_eq!(
-            Some(ExprResult::Int(14)),
-            interpret_this("2 * 2 * 2 + 2 + 2 + 2;")
-        );
-    }
-
-    #[test]
-    fn test_parens() {
-        assert_eq!(Some(ExprResult::Int(10)), interpret_this("(2 * 3) + 4;"));
-        assert_eq!(Some(ExprResult::Int(10)), interpret_this("4 + (2 * 3);"));
-        assert_eq!(Some(ExprResult::Int(14)), interpret_this("2 * (3 + 4);"));
-        assert_eq!(Some(ExprResult::Int(14)), interpret_this("(3 + 4) * 2;"));
-    }
-
-    #[test]
-    fn test_op_precedence() {
-        assert_eq!(Some(ExprResult::Bool(true)), interpret_this("1 + 1 == 2;"));
-        assert_eq!(Some(ExprResult::Bool(true)), interpret_this("2 == 1 + 1;"));
         assert_eq!(
             Some(ExprResult::Bool(true)),
-            interpret_this("2 * 2 + 2 == 3 / 3 + 5;")
+            interpret_this(
+                r#"
+                enum Opt { Some(v), None }
+                Opt::Some(1) == Opt::Some(1);
+        "#
+            )
         );
     }
 
     #[test]
-    fn test_loop() {
-        assert_eq!(
-            Some(ExprResult::Bool(true)),
-            interpret_this("loop { break; } 1 == 1;")
-        )
-    }
-
-    #[test]
-    fn test_fizzbuzz() {
+    fn test_match_bool() {
         assert_eq!(
-            Some(ExprResult::Int(100)),
-            interpret_this(
-                r#"
-                fn fizzbuzz(i, limit) {
-                    print(i);
-                    print(" ");
-                    if (i % 3 == 0) {
-                        print("fizz");
-                    }
-                    if (i % 5 == 0) {
-                        print("buzz");
-                    }
-                    print(" ");
-
-                    if (i < limit) {
-                        fizzbuzz(i + 1, limit);
-                    } else {
-                        i;
-                    }
-                }
-
-                fizzbuzz(1, 100);
-            "#
-            )
+            Some(ExprResult::Int(0)),
+            interpret_this("match (false) { true => { 1; } false => { 0; } }")
         );
     }
 
@@ -657,4 +3299,14 @@ mod test {
         let ast_root = Parser::new(lexemes.into()).build_ast().unwrap();
         Interpreter::new().interpret(ast_root).unwrap()
     }
+
+    fn interpret_error(input: &'static str) -> String {
+        let reader = Box::new(StrReader::new(input));
+        let lexemes = Lexer::new(reader).read_any().unwrap();
+        let ast_root = Parser::new(lexemes.into()).build_ast().unwrap();
+        Interpreter::new()
+            .interpret(ast_root)
+            .unwrap_err()
+            .to_string()
+    }
 }
@@ -1,4 +1,10 @@
-pub type ImmVal = i32;
+use std::collections::HashMap;
+
+use num_bigint::BigInt;
+
+use crate::shared::*;
+
+pub type ImmVal = i64;
 pub type CondCode = Vec<CondResult>;
 pub type OutRegAndOps = (Reg, Vec<Operation>);
 pub type MaybeOutRegAndOps = (Option<Reg>, Vec<Operation>);
@@ -8,6 +14,8 @@ pub type RegAddr = usize;
 pub enum Label {
     Named(String),
     Numbered(usize),
+    // Functions of imported modules: (module, function).
+    Namespaced(String, String),
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -16,6 +24,8 @@ pub enum Reg {
     Arp(RegAddr), // ARP + offset.
 }
 
+// Not all of the ILOC instruction set is generated yet.
+#[allow(dead_code)]
 #[derive(Debug, PartialEq)]
 pub enum CondResult {
     Eq,
@@ -26,16 +36,39 @@ pub enum CondResult {
     Gte,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, PartialEq)]
 pub enum Operation {
     // This is a hack during generation.
     Label(Label),
+    // Source location of the next operation, for its runtime errors. Removed from the
+    // instructions when the IR is assembled.
+    Loc(Loc),
     // This is not part of ILOC but without these it's not trivial how to make proc calls.
     Call(Label),
     Return,
     Push(Reg),
-    PushI(i32),
+    PushI(i64),
     Pop(Reg),
+    // Heap management is not part of ILOC either: reserves `size` cells and returns the address.
+    Alloc {
+        size: Reg,
+        out: Reg,
+    },
+    // Stops the execution with a runtime error, each `{}` of `msg` is replaced by the value of the
+    // next register of `args`.
+    Abort {
+        msg: String,
+        args: Vec<Reg>,
+    },
+    // Exception handling is not part of ILOC either. A handler catches throws and runtime errors
+    // until it is popped, jumping to `label` with the thrown value (or the error) in `reg`.
+    PushHandler {
+        label: Label,
+        reg: Reg,
+    },
+    PopHandler,
+    Throw(Reg),
 
     Add {
         lhs: Reg,
@@ -57,6 +90,42 @@ pub enum Operation {
         rhs: Reg,
         out: Reg,
     },
+    Pow {
+        lhs: Reg,
+        rhs: Reg,
+        out: Reg,
+    },
+    // Not in ILOC, which expands it to div, mul and sub. That only holds for ints.
+    Mod {
+        lhs: Reg,
+        rhs: Reg,
+        out: Reg,
+    },
+    And {
+        lhs: Reg,
+        rhs: Reg,
+        out: Reg,
+    },
+    Or {
+        lhs: Reg,
+        rhs: Reg,
+        out: Reg,
+    },
+    Xor {
+        lhs: Reg,
+        rhs: Reg,
+        out: Reg,
+    },
+    LShift {
+        lhs: Reg,
+        rhs: Reg,
+        out: Reg,
+    },
+    RShift {
+        lhs: Reg,
+        rhs: Reg,
+        out: Reg,
+    },
 
     AddI {
         lhs: Reg,
@@ -78,6 +147,50 @@ pub enum Operation {
         rhs: ImmVal,
         out: Reg,
     },
+    PowI {
+        lhs: Reg,
+        rhs: ImmVal,
+        out: Reg,
+    },
+    AndI {
+        lhs: Reg,
+        rhs: ImmVal,
+        out: Reg,
+    },
+    OrI {
+        lhs: Reg,
+        rhs: ImmVal,
+        out: Reg,
+    },
+    XorI {
+        lhs: Reg,
+        rhs: ImmVal,
+        out: Reg,
+    },
+    LShiftI {
+        lhs: Reg,
+        rhs: ImmVal,
+        out: Reg,
+    },
+    RShiftI {
+        lhs: Reg,
+        rhs: ImmVal,
+        out: Reg,
+    },
+
+    // Math builtins, not part of ILOC either. The operand may be an int or a float.
+    Sqrt {
+        reg: Reg,
+        out: Reg,
+    },
+    Floor {
+        reg: Reg,
+        out: Reg,
+    },
+    Round {
+        reg: Reg,
+        out: Reg,
+    },
 
     Load {
         addr: Reg,
@@ -97,6 +210,18 @@ pub enum Operation {
         val: ImmVal,
         out: Reg,
     },
+    // Bigint constants, ints that do not fit in an `ImmVal`.
+    LoadBigI {
+        val: BigInt,
+        out: Reg,
+    },
+    LoadF {
+        val: f64,
+        out: Reg,
+    },
+    LoadNull {
+        out: Reg,
+    },
 
     Store {
         reg: Reg,
@@ -127,6 +252,7 @@ pub enum Operation {
     JumpI(Label),
     Jump(Reg),
 
+    // Loads the address of `label` into `reg`, to build jump tables for `Jump`.
     Tbl {
         reg: Reg,
         label: Label,
@@ -180,13 +306,27 @@ pub enum Operation {
 pub struct IR {
     pub instructions: Vec<Operation>,
     pub return_reg: Option<Reg>,
+    // Instruction index -> source location.
+    pub locs: HashMap<usize, Loc>,
 }
 
 impl IR {
-    pub fn new(instructions: Vec<Operation>, return_reg: Option<Reg>) -> IR {
+    pub fn new(ops: Vec<Operation>, return_reg: Option<Reg>) -> IR {
+        let mut instructions = vec![];
+        let mut locs = HashMap::new();
+        for op in ops {
+            match op {
+                Operation::Loc(loc) => {
+                    locs.insert(instructions.len(), loc);
+                }
+                op => instructions.push(op),
+            }
+        }
+
         IR {
             instructions,
             return_reg,
+            locs,
         }
     }
 }
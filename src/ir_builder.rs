@@ -1,8 +1,23 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use crate::ast::*;
 use crate::ir::*;
 use crate::shared::*;
+use crate::type_checker::*;
+
+/**
+ * Arrays live on the heap behind a fixed header, so growing the elements does not invalidate
+ * other references to the same array:
+ * addr + 0: length
+ * addr + 1: capacity
+ * addr + 2: address of the elements
+ */
+const ARRAY_HEADER_SIZE: ImmVal = 3;
+const ARRAY_CAPACITY_OFFS: ImmVal = 1;
+const ARRAY_ELEMENTS_OFFS: ImmVal = 2;
+// Records start with the tag of their struct, followed by the fields.
+const RECORD_TAG_OFFS: ImmVal = 0;
+const RECORD_HEADER_SIZE: ImmVal = 1;
 
 /**
  * --------------
@@ -14,7 +29,6 @@ use crate::shared::*;
  * local var 2     > local variables
  * ...
  */
-
 struct Scope {
     next_free_reg_addr: RegAddr,
     variables: HashMap<String, Reg>,
@@ -29,10 +43,41 @@ impl Scope {
     }
 }
 
+/**
+ * Fields in declaration order and the tag that identifies the struct of a record at runtime.
+ */
+struct StructLayout {
+    tag: ImmVal,
+    fields: Vec<String>,
+}
+
+/**
+ * Jump targets of an enclosing loop, looked up by `break` and `continue`.
+ */
+#[derive(Clone)]
+struct LoopContext {
+    label: Option<String>,
+    // Number of enclosing `try` handlers, the ones above it are popped when jumping out.
+    try_depth: usize,
+    continue_label: Label,
+    end_label: Label,
+    out: Reg,
+}
+
 pub struct IRBuilder {
     next_free_label: usize,
     frames: Vec<Scope>,
-    break_context_ends: Vec<Label>,
+    loop_contexts: Vec<LoopContext>,
+    try_depth: usize,
+    structs: HashMap<String, StructLayout>,
+    enums: EnumDefs,
+    constants: ConstDefs,
+    // Module of the definitions being built and the functions of every module.
+    module: Option<String>,
+    module_functions: HashSet<(String, String)>,
+    // Functions ending with a tuple literal push that many values instead of one.
+    tuple_returns: HashMap<Label, usize>,
+    fn_params: HashMap<Label, Vec<FnParam>>,
 }
 
 impl IRBuilder {
@@ -40,17 +85,35 @@ impl IRBuilder {
         IRBuilder {
             next_free_label: 0,
             frames: vec![Scope::new()],
-            break_context_ends: vec![],
+            loop_contexts: vec![],
+            try_depth: 0,
+            structs: HashMap::new(),
+            enums: HashMap::new(),
+            constants: HashMap::new(),
+            module: None,
+            module_functions: HashSet::new(),
+            tuple_returns: HashMap::new(),
+            fn_params: HashMap::new(),
         }
     }
 
+    /**
+     * The VM has no strings: a string literal, string pattern or string constant fails the build
+     * where it is lowered. This includes functions that are never called, as every function is
+     * lowered.
+     */
     pub fn build(&mut self, ast: AstProgram) -> Result<IR, Error> {
+        TypeChecker::new().check(&ast)?;
+
         let (out, ops) = self.build_program(ast)?;
 
         Ok(IR::new(ops, out))
     }
 
     fn build_program(&mut self, ast: AstProgram) -> Result<MaybeOutRegAndOps, Error> {
+        self.constants = collect_constants(&ast.statements)?;
+        self.collect_definitions(None, &ast.statements)?;
+
         let mut ins = vec![];
         let mut out: Option<Reg> = None;
         for stmt in ast.statements {
@@ -62,25 +125,131 @@ impl IRBuilder {
         Ok((out, ins))
     }
 
+    /**
+     * Struct and enum layouts and function signatures are needed before any code using them is
+     * generated.
+     */
+    fn collect_definitions(
+        &mut self,
+        module: Option<&str>,
+        statements: &[AstStatement],
+    ) -> Result<(), Error> {
+        for stmt in statements {
+            match stmt {
+                AstStatement::StructDef { name, fields } => {
+                    let layout = StructLayout {
+                        tag: self.structs.len() as ImmVal,
+                        fields: fields.iter().map(|field| field.to_string()).collect(),
+                    };
+                    self.structs.insert(name.to_string(), layout);
+                }
+                AstStatement::EnumDef { name, variants } => {
+                    self.enums.insert(
+                        name.to_string(),
+                        variants
+                            .iter()
+                            .map(|variant| (variant.name.to_string(), variant.fields.len()))
+                            .collect(),
+                    );
+                }
+                AstStatement::FnDef {
+                    name, args, block, ..
+                } => {
+                    if let Some(module) = module {
+                        self.module_functions
+                            .insert((module.to_string(), name.to_string()));
+                    }
+                    let fn_label = self.fn_label(module, name);
+                    if let Some(AstBlockLine::Expr(AstExpr::Tuple(items))) = block.0.last() {
+                        self.tuple_returns.insert(fn_label.clone(), items.len());
+                    }
+                    self.fn_params
+                        .insert(fn_label, fn_params(name, args, &self.constants)?);
+                }
+                AstStatement::Module { name, statements } => {
+                    self.collect_definitions(Some(name), statements)?;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
     fn build_statement(&mut self, stmt: AstStatement) -> Result<MaybeOutRegAndOps, Error> {
         match stmt {
-            AstStatement::FnDef { name, args, block } => {
+            AstStatement::FnDef {
+                name, args, block, ..
+            } => {
                 let ops = self.build_fn_def(name, args, block)?;
                 Ok((None, ops))
             }
+            AstStatement::StructDef { .. }
+            | AstStatement::EnumDef { .. }
+            | AstStatement::ConstDef { .. } => Ok((None, vec![])),
+            AstStatement::Import(path) => Err(format!("Unresolved import '{}'", path).into()),
+            AstStatement::Module { name, statements } => {
+                self.module = Some(name.to_string());
+
+                let mut ops = vec![];
+                for stmt in statements {
+                    if let AstStatement::BlockLine(_) = stmt {
+                        return Err("Modules may only contain definitions".into());
+                    }
+                    let (_, mut stmt_ops) = self.build_statement(stmt)?;
+                    ops.append(&mut stmt_ops);
+                }
+
+                self.module = None;
+                Ok((None, ops))
+            }
             AstStatement::BlockLine(line) => self.build_block_line(line),
         }
     }
 
+    fn fn_label(&self, module: Option<&str>, name: &str) -> Label {
+        match module {
+            Some(module) => Label::Namespaced(module.into(), name.into()),
+            None => Label::Named(name.into()),
+        }
+    }
+
+    /**
+     * Unqualified calls inside a module prefer the module's own functions.
+     */
+    fn resolve_fn_label(&self, module: Option<&str>, name: &str) -> Result<Label, Error> {
+        match module {
+            Some(module) => {
+                if !self
+                    .module_functions
+                    .contains(&(module.to_string(), name.to_string()))
+                {
+                    return Err(format!("Missing function '{}::{}'", module, name).into());
+                }
+                Ok(self.fn_label(Some(module), name))
+            }
+            None => match &self.module {
+                Some(current_module)
+                    if self
+                        .module_functions
+                        .contains(&(current_module.clone(), name.to_string())) =>
+                {
+                    Ok(self.fn_label(Some(current_module), name))
+                }
+                _ => Ok(self.fn_label(None, name)),
+            },
+        }
+    }
+
     fn build_fn_def(
         &mut self,
         name: &str,
-        args: Vec<&str>,
+        args: Vec<AstFnArg>,
         block: AstBlock,
     ) -> Result<Vec<Operation>, Error> {
         let mut ops = vec![];
 
-        let fn_start_label = Label::Named(name.into());
+        let fn_start_label = self.fn_label(self.module.as_deref(), name);
         let fn_end_label = self.next_free_label();
 
         // Need to declare: `Label(end-of(name))` (so we can jump from pre-function line to after the function)
@@ -98,21 +267,57 @@ impl IRBuilder {
         // Pop arguments.
         // !!! DANGER !!! Currently there is no check that each push-ed value will be popped. RISK!
         for arg in args {
-            let arg_reg = self.get_variable_reg_addr(arg);
-            ops.push(Operation::Pop(arg_reg));
+            let arg_reg = self.get_variable_reg_addr(arg.name);
+            if arg.variadic {
+                ops.append(&mut self.build_variadic_arg(arg_reg));
+            } else {
+                ops.push(Operation::Pop(arg_reg));
+            }
         }
 
+        let mut block = block;
+        // A function ending with `throw` never returns, so there is no value to push.
+        let ends_with_throw = matches!(block.0.last(), Some(AstBlockLine::Throw(_)));
+        let tuple_items = match block.0.last() {
+            Some(AstBlockLine::Expr(AstExpr::Tuple(_))) => match block.0.pop() {
+                Some(AstBlockLine::Expr(AstExpr::Tuple(items))) => Some(items),
+                _ => unreachable!(),
+            },
+            Some(AstBlockLine::Expr(expr)) if self.is_tuple_valued(expr) => {
+                return Err(format!(
+                    "Function '{}' must end with a tuple literal to return several values in IR",
+                    name
+                )
+                .into());
+            }
+            _ => None,
+        };
+
         let (block_out_reg, mut block_ops) = self.build_block(block)?;
-        if block_out_reg.is_none() {
-            return Err("No expression ending for a block.".into());
+        ops.append(&mut block_ops);
+
+        // Save return values: tuple items are pushed in reverse, so the caller pops them in order.
+        match tuple_items {
+            Some(items) => {
+                let mut item_regs = vec![];
+                for item in items {
+                    let (item_reg, mut item_ops) = self.build_expr(item)?;
+                    ops.append(&mut item_ops);
+                    item_regs.push(item_reg);
+                }
+                while let Some(item_reg) = item_regs.pop() {
+                    ops.push(Operation::Push(item_reg));
+                }
+            }
+            None => match block_out_reg {
+                Some(block_out_reg) => ops.push(Operation::Push(block_out_reg)),
+                None if ends_with_throw => {}
+                None => return Err("No expression ending for a block.".into()),
+            },
         }
 
         self.frames.pop();
 
-        ops.append(&mut block_ops);
-
-        // Save return value.
-        ops.push(Operation::Push(block_out_reg.unwrap()));
         ops.push(Operation::Return);
 
         ops.push(Operation::Label(fn_end_label));
@@ -120,6 +325,38 @@ impl IRBuilder {
         Ok(ops)
     }
 
+    /**
+     * Whether the expression evaluates to a tuple, through the tail lines of its blocks.
+     */
+    fn is_tuple_valued(&self, expr: &AstExpr) -> bool {
+        let block_is_tuple_valued = |block: &AstBlock| match block.0.last() {
+            Some(AstBlockLine::Expr(expr)) => self.is_tuple_valued(expr),
+            _ => false,
+        };
+
+        match expr {
+            AstExpr::Tuple(_) => true,
+            AstExpr::ParenExpr(expr) => self.is_tuple_valued(expr),
+            AstExpr::FnCall { module, name, .. } => self
+                .resolve_fn_label(*module, name)
+                .is_ok_and(|fn_label| self.tuple_returns.contains_key(&fn_label)),
+            AstExpr::If {
+                true_block,
+                false_block,
+                ..
+            } => {
+                block_is_tuple_valued(true_block)
+                    || false_block.as_ref().is_some_and(block_is_tuple_valued)
+            }
+            AstExpr::Block(block) => block_is_tuple_valued(block),
+            AstExpr::Try {
+                block, catch_block, ..
+            } => block_is_tuple_valued(block) || block_is_tuple_valued(catch_block),
+            AstExpr::Match { arms, .. } => arms.iter().any(|arm| block_is_tuple_valued(&arm.block)),
+            _ => false,
+        }
+    }
+
     fn build_block(&mut self, block: AstBlock) -> Result<MaybeOutRegAndOps, Error> {
         let mut ops = vec![];
         let mut out: Option<Reg> = None;
@@ -138,182 +375,1695 @@ impl IRBuilder {
                 let (expr_reg, ops) = self.build_expr(expr)?;
                 Ok((Some(expr_reg), ops))
             }
-            AstBlockLine::Loop(block) => self.build_loop(block).map(|ops| (None, ops)),
-            AstBlockLine::Break => self.build_break().map(|ops| (None, ops)),
+            AstBlockLine::Break { label, value } => {
+                self.build_break(label, value).map(|ops| (None, ops))
+            }
+            AstBlockLine::Continue(label) => {
+                let target = self.loop_context(label, "continue")?;
+                let mut ops = self.pop_handlers(&target);
+                ops.push(Operation::JumpI(target.continue_label));
+                Ok((None, ops))
+            }
+            AstBlockLine::Throw(expr) => {
+                let (value_reg, mut ops) = self.build_expr(expr)?;
+                ops.push(Operation::Throw(value_reg));
+                Ok((None, ops))
+            }
+            AstBlockLine::Yield(_) => Err("Generators are not supported in IR".into()),
         }
     }
 
-    fn build_break(&mut self) -> Result<Vec<Operation>, Error> {
-        self.break_context_ends
-            .last()
-            .map(|label| vec![Operation::JumpI(label.clone())])
-            .ok_or("No context to break out from".into())
+    /**
+     * Handlers of the `try` blocks a `break` or `continue` jumps out of.
+     */
+    fn pop_handlers(&self, target: &LoopContext) -> Vec<Operation> {
+        (target.try_depth..self.try_depth)
+            .map(|_| Operation::PopHandler)
+            .collect()
     }
 
-    fn build_loop(&mut self, block: AstBlock) -> Result<Vec<Operation>, Error> {
+    /**
+     * The loop targeted by `label`, or the innermost loop without one.
+     */
+    fn loop_context(&self, label: Option<&str>, keyword: &str) -> Result<LoopContext, Error> {
+        self.loop_contexts
+            .iter()
+            .rev()
+            .find(|context| label.is_none() || context.label.as_deref() == label)
+            .cloned()
+            .ok_or_else(|| format!("No context to {} from", keyword).into())
+    }
+
+    /**
+     * `break expr;` copies the value into the output register of the loop before jumping out.
+     */
+    fn build_break(
+        &mut self,
+        label: Option<&str>,
+        value: Option<AstExpr>,
+    ) -> Result<Vec<Operation>, Error> {
+        let target = self.loop_context(label, "break out")?;
+
+        let mut ops = vec![];
+        if let Some(value) = value {
+            let (value_reg, mut value_ops) = self.build_expr(value)?;
+            ops.append(&mut value_ops);
+            ops.push(Operation::I2i {
+                lhs: value_reg,
+                rhs: target.out,
+            });
+        }
+        ops.append(&mut self.pop_handlers(&target));
+        ops.push(Operation::JumpI(target.end_label));
+
+        Ok(ops)
+    }
+
+    fn build_loop(&mut self, label: Option<&str>, block: AstBlock) -> Result<OutRegAndOps, Error> {
         let loop_start_label = self.next_free_label();
         let loop_end_label = self.next_free_label();
 
-        self.break_context_ends.push(loop_end_label.clone());
+        self.build_loop_parts(
+            label,
+            loop_start_label,
+            loop_end_label,
+            vec![],
+            block,
+            vec![],
+        )
+    }
+
+    /**
+     * `head` runs before each iteration and may jump to `loop_end_label` to stop, `step` runs
+     * after each iteration, including those ended by `continue`. The loop evaluates to null
+     * unless a `break` sets a value.
+     */
+    fn build_loop_parts(
+        &mut self,
+        label: Option<&str>,
+        loop_start_label: Label,
+        loop_end_label: Label,
+        mut head: Vec<Operation>,
+        block: AstBlock,
+        mut step: Vec<Operation>,
+    ) -> Result<OutRegAndOps, Error> {
+        let out = self.next_free_reg_addr();
+        let step_label = (!step.is_empty()).then(|| self.next_free_label());
+        self.loop_contexts.push(LoopContext {
+            label: label.map(String::from),
+            try_depth: self.try_depth,
+            continue_label: step_label
+                .clone()
+                .unwrap_or_else(|| loop_start_label.clone()),
+            end_label: loop_end_label.clone(),
+            out,
+        });
 
         let mut ops = vec![];
+        ops.push(Operation::LoadNull { out });
         ops.push(Operation::Label(loop_start_label.clone()));
+        ops.append(&mut head);
 
-        let (_, mut block_ops) = self.build_block(block)?;
+        let block_result = self.build_block(block);
+        self.loop_contexts.pop().expect("Missing loop");
+        let (_, mut block_ops) = block_result?;
         ops.append(&mut block_ops);
+        if let Some(step_label) = step_label {
+            ops.push(Operation::Label(step_label));
+        }
+        ops.append(&mut step);
         ops.push(Operation::JumpI(loop_start_label));
         ops.push(Operation::Label(loop_end_label));
 
-        self.break_context_ends.pop().expect("Missing label");
+        Ok((out, ops))
+    }
+
+    /**
+     * Ranges are lowered to a counter loop, without allocating the range. The counter is kept
+     * apart from the loop variable, so assigning to the variable does not change the iteration.
+     */
+    fn build_for(
+        &mut self,
+        label: Option<&str>,
+        varname: &str,
+        iter: AstExpr,
+        block: AstBlock,
+    ) -> Result<OutRegAndOps, Error> {
+        let (from, to, inclusive) = match iter {
+            AstExpr::Range {
+                from,
+                to,
+                inclusive,
+            } => (*from, *to, inclusive),
+            _ => return Err("Only ranges can be iterated in IR".into()),
+        };
+        self.check_not_constant(varname)?;
 
-        Ok(ops)
+        let (from_reg, mut ops) = self.build_expr(from)?;
+        let (to_reg, mut to_ops) = self.build_expr(to)?;
+        ops.append(&mut to_ops);
+
+        let counter = self.next_free_reg_addr();
+        ops.push(Operation::I2i {
+            lhs: from_reg,
+            rhs: counter,
+        });
+        let end = self.next_free_reg_addr();
+        ops.push(Operation::I2i {
+            lhs: to_reg,
+            rhs: end,
+        });
+
+        let loop_start_label = self.next_free_label();
+        let loop_end_label = self.next_free_label();
+        let loop_body_label = self.next_free_label();
+
+        let var_reg = self.get_variable_reg_addr(varname);
+        let in_range = self.next_free_reg_addr();
+        let head = vec![
+            if inclusive {
+                Operation::CmpLte {
+                    lhs: counter,
+                    rhs: end,
+                    out: in_range,
+                }
+            } else {
+                Operation::CmpLt {
+                    lhs: counter,
+                    rhs: end,
+                    out: in_range,
+                }
+            },
+            Operation::CondBranch {
+                cond: in_range,
+                label_true: loop_body_label.clone(),
+                label_false: loop_end_label.clone(),
+            },
+            Operation::Label(loop_body_label),
+            Operation::I2i {
+                lhs: counter,
+                rhs: var_reg,
+            },
+        ];
+        let step = vec![Operation::AddI {
+            lhs: counter,
+            rhs: 1,
+            out: counter,
+        }];
+
+        let (out, mut loop_ops) =
+            self.build_loop_parts(label, loop_start_label, loop_end_label, head, block, step)?;
+        ops.append(&mut loop_ops);
+
+        Ok((out, ops))
     }
 
     fn build_expr(&mut self, expr: AstExpr) -> Result<OutRegAndOps, Error> {
         match expr {
-            AstExpr::FnCall { name, args } => self.build_expr_fn_call(name, args),
-            AstExpr::Str(s) => unimplemented!(),
+            AstExpr::FnCall {
+                module,
+                name,
+                args,
+                named_args,
+            } => self.build_expr_fn_call(module, name, args, named_args),
+            AstExpr::Str(_) | AstExpr::StrTemplate(_) => {
+                Err("Strings are not supported in IR".into())
+            }
             AstExpr::Int(i) => self.build_expr_int(i),
+            AstExpr::Float(v) => self.build_expr_float(v),
             AstExpr::Name(name) => self.build_expr_name(name),
             AstExpr::Boolean(b) => self.build_expr_bool(b),
-            AstExpr::Assignment { varname, expr } => self.build_expr_assignment(varname, *expr),
-            AstExpr::BinOp { lhs, op, rhs } => self.build_expr_binop(*lhs, op, *rhs),
+            AstExpr::Null => self.build_expr_null(),
+            AstExpr::Assignment { varname, expr, .. } => self.build_expr_assignment(varname, *expr),
+            AstExpr::OpAssignment {
+                varname,
+                op,
+                expr,
+                loc,
+            } => self.build_expr_op_assignment(varname, op, *expr, loc),
+            AstExpr::BinOp {
+                lhs,
+                op: Op::Coalesce,
+                rhs,
+                ..
+            } => self.build_expr_coalesce(*lhs, *rhs),
+            AstExpr::BinOp { lhs, op, rhs, loc } => self.build_expr_binop(*lhs, op, *rhs, loc),
+            AstExpr::BitNot(expr) => self.build_expr_bit_not(*expr),
+            AstExpr::Range { .. } => Err("Ranges are only supported in for loops in IR".into()),
+            AstExpr::Loop { label, block } => self.build_loop(label, block),
+            AstExpr::For {
+                label,
+                varname,
+                iter,
+                block,
+            } => self.build_for(label, varname, *iter, block),
             AstExpr::If {
                 cond,
                 true_block,
                 false_block,
             } => self.build_expr_if(*cond, true_block, false_block),
+            AstExpr::Block(block) => self.build_expr_block(block),
+            AstExpr::Try {
+                block,
+                catch_var,
+                catch_block,
+            } => self.build_expr_try(block, catch_var, catch_block),
             AstExpr::ParenExpr(expr) => self.build_expr(*expr),
+            AstExpr::Tuple(_) => {
+                Err("Tuples are only supported as return values and in destructuring in IR".into())
+            }
+            AstExpr::TupleAssignment { varnames, expr } => {
+                self.build_expr_tuple_assignment(varnames, *expr)
+            }
+            AstExpr::Array(items) => self.build_expr_array(items),
+            AstExpr::Map(_) => Err("Maps are not supported in IR".into()),
+            AstExpr::Index { expr, index } => self.build_expr_index(*expr, *index),
+            AstExpr::IndexAssignment { expr, index, value } => {
+                self.build_expr_index_assignment(*expr, *index, *value)
+            }
+            AstExpr::StructInit { name, fields } => self.build_expr_struct_init(name, fields),
+            AstExpr::Field { expr, field } => {
+                let (record, mut ops) = self.build_expr(*expr)?;
+                let out = self.next_free_reg_addr();
+                ops.append(&mut self.build_field_access(record, field, |offs| {
+                    Operation::LoadAI {
+                        addr: record,
+                        offs,
+                        out,
+                    }
+                })?);
+                Ok((out, ops))
+            }
+            AstExpr::FieldAssignment { expr, field, value } => {
+                let (record, mut ops) = self.build_expr(*expr)?;
+                let (value, mut value_ops) = self.build_expr(*value)?;
+                ops.append(&mut value_ops);
+                ops.append(&mut self.build_field_access(record, field, |offs| {
+                    Operation::StoreAI {
+                        reg: value,
+                        addr: record,
+                        offs,
+                    }
+                })?);
+                Ok((value, ops))
+            }
+            AstExpr::EnumVariant {
+                enum_name,
+                variant,
+                args,
+            } => self.build_expr_enum_variant(enum_name, variant, args),
+            AstExpr::Match { expr, arms } => self.build_expr_match(*expr, arms),
         }
     }
 
-    fn build_expr_if(
+    /**
+     * Enum values are a block starting with the variant index (tag), followed by the payload.
+     */
+    fn build_expr_enum_variant(
         &mut self,
-        cond: AstExpr,
-        true_block: AstBlock,
-        false_block: Option<AstBlock>,
+        enum_name: &str,
+        variant: &str,
+        args: Vec<AstExpr>,
     ) -> Result<OutRegAndOps, Error> {
-        let out = self.next_free_reg_addr();
-        let mut ops = vec![];
+        let (tag, size) = self.variant_tag(enum_name, variant)?;
+        if size != args.len() {
+            return Err(format!(
+                "Variant {}::{} expects {} values, got {}",
+                enum_name,
+                variant,
+                size,
+                args.len()
+            )
+            .into());
+        }
 
-        let (cond_out, mut cond_ops) = self.build_expr(cond)?;
-        ops.append(&mut cond_ops);
+        let mut ops = vec![];
 
-        let label_true = self.next_free_label();
-        let label_end = self.next_free_label();
-        let label_false = self.next_free_label();
+        let mut arg_regs = vec![];
+        for arg in args {
+            let (arg_reg, mut arg_ops) = self.build_expr(arg)?;
+            ops.append(&mut arg_ops);
+            arg_regs.push(arg_reg);
+        }
 
-        ops.push(Operation::CondBranch {
-            cond: cond_out,
-            label_true: label_true.clone(),
-            label_false: label_false.clone(),
+        let block_size = self.next_free_reg_addr();
+        ops.push(Operation::LoadI {
+            val: 1 + size as ImmVal,
+            out: block_size,
+        });
+        let out = self.next_free_reg_addr();
+        ops.push(Operation::Alloc {
+            size: block_size,
+            out,
         });
 
-        let (true_out, mut true_ops) = self.build_block(true_block)?;
-        ops.push(Operation::Label(label_true));
-        ops.append(&mut true_ops);
-        // Saving return value to reg - or set it to 0 for now.
-        if let Some(true_out) = true_out {
-            ops.push(Operation::I2i {
-                lhs: true_out,
-                rhs: out,
+        let tag_reg = self.next_free_reg_addr();
+        ops.push(Operation::LoadI {
+            val: tag,
+            out: tag_reg,
+        });
+        ops.push(Operation::Store {
+            reg: tag_reg,
+            addr: out,
+        });
+        for (i, arg_reg) in arg_regs.into_iter().enumerate() {
+            ops.push(Operation::StoreAI {
+                reg: arg_reg,
+                addr: out,
+                offs: 1 + i as ImmVal,
             });
-        } else {
-            ops.push(Operation::LoadI { val: 0, out });
         }
-        ops.push(Operation::JumpI(label_end.clone()));
 
-        ops.push(Operation::Label(label_false));
+        Ok((out, ops))
+    }
 
-        let false_out = if let Some(false_block) = false_block {
-            let (false_out, mut false_ops) = self.build_block(false_block)?;
-            ops.append(&mut false_ops);
-            false_out
-        } else {
-            None
-        };
+    fn variant_tag(&self, enum_name: &str, variant: &str) -> Result<(ImmVal, usize), Error> {
+        self.enums
+            .get(enum_name)
+            .ok_or(format!("Missing enum '{}'", enum_name))?
+            .iter()
+            .enumerate()
+            .find(|(_, (name, _))| name == variant)
+            .map(|(tag, (_, size))| (tag as ImmVal, *size))
+            .ok_or(format!("Enum '{}' has no variant '{}'", enum_name, variant).into())
+    }
 
-        if let Some(false_out) = false_out {
-            ops.push(Operation::I2i {
-                lhs: false_out,
-                rhs: out,
-            });
+    /**
+     * Enum matches dispatch on the tag through a jump table of arm jumps, literal matches
+     * compare the arms one after the other.
+     */
+    fn build_expr_match(
+        &mut self,
+        expr: AstExpr,
+        arms: Vec<AstMatchArm>,
+    ) -> Result<OutRegAndOps, Error> {
+        let (value, mut ops) = self.build_expr(expr)?;
+        let out = self.next_free_reg_addr();
+
+        let label_end = self.next_free_label();
+        let arm_labels = arms
+            .iter()
+            .map(|_| self.next_free_label())
+            .collect::<Vec<Label>>();
+
+        let enum_name = arms.iter().find_map(|arm| match &arm.pattern {
+            AstPattern::Variant { enum_name, .. } => Some(enum_name.to_string()),
+            _ => None,
+        });
+
+        if let Some(enum_name) = enum_name {
+            let mut dispatch_ops =
+                self.build_match_jump_table(value, &enum_name, &arms, &arm_labels)?;
+            ops.append(&mut dispatch_ops);
         } else {
-            ops.push(Operation::LoadI { val: 0, out });
+            let mut dispatch_ops = self.build_match_compare_chain(value, &arms, &arm_labels)?;
+            ops.append(&mut dispatch_ops);
+        }
+
+        for (arm, arm_label) in arms.into_iter().zip(arm_labels) {
+            ops.push(Operation::Label(arm_label));
+
+            match arm.pattern {
+                AstPattern::Binding(name) => {
+                    self.check_not_constant(name)?;
+                    let var_reg = self.get_variable_reg_addr(name);
+                    ops.push(Operation::I2i {
+                        lhs: value,
+                        rhs: var_reg,
+                    });
+                }
+                AstPattern::Variant { bindings, .. } => {
+                    for (i, name) in bindings.into_iter().enumerate() {
+                        if name == "_" {
+                            continue;
+                        }
+                        self.check_not_constant(name)?;
+                        let var_reg = self.get_variable_reg_addr(name);
+                        ops.push(Operation::LoadAI {
+                            addr: value,
+                            offs: 1 + i as ImmVal,
+                            out: var_reg,
+                        });
+                    }
+                }
+                _ => {}
+            };
+
+            let (arm_out, mut arm_ops) = self.build_block(arm.block)?;
+            ops.append(&mut arm_ops);
+            if let Some(arm_out) = arm_out {
+                ops.push(Operation::I2i {
+                    lhs: arm_out,
+                    rhs: out,
+                });
+            } else {
+                ops.push(Operation::LoadNull { out });
+            }
+            ops.push(Operation::JumpI(label_end.clone()));
         }
-        ops.push(Operation::JumpI(label_end.clone()));
 
         ops.push(Operation::Label(label_end));
 
         Ok((out, ops))
     }
 
-    fn build_expr_fn_call(
+    fn build_match_jump_table(
         &mut self,
-        name: &str,
-        args: Vec<AstExpr>,
-    ) -> Result<OutRegAndOps, Error> {
+        value: Reg,
+        enum_name: &str,
+        arms: &[AstMatchArm],
+        arm_labels: &[Label],
+    ) -> Result<Vec<Operation>, Error> {
         let mut ops = vec![];
 
-        // let mut op_lists = vec![];
-        let mut op_returns = vec![];
-        for arg_expr in args {
-            let (arg_expr_reg, mut arg_expr_ops) = self.build_expr(arg_expr)?;
-            op_returns.push(arg_expr_reg);
-            // op_lists.push(arg_expr_ops);
+        let variants = self.enums[enum_name].clone();
+
+        // Each variant jumps to the first arm accepting it. Exhaustiveness is checked already.
+        let mut arm_jumps = vec![];
+        for (variant, _) in &variants {
+            let arm_pos = arms
+                .iter()
+                .position(|arm| match &arm.pattern {
+                    AstPattern::Variant {
+                        variant: pattern_variant,
+                        ..
+                    } => pattern_variant == variant,
+                    AstPattern::Wildcard | AstPattern::Binding(_) => true,
+                    _ => false,
+                })
+                .ok_or(format!("No match arm for {}::{}", enum_name, variant))?;
+            arm_jumps.push(Operation::JumpI(arm_labels[arm_pos].clone()));
+        }
 
-            ops.append(&mut arg_expr_ops);
+        // The table is a run of jumps placed in the code, so it is built once per match site: the
+        // jump to `table + tag` lands on the jump of the variant, as jumps resume after the target.
+        let tag = self.next_free_reg_addr();
+        ops.push(Operation::Load {
+            addr: value,
+            out: tag,
+        });
+        let table_label = self.next_free_label();
+        let target = self.next_free_reg_addr();
+        ops.push(Operation::Tbl {
+            reg: target,
+            label: table_label.clone(),
+        });
+        ops.push(Operation::Add {
+            lhs: target,
+            rhs: tag,
+            out: target,
+        });
+        ops.push(Operation::Jump(target));
+        ops.push(Operation::Label(table_label));
+        ops.append(&mut arm_jumps);
+
+        Ok(ops)
+    }
+
+    fn build_match_compare_chain(
+        &mut self,
+        value: Reg,
+        arms: &[AstMatchArm],
+        arm_labels: &[Label],
+    ) -> Result<Vec<Operation>, Error> {
+        let mut ops = vec![];
+
+        for (arm, arm_label) in arms.iter().zip(arm_labels) {
+            let literal = match arm.pattern {
+                AstPattern::Wildcard | AstPattern::Binding(_) => {
+                    ops.push(Operation::JumpI(arm_label.clone()));
+                    return Ok(ops);
+                }
+                AstPattern::Int(v) => v,
+                AstPattern::Boolean(b) => {
+                    if b {
+                        1
+                    } else {
+                        0
+                    }
+                }
+                AstPattern::Str(_) => return Err("Strings are not supported in IR".into()),
+                AstPattern::Variant { .. } => unreachable!("Variant in literal match"),
+            };
+
+            let literal_reg = self.next_free_reg_addr();
+            ops.push(Operation::LoadI {
+                val: literal,
+                out: literal_reg,
+            });
+            let is_eq = self.next_free_reg_addr();
+            ops.push(Operation::CmpEq {
+                lhs: value,
+                rhs: literal_reg,
+                out: is_eq,
+            });
+
+            let label_next = self.next_free_label();
+            ops.push(Operation::CondBranch {
+                cond: is_eq,
+                label_true: arm_label.clone(),
+                label_false: label_next.clone(),
+            });
+            ops.push(Operation::Label(label_next));
         }
 
-        // Reverse order - so `POP` inside the proceduce can read them in order.
-        // TODO: Add verification that we push exactly as much as the arg count. This we could do if
-        //       save some info about the function.
-        while let Some(op_return) = op_returns.pop() {
-            ops.push(Operation::Push(op_return));
+        ops.push(Operation::Abort {
+            msg: "No match arm".into(),
+            args: vec![],
+        });
+
+        Ok(ops)
+    }
+
+    /**
+     * Records are the struct tag followed by the fields in declaration order, addressed with the
+     * field index (after the tag) as offset.
+     */
+    fn build_expr_struct_init(
+        &mut self,
+        name: &str,
+        fields: Vec<(&str, AstExpr)>,
+    ) -> Result<OutRegAndOps, Error> {
+        let layout = self
+            .structs
+            .get(name)
+            .ok_or(format!("Missing struct '{}'", name))?;
+        let tag = layout.tag;
+        let declared_fields = layout.fields.clone();
+
+        if fields.len() != declared_fields.len() {
+            return Err(format!(
+                "Struct '{}' expects {} fields, got {}",
+                name,
+                declared_fields.len(),
+                fields.len()
+            )
+            .into());
         }
 
-        // When executing `call` the return adds could automatically saved by the VM.
+        let mut ops = vec![];
 
-        ops.push(Operation::Call(Label::Named(name.into())));
+        let mut field_regs = vec![];
+        for (field, expr) in fields {
+            let offs = declared_fields
+                .iter()
+                .position(|declared| declared == field)
+                .ok_or(format!("Struct '{}' has no field '{}'", name, field))?;
+            let (field_reg, mut field_ops) = self.build_expr(expr)?;
+            ops.append(&mut field_ops);
+            field_regs.push((field_reg, offs));
+        }
+        // A repeated field leaves another one out.
+        for (offs, field) in declared_fields.iter().enumerate() {
+            if !field_regs.iter().any(|(_, other)| *other == offs) {
+                return Err(format!("Missing field '{}' for struct '{}'", field, name).into());
+            }
+        }
 
+        let size = self.next_free_reg_addr();
+        ops.push(Operation::LoadI {
+            val: RECORD_HEADER_SIZE + declared_fields.len() as ImmVal,
+            out: size,
+        });
         let out = self.next_free_reg_addr();
-        ops.push(Operation::Pop(out));
+        ops.push(Operation::Alloc { size, out });
+
+        let tag_reg = self.next_free_reg_addr();
+        ops.push(Operation::LoadI {
+            val: tag,
+            out: tag_reg,
+        });
+        ops.push(Operation::StoreAI {
+            reg: tag_reg,
+            addr: out,
+            offs: RECORD_TAG_OFFS,
+        });
+
+        for (field_reg, offs) in field_regs {
+            ops.push(Operation::StoreAI {
+                reg: field_reg,
+                addr: out,
+                offs: RECORD_HEADER_SIZE + offs as ImmVal,
+            });
+        }
 
         Ok((out, ops))
     }
 
-    fn build_expr_assignment(
+    /**
+     * The IR is untyped, so the offset of a field is known up front only when it is the same in
+     * every struct declaring it. Otherwise the record's tag selects the offset at runtime.
+     */
+    fn build_field_access(
         &mut self,
-        varname: &str,
-        expr: AstExpr,
-    ) -> Result<OutRegAndOps, Error> {
-        let (expr_reg, mut expr_ops) = self.build_expr(expr)?;
-        let out = self.get_variable_reg_addr(varname);
+        record: Reg,
+        field: &str,
+        access: impl Fn(ImmVal) -> Operation,
+    ) -> Result<Vec<Operation>, Error> {
+        let mut offsets: Vec<(ImmVal, ImmVal)> = self
+            .structs
+            .values()
+            .filter_map(|layout| {
+                let offs = layout
+                    .fields
+                    .iter()
+                    .position(|declared| declared == field)?;
+                Some((layout.tag, RECORD_HEADER_SIZE + offs as ImmVal))
+            })
+            .collect();
+        offsets.sort();
+
+        let (_, offs) = *offsets
+            .first()
+            .ok_or(format!("Unknown field '{}'", field))?;
+        if offsets.iter().all(|(_, other)| *other == offs) {
+            return Ok(vec![access(offs)]);
+        }
+
         let mut ops = vec![];
-        ops.append(&mut expr_ops);
+        let tag = self.next_free_reg_addr();
+        ops.push(Operation::LoadAI {
+            addr: record,
+            offs: RECORD_TAG_OFFS,
+            out: tag,
+        });
+        let label_end = self.next_free_label();
+        for (struct_tag, offs) in offsets {
+            let struct_tag_reg = self.next_free_reg_addr();
+            let is_struct = self.next_free_reg_addr();
+            let label_struct = self.next_free_label();
+            let label_next = self.next_free_label();
+            ops.push(Operation::LoadI {
+                val: struct_tag,
+                out: struct_tag_reg,
+            });
+            ops.push(Operation::CmpEq {
+                lhs: tag,
+                rhs: struct_tag_reg,
+                out: is_struct,
+            });
+            ops.push(Operation::CondBranch {
+                cond: is_struct,
+                label_true: label_struct.clone(),
+                label_false: label_next.clone(),
+            });
+            ops.push(Operation::Label(label_struct));
+            ops.push(access(offs));
+            ops.push(Operation::JumpI(label_end.clone()));
+            ops.push(Operation::Label(label_next));
+        }
+        ops.push(Operation::Abort {
+            msg: format!("Record has no field '{}'", field),
+            args: vec![],
+        });
+        ops.push(Operation::Label(label_end));
 
-        ops.push(Operation::I2i {
-            lhs: expr_reg,
-            rhs: out,
+        Ok(ops)
+    }
+
+    /**
+     * Pops the count pushed by the caller, then that many values into a new array.
+     */
+    fn build_variadic_arg(&mut self, out: Reg) -> Vec<Operation> {
+        let mut ops = vec![];
+
+        let len = self.next_free_reg_addr();
+        ops.push(Operation::Pop(len));
+
+        let header_size = self.next_free_reg_addr();
+        ops.push(Operation::LoadI {
+            val: ARRAY_HEADER_SIZE,
+            out: header_size,
         });
+        ops.push(Operation::Alloc {
+            size: header_size,
+            out,
+        });
+        let elements = self.next_free_reg_addr();
+        ops.push(Operation::Alloc {
+            size: len,
+            out: elements,
+        });
+        ops.push(Operation::Store {
+            reg: len,
+            addr: out,
+        });
+        ops.push(Operation::StoreAI {
+            reg: len,
+            addr: out,
+            offs: ARRAY_CAPACITY_OFFS,
+        });
+        ops.push(Operation::StoreAI {
+            reg: elements,
+            addr: out,
+            offs: ARRAY_ELEMENTS_OFFS,
+        });
+
+        let index = self.next_free_reg_addr();
+        ops.push(Operation::LoadI { val: 0, out: index });
+
+        let label_cond = self.next_free_label();
+        let label_body = self.next_free_label();
+        let label_end = self.next_free_label();
+
+        ops.push(Operation::Label(label_cond.clone()));
+        let is_done = self.next_free_reg_addr();
+        ops.push(Operation::CmpGte {
+            lhs: index,
+            rhs: len,
+            out: is_done,
+        });
+        ops.push(Operation::CondBranch {
+            cond: is_done,
+            label_true: label_end.clone(),
+            label_false: label_body.clone(),
+        });
+
+        ops.push(Operation::Label(label_body));
+        let value = self.next_free_reg_addr();
+        ops.push(Operation::Pop(value));
+        ops.push(Operation::StoreAO {
+            reg: value,
+            addr: elements,
+            offs: index,
+        });
+        ops.push(Operation::AddI {
+            lhs: index,
+            rhs: 1,
+            out: index,
+        });
+        ops.push(Operation::JumpI(label_cond));
+
+        ops.push(Operation::Label(label_end));
+
+        ops
+    }
+
+    fn build_expr_array(&mut self, items: Vec<AstExpr>) -> Result<OutRegAndOps, Error> {
+        let mut ops = vec![];
+
+        let mut item_regs = vec![];
+        for item in items {
+            let (item_reg, mut item_ops) = self.build_expr(item)?;
+            ops.append(&mut item_ops);
+            item_regs.push(item_reg);
+        }
+
+        let header_size = self.next_free_reg_addr();
+        ops.push(Operation::LoadI {
+            val: ARRAY_HEADER_SIZE,
+            out: header_size,
+        });
+        let out = self.next_free_reg_addr();
+        ops.push(Operation::Alloc {
+            size: header_size,
+            out,
+        });
+
+        let len = self.next_free_reg_addr();
+        ops.push(Operation::LoadI {
+            val: item_regs.len() as ImmVal,
+            out: len,
+        });
+        let elements = self.next_free_reg_addr();
+        ops.push(Operation::Alloc {
+            size: len,
+            out: elements,
+        });
+
+        ops.push(Operation::Store {
+            reg: len,
+            addr: out,
+        });
+        ops.push(Operation::StoreAI {
+            reg: len,
+            addr: out,
+            offs: ARRAY_CAPACITY_OFFS,
+        });
+        ops.push(Operation::StoreAI {
+            reg: elements,
+            addr: out,
+            offs: ARRAY_ELEMENTS_OFFS,
+        });
+
+        for (i, item_reg) in item_regs.into_iter().enumerate() {
+            let offs = self.next_free_reg_addr();
+            ops.push(Operation::LoadI {
+                val: i as ImmVal,
+                out: offs,
+            });
+            ops.push(Operation::StoreAO {
+                reg: item_reg,
+                addr: elements,
+                offs,
+            });
+        }
+
+        Ok((out, ops))
+    }
+
+    fn build_expr_index(&mut self, expr: AstExpr, index: AstExpr) -> Result<OutRegAndOps, Error> {
+        let (array, mut ops) = self.build_expr(expr)?;
+        let (index, mut index_ops) = self.build_expr(index)?;
+        ops.append(&mut index_ops);
+
+        let (elements, mut elements_ops) = self.build_array_elements_checked(array, index);
+        ops.append(&mut elements_ops);
+
+        let out = self.next_free_reg_addr();
+        ops.push(Operation::LoadAO {
+            addr: elements,
+            offs: index,
+            out,
+        });
+
+        Ok((out, ops))
+    }
+
+    fn build_expr_index_assignment(
+        &mut self,
+        expr: AstExpr,
+        index: AstExpr,
+        value: AstExpr,
+    ) -> Result<OutRegAndOps, Error> {
+        let (array, mut ops) = self.build_expr(expr)?;
+        let (index, mut index_ops) = self.build_expr(index)?;
+        ops.append(&mut index_ops);
+        let (value, mut value_ops) = self.build_expr(value)?;
+        ops.append(&mut value_ops);
+
+        let (elements, mut elements_ops) = self.build_array_elements_checked(array, index);
+        ops.append(&mut elements_ops);
+
+        ops.push(Operation::StoreAO {
+            reg: value,
+            addr: elements,
+            offs: index,
+        });
+
+        Ok((value, ops))
+    }
+
+    /**
+     * Verifies `0 <= index < len(array)` and loads the address of the array elements.
+     */
+    fn build_array_elements_checked(&mut self, array: Reg, index: Reg) -> OutRegAndOps {
+        let mut ops = vec![];
+
+        let len = self.next_free_reg_addr();
+        ops.push(Operation::Load {
+            addr: array,
+            out: len,
+        });
+
+        let zero = self.next_free_reg_addr();
+        ops.push(Operation::LoadI { val: 0, out: zero });
+
+        let label_lower_ok = self.next_free_label();
+        let label_ok = self.next_free_label();
+        let label_fail = self.next_free_label();
+
+        let lower_ok = self.next_free_reg_addr();
+        ops.push(Operation::CmpGte {
+            lhs: index,
+            rhs: zero,
+            out: lower_ok,
+        });
+        ops.push(Operation::CondBranch {
+            cond: lower_ok,
+            label_true: label_lower_ok.clone(),
+            label_false: label_fail.clone(),
+        });
+        ops.push(Operation::Label(label_lower_ok));
+
+        let upper_ok = self.next_free_reg_addr();
+        ops.push(Operation::CmpLt {
+            lhs: index,
+            rhs: len,
+            out: upper_ok,
+        });
+        ops.push(Operation::CondBranch {
+            cond: upper_ok,
+            label_true: label_ok.clone(),
+            label_false: label_fail.clone(),
+        });
+
+        ops.push(Operation::Label(label_fail));
+        ops.push(Operation::Abort {
+            msg: "Index out of bounds: {} (length {})".into(),
+            args: vec![index, len],
+        });
+        ops.push(Operation::Label(label_ok));
+
+        let elements = self.next_free_reg_addr();
+        ops.push(Operation::LoadAI {
+            addr: array,
+            offs: ARRAY_ELEMENTS_OFFS,
+            out: elements,
+        });
+
+        (elements, ops)
+    }
+
+    /**
+     * Variables first assigned in the block go out of scope at its end, their registers are
+     * not reused.
+     */
+    fn build_expr_block(&mut self, block: AstBlock) -> Result<OutRegAndOps, Error> {
+        let outer: HashSet<String> = self
+            .frames
+            .last()
+            .ok_or("No scope for block")?
+            .variables
+            .keys()
+            .cloned()
+            .collect();
+
+        let block_result = self.build_block(block);
+        self.frames
+            .last_mut()
+            .ok_or("No scope for block")?
+            .variables
+            .retain(|name, _| outer.contains(name));
+        let (block_out, mut ops) = block_result?;
+
+        let out = match block_out {
+            Some(block_out) => block_out,
+            None => {
+                let out = self.next_free_reg_addr();
+                ops.push(Operation::LoadNull { out });
+                out
+            }
+        };
+        Ok((out, ops))
+    }
+
+    fn build_expr_try(
+        &mut self,
+        block: AstBlock,
+        catch_var: &str,
+        catch_block: AstBlock,
+    ) -> Result<OutRegAndOps, Error> {
+        self.check_not_constant(catch_var)?;
+        let out = self.next_free_reg_addr();
+        let catch_reg = self.get_variable_reg_addr(catch_var);
+        let label_catch = self.next_free_label();
+        let label_end = self.next_free_label();
+
+        let mut ops = vec![Operation::PushHandler {
+            label: label_catch.clone(),
+            reg: catch_reg,
+        }];
+
+        self.try_depth += 1;
+        let block_result = self.build_block(block);
+        self.try_depth -= 1;
+        let (block_out, mut block_ops) = block_result?;
+        ops.append(&mut block_ops);
+        ops.push(match block_out {
+            Some(block_out) => Operation::I2i {
+                lhs: block_out,
+                rhs: out,
+            },
+            None => Operation::LoadNull { out },
+        });
+        ops.push(Operation::PopHandler);
+        ops.push(Operation::JumpI(label_end.clone()));
+
+        ops.push(Operation::Label(label_catch));
+        let (catch_out, mut catch_ops) = self.build_block(catch_block)?;
+        ops.append(&mut catch_ops);
+        ops.push(match catch_out {
+            Some(catch_out) => Operation::I2i {
+                lhs: catch_out,
+                rhs: out,
+            },
+            None => Operation::LoadNull { out },
+        });
+        ops.push(Operation::Label(label_end));
+
+        Ok((out, ops))
+    }
+
+    fn build_expr_if(
+        &mut self,
+        cond: AstExpr,
+        true_block: AstBlock,
+        false_block: Option<AstBlock>,
+    ) -> Result<OutRegAndOps, Error> {
+        let out = self.next_free_reg_addr();
+        let mut ops = vec![];
+
+        let (cond_out, mut cond_ops) = self.build_expr(cond)?;
+        ops.append(&mut cond_ops);
+
+        let label_true = self.next_free_label();
+        let label_end = self.next_free_label();
+        let label_false = self.next_free_label();
+
+        ops.push(Operation::CondBranch {
+            cond: cond_out,
+            label_true: label_true.clone(),
+            label_false: label_false.clone(),
+        });
+
+        let (true_out, mut true_ops) = self.build_block(true_block)?;
+        ops.push(Operation::Label(label_true));
+        ops.append(&mut true_ops);
+        // Saving return value to reg - or null without one.
+        if let Some(true_out) = true_out {
+            ops.push(Operation::I2i {
+                lhs: true_out,
+                rhs: out,
+            });
+        } else {
+            ops.push(Operation::LoadNull { out });
+        }
+        ops.push(Operation::JumpI(label_end.clone()));
+
+        ops.push(Operation::Label(label_false));
+
+        let false_out = if let Some(false_block) = false_block {
+            let (false_out, mut false_ops) = self.build_block(false_block)?;
+            ops.append(&mut false_ops);
+            false_out
+        } else {
+            None
+        };
+
+        if let Some(false_out) = false_out {
+            ops.push(Operation::I2i {
+                lhs: false_out,
+                rhs: out,
+            });
+        } else {
+            ops.push(Operation::LoadNull { out });
+        }
+        ops.push(Operation::JumpI(label_end.clone()));
+
+        ops.push(Operation::Label(label_end));
+
+        Ok((out, ops))
+    }
+
+    fn build_expr_fn_call(
+        &mut self,
+        module: Option<&str>,
+        name: &str,
+        args: Vec<AstExpr>,
+        named_args: AstNamedArgs,
+    ) -> Result<OutRegAndOps, Error> {
+        if module.is_none()
+            && matches!(
+                name,
+                "len" | "push" | "pop" | "sqrt" | "floor" | "round" | "is_null"
+            )
+        {
+            if !named_args.is_empty() {
+                return Err(format!("Function '{}' does not take named arguments", name).into());
+            }
+            match name {
+                "len" => return self.build_expr_fn_call_len(args),
+                "push" => return self.build_expr_fn_call_push(args),
+                "pop" => return self.build_expr_fn_call_pop(args),
+                "is_null" => return self.build_expr_fn_call_is_null(args),
+                _ => return self.build_expr_fn_call_math(name, args),
+            };
+        }
+
+        let (fn_label, mut ops) = self.build_call(module, name, args, named_args)?;
+        if let Some(count) = self.tuple_returns.get(&fn_label) {
+            return Err(format!(
+                "Function '{}' returns {} values, which must be destructured in IR",
+                name, count
+            )
+            .into());
+        }
+
+        let out = self.next_free_reg_addr();
+        ops.push(Operation::Pop(out));
+
+        Ok((out, ops))
+    }
+
+    /**
+     * Pushes the arguments and calls the function, leaving the return value(s) on the stack.
+     */
+    fn build_call(
+        &mut self,
+        module: Option<&str>,
+        name: &str,
+        args: Vec<AstExpr>,
+        named_args: AstNamedArgs,
+    ) -> Result<(Label, Vec<Operation>), Error> {
+        let fn_label = self.resolve_fn_label(module, name)?;
+        let params = self
+            .fn_params
+            .get(&fn_label)
+            .ok_or(format!("Missing function '{}'", name))?;
+        // Resolving against the parameters makes us push exactly as much as the callee pops.
+        let call_args = resolve_call_args(name, params, args, named_args)?;
+
+        let mut ops = vec![];
+
+        let mut pushes = vec![];
+        for call_arg in call_args {
+            let (arg_expr_reg, mut arg_expr_ops) = match call_arg {
+                CallArg::Given(arg_expr) => self.build_expr(arg_expr)?,
+                CallArg::Default(value) => self.build_const_value(&value)?,
+                CallArg::Rest(arg_exprs) => {
+                    // A variadic callee pops the count first, then that many values.
+                    pushes.push(Operation::PushI(arg_exprs.len() as i64));
+                    for arg_expr in arg_exprs {
+                        let (arg_expr_reg, mut arg_expr_ops) = self.build_expr(arg_expr)?;
+                        pushes.push(Operation::Push(arg_expr_reg));
+                        ops.append(&mut arg_expr_ops);
+                    }
+                    continue;
+                }
+            };
+            pushes.push(Operation::Push(arg_expr_reg));
+
+            ops.append(&mut arg_expr_ops);
+        }
+
+        // Reverse order - so `POP` inside the proceduce can read them in order.
+        ops.extend(pushes.into_iter().rev());
+
+        // When executing `call` the return adds could automatically saved by the VM.
+
+        ops.push(Operation::Call(fn_label.clone()));
+
+        Ok((fn_label, ops))
+    }
+
+    /**
+     * Destructures tuple literals, and the values pushed by functions returning tuples. The
+     * result is the first item.
+     */
+    fn build_expr_tuple_assignment(
+        &mut self,
+        varnames: Vec<&str>,
+        expr: AstExpr,
+    ) -> Result<OutRegAndOps, Error> {
+        for varname in &varnames {
+            self.check_not_constant(varname)?;
+        }
+
+        let (item_regs, mut ops) = match expr {
+            AstExpr::Tuple(items) => {
+                if items.len() != varnames.len() {
+                    return Err(format!(
+                        "Cannot destructure {} values into {} variables",
+                        items.len(),
+                        varnames.len()
+                    )
+                    .into());
+                }
+
+                // Names evaluate to the variable's own register, so the items are copied before
+                // assigning any, eg: `(a, b) = (b, a);`.
+                let mut ops = vec![];
+                let mut item_regs = vec![];
+                for item in items {
+                    let (item_reg, mut item_ops) = self.build_expr(item)?;
+                    ops.append(&mut item_ops);
+                    let copy_reg = self.next_free_reg_addr();
+                    ops.push(Operation::I2i {
+                        lhs: item_reg,
+                        rhs: copy_reg,
+                    });
+                    item_regs.push(copy_reg);
+                }
+                (item_regs, ops)
+            }
+            AstExpr::FnCall {
+                module,
+                name,
+                args,
+                named_args,
+            } => {
+                let (fn_label, mut ops) = self.build_call(module, name, args, named_args)?;
+                let count = self.tuple_returns.get(&fn_label).copied().unwrap_or(1);
+                if count != varnames.len() {
+                    return Err(format!(
+                        "Cannot destructure {} values returned by '{}' into {} variables",
+                        count,
+                        name,
+                        varnames.len()
+                    )
+                    .into());
+                }
+
+                let mut item_regs = vec![];
+                for _ in 0..count {
+                    let item_reg = self.next_free_reg_addr();
+                    ops.push(Operation::Pop(item_reg));
+                    item_regs.push(item_reg);
+                }
+                (item_regs, ops)
+            }
+            _ => {
+                return Err(
+                    "Only tuple literals and function calls can be destructured in IR".into(),
+                )
+            }
+        };
+
+        for (varname, item_reg) in varnames.into_iter().zip(&item_regs) {
+            if varname != "_" {
+                let var_reg = self.get_variable_reg_addr(varname);
+                ops.push(Operation::I2i {
+                    lhs: *item_reg,
+                    rhs: var_reg,
+                });
+            }
+        }
+
+        Ok((item_regs[0], ops))
+    }
+
+    fn build_expr_fn_call_len(&mut self, args: Vec<AstExpr>) -> Result<OutRegAndOps, Error> {
+        let [array]: [AstExpr; 1] = args
+            .try_into()
+            .map_err(|_| "Function 'len' expects 1 argument")?;
+
+        let (array, mut ops) = self.build_expr(array)?;
+
+        let out = self.next_free_reg_addr();
+        ops.push(Operation::Load { addr: array, out });
+
+        Ok((out, ops))
+    }
+
+    fn build_expr_fn_call_push(&mut self, args: Vec<AstExpr>) -> Result<OutRegAndOps, Error> {
+        let [array, value]: [AstExpr; 2] = args
+            .try_into()
+            .map_err(|_| "Function 'push' expects 2 arguments")?;
+
+        let (array, mut ops) = self.build_expr(array)?;
+        let (value, mut value_ops) = self.build_expr(value)?;
+        ops.append(&mut value_ops);
+
+        let len = self.next_free_reg_addr();
+        ops.push(Operation::Load {
+            addr: array,
+            out: len,
+        });
+        let capacity = self.next_free_reg_addr();
+        ops.push(Operation::LoadAI {
+            addr: array,
+            offs: ARRAY_CAPACITY_OFFS,
+            out: capacity,
+        });
+
+        let label_grow = self.next_free_label();
+        let label_copy = self.next_free_label();
+        let label_copy_item = self.next_free_label();
+        let label_copied = self.next_free_label();
+        let label_store = self.next_free_label();
+
+        let is_full = self.next_free_reg_addr();
+        ops.push(Operation::CmpGte {
+            lhs: len,
+            rhs: capacity,
+            out: is_full,
+        });
+        ops.push(Operation::CondBranch {
+            cond: is_full,
+            label_true: label_grow.clone(),
+            label_false: label_store.clone(),
+        });
+
+        // Grow: move the elements to a twice as big (+1 for empty arrays) place.
+        ops.push(Operation::Label(label_grow));
+        let new_capacity = self.next_free_reg_addr();
+        ops.push(Operation::MulI {
+            lhs: capacity,
+            rhs: 2,
+            out: new_capacity,
+        });
+        ops.push(Operation::AddI {
+            lhs: new_capacity,
+            rhs: 1,
+            out: new_capacity,
+        });
+        let new_elements = self.next_free_reg_addr();
+        ops.push(Operation::Alloc {
+            size: new_capacity,
+            out: new_elements,
+        });
+        let old_elements = self.next_free_reg_addr();
+        ops.push(Operation::LoadAI {
+            addr: array,
+            offs: ARRAY_ELEMENTS_OFFS,
+            out: old_elements,
+        });
+
+        let i = self.next_free_reg_addr();
+        ops.push(Operation::LoadI { val: 0, out: i });
+        ops.push(Operation::Label(label_copy.clone()));
+        let is_copied = self.next_free_reg_addr();
+        ops.push(Operation::CmpGte {
+            lhs: i,
+            rhs: len,
+            out: is_copied,
+        });
+        ops.push(Operation::CondBranch {
+            cond: is_copied,
+            label_true: label_copied.clone(),
+            label_false: label_copy_item.clone(),
+        });
+        ops.push(Operation::Label(label_copy_item));
+        let item = self.next_free_reg_addr();
+        ops.push(Operation::LoadAO {
+            addr: old_elements,
+            offs: i,
+            out: item,
+        });
+        ops.push(Operation::StoreAO {
+            reg: item,
+            addr: new_elements,
+            offs: i,
+        });
+        ops.push(Operation::AddI {
+            lhs: i,
+            rhs: 1,
+            out: i,
+        });
+        ops.push(Operation::JumpI(label_copy));
+
+        ops.push(Operation::Label(label_copied));
+        ops.push(Operation::StoreAI {
+            reg: new_capacity,
+            addr: array,
+            offs: ARRAY_CAPACITY_OFFS,
+        });
+        ops.push(Operation::StoreAI {
+            reg: new_elements,
+            addr: array,
+            offs: ARRAY_ELEMENTS_OFFS,
+        });
+        ops.push(Operation::JumpI(label_store.clone()));
+
+        // Store the new value and bump the length.
+        ops.push(Operation::Label(label_store));
+        let elements = self.next_free_reg_addr();
+        ops.push(Operation::LoadAI {
+            addr: array,
+            offs: ARRAY_ELEMENTS_OFFS,
+            out: elements,
+        });
+        ops.push(Operation::StoreAO {
+            reg: value,
+            addr: elements,
+            offs: len,
+        });
+        let out = self.next_free_reg_addr();
+        ops.push(Operation::AddI {
+            lhs: len,
+            rhs: 1,
+            out,
+        });
+        ops.push(Operation::Store {
+            reg: out,
+            addr: array,
+        });
+
+        Ok((out, ops))
+    }
+
+    fn build_expr_fn_call_math(
+        &mut self,
+        name: &str,
+        args: Vec<AstExpr>,
+    ) -> Result<OutRegAndOps, Error> {
+        let [value]: [AstExpr; 1] = args
+            .try_into()
+            .map_err(|_| format!("Function '{}' expects 1 argument", name))?;
+
+        let (reg, mut ops) = self.build_expr(value)?;
+        let out = self.next_free_reg_addr();
+        ops.push(match name {
+            "sqrt" => Operation::Sqrt { reg, out },
+            "floor" => Operation::Floor { reg, out },
+            _ => Operation::Round { reg, out },
+        });
+
+        Ok((out, ops))
+    }
+
+    fn build_expr_fn_call_is_null(&mut self, args: Vec<AstExpr>) -> Result<OutRegAndOps, Error> {
+        let [value]: [AstExpr; 1] = args
+            .try_into()
+            .map_err(|_| "Function 'is_null' expects 1 argument")?;
+
+        let (value, mut ops) = self.build_expr(value)?;
+        let null = self.next_free_reg_addr();
+        let out = self.next_free_reg_addr();
+        ops.push(Operation::LoadNull { out: null });
+        ops.push(Operation::CmpEq {
+            lhs: value,
+            rhs: null,
+            out,
+        });
+
+        Ok((out, ops))
+    }
+
+    fn build_expr_fn_call_pop(&mut self, args: Vec<AstExpr>) -> Result<OutRegAndOps, Error> {
+        let [array]: [AstExpr; 1] = args
+            .try_into()
+            .map_err(|_| "Function 'pop' expects 1 argument")?;
+
+        let (array, mut ops) = self.build_expr(array)?;
+
+        let len = self.next_free_reg_addr();
+        ops.push(Operation::Load {
+            addr: array,
+            out: len,
+        });
+        let zero = self.next_free_reg_addr();
+        ops.push(Operation::LoadI { val: 0, out: zero });
+
+        let label_ok = self.next_free_label();
+        let label_fail = self.next_free_label();
+
+        let not_empty = self.next_free_reg_addr();
+        ops.push(Operation::CmpGt {
+            lhs: len,
+            rhs: zero,
+            out: not_empty,
+        });
+        ops.push(Operation::CondBranch {
+            cond: not_empty,
+            label_true: label_ok.clone(),
+            label_false: label_fail.clone(),
+        });
+        ops.push(Operation::Label(label_fail));
+        ops.push(Operation::Abort {
+            msg: "Pop from empty array".into(),
+            args: vec![],
+        });
+        ops.push(Operation::Label(label_ok));
+
+        ops.push(Operation::SubI {
+            lhs: len,
+            rhs: 1,
+            out: len,
+        });
+        ops.push(Operation::Store {
+            reg: len,
+            addr: array,
+        });
+        let elements = self.next_free_reg_addr();
+        ops.push(Operation::LoadAI {
+            addr: array,
+            offs: ARRAY_ELEMENTS_OFFS,
+            out: elements,
+        });
+        let out = self.next_free_reg_addr();
+        ops.push(Operation::LoadAO {
+            addr: elements,
+            offs: len,
+            out,
+        });
+
+        Ok((out, ops))
+    }
+
+    fn build_expr_assignment(
+        &mut self,
+        varname: &str,
+        expr: AstExpr,
+    ) -> Result<OutRegAndOps, Error> {
+        self.check_not_constant(varname)?;
+        let (expr_reg, mut expr_ops) = self.build_expr(expr)?;
+        let out = self.get_variable_reg_addr(varname);
+        let mut ops = vec![];
+        ops.append(&mut expr_ops);
+
+        ops.push(Operation::I2i {
+            lhs: expr_reg,
+            rhs: out,
+        });
+
+        Ok((out, ops))
+    }
+
+    fn build_expr_binop(
+        &mut self,
+        lhs: AstExpr,
+        op: Op,
+        rhs: AstExpr,
+        loc: Loc,
+    ) -> Result<OutRegAndOps, Error> {
+        // Constants are inlined as immediate operands.
+        let has_immediate_form =
+            !matches!(op, Op::Mod | Op::Eq | Op::Lt | Op::Lte | Op::Gt | Op::Gte);
+        let is_commutative = matches!(op, Op::Add | Op::Mul | Op::BitAnd | Op::BitOr | Op::BitXor);
+        if let (Some(immediate), true) = (self.constant_int(&rhs), has_immediate_form) {
+            return self.build_expr_binop_immediate(lhs, op, immediate, loc);
+        }
+        if let (Some(immediate), true) = (self.constant_int(&lhs), is_commutative) {
+            return self.build_expr_binop_immediate(rhs, op, immediate, loc);
+        }
+
+        let (lhs_reg, mut lhs_ops) = self.build_expr(lhs)?;
+        let (rhs_reg, mut rhs_ops) = self.build_expr(rhs)?;
+
+        let mut ops = vec![];
+        ops.append(&mut lhs_ops);
+        ops.append(&mut rhs_ops);
+
+        let out = self.next_free_reg_addr();
+        ops.append(&mut self.build_binop_ops(op, lhs_reg, rhs_reg, out, loc));
+
+        Ok((out, ops))
+    }
+
+    /**
+     * `a ?? b` only evaluates `b` when `a` is null.
+     */
+    fn build_expr_coalesce(&mut self, lhs: AstExpr, rhs: AstExpr) -> Result<OutRegAndOps, Error> {
+        let out = self.next_free_reg_addr();
+        let (lhs_reg, mut ops) = self.build_expr(lhs)?;
+
+        let null = self.next_free_reg_addr();
+        let is_null = self.next_free_reg_addr();
+        ops.push(Operation::LoadNull { out: null });
+        ops.push(Operation::CmpEq {
+            lhs: lhs_reg,
+            rhs: null,
+            out: is_null,
+        });
+
+        let label_null = self.next_free_label();
+        let label_value = self.next_free_label();
+        let label_end = self.next_free_label();
+        ops.push(Operation::CondBranch {
+            cond: is_null,
+            label_true: label_null.clone(),
+            label_false: label_value.clone(),
+        });
+
+        ops.push(Operation::Label(label_value));
+        ops.push(Operation::I2i {
+            lhs: lhs_reg,
+            rhs: out,
+        });
+        ops.push(Operation::JumpI(label_end.clone()));
+
+        ops.push(Operation::Label(label_null));
+        let (rhs_reg, mut rhs_ops) = self.build_expr(rhs)?;
+        ops.append(&mut rhs_ops);
+        ops.push(Operation::I2i {
+            lhs: rhs_reg,
+            rhs: out,
+        });
+        ops.push(Operation::Label(label_end));
+
+        Ok((out, ops))
+    }
+
+    /**
+     * `~x` is `x ^ -1`, all bits set.
+     */
+    fn build_expr_bit_not(&mut self, expr: AstExpr) -> Result<OutRegAndOps, Error> {
+        let (expr_reg, mut ops) = self.build_expr(expr)?;
+        let out = self.next_free_reg_addr();
+        ops.push(Operation::XorI {
+            lhs: expr_reg,
+            rhs: -1,
+            out,
+        });
+
+        Ok((out, ops))
+    }
+
+    fn build_expr_binop_immediate(
+        &mut self,
+        expr: AstExpr,
+        op: Op,
+        immediate: ImmVal,
+        loc: Loc,
+    ) -> Result<OutRegAndOps, Error> {
+        let (expr_reg, mut ops) = self.build_expr(expr)?;
+        let out = self.next_free_reg_addr();
+        ops.push(Operation::Loc(loc));
+        ops.push(
+            immediate_op(&op, expr_reg, immediate, out)
+                .ok_or(format!("No immediate form of {:?}", op))?,
+        );
+
+        Ok((out, ops))
+    }
+
+    /**
+     * Compound assignments write the result straight into the variable's register. An integer
+     * literal operand uses the immediate form of the operation.
+     */
+    fn build_expr_op_assignment(
+        &mut self,
+        varname: &str,
+        op: Op,
+        expr: AstExpr,
+        loc: Loc,
+    ) -> Result<OutRegAndOps, Error> {
+        self.check_not_constant(varname)?;
+        let var_reg = *self
+            .frames
+            .last()
+            .unwrap()
+            .variables
+            .get(varname)
+            .ok_or("Variable not found")?;
+
+        let immediate = match &expr {
+            AstExpr::Int(v) => Some(*v),
+            _ => self.constant_int(&expr),
+        };
+        let immediate_op =
+            immediate.and_then(|immediate| immediate_op(&op, var_reg, immediate, var_reg));
+        if let Some(immediate_op) = immediate_op {
+            return Ok((var_reg, vec![Operation::Loc(loc), immediate_op]));
+        }
 
-        Ok((out, ops))
+        let (expr_reg, mut ops) = self.build_expr(expr)?;
+        ops.append(&mut self.build_binop_ops(op, var_reg, expr_reg, var_reg, loc));
+
+        Ok((var_reg, ops))
     }
 
-    fn build_expr_binop(
+    fn build_binop_ops(
         &mut self,
-        lhs: AstExpr,
         op: Op,
-        rhs: AstExpr,
-    ) -> Result<OutRegAndOps, Error> {
-        let (lhs_reg, mut lhs_ops) = self.build_expr(lhs)?;
-        let (rhs_reg, mut rhs_ops) = self.build_expr(rhs)?;
-
-        let mut ops = vec![];
-        ops.append(&mut lhs_ops);
-        ops.append(&mut rhs_ops);
-
-        let out = self.next_free_reg_addr();
+        lhs_reg: Reg,
+        rhs_reg: Reg,
+        out: Reg,
+        loc: Loc,
+    ) -> Vec<Operation> {
+        let mut ops = vec![Operation::Loc(loc)];
 
         match op {
             Op::Add => ops.push(Operation::Add {
@@ -336,66 +2086,122 @@ impl IRBuilder {
                 rhs: rhs_reg,
                 out,
             }),
+            Op::Pow => ops.push(Operation::Pow {
+                lhs: lhs_reg,
+                rhs: rhs_reg,
+                out,
+            }),
+            Op::BitAnd => ops.push(Operation::And {
+                lhs: lhs_reg,
+                rhs: rhs_reg,
+                out,
+            }),
+            Op::BitOr => ops.push(Operation::Or {
+                lhs: lhs_reg,
+                rhs: rhs_reg,
+                out,
+            }),
+            Op::BitXor => ops.push(Operation::Xor {
+                lhs: lhs_reg,
+                rhs: rhs_reg,
+                out,
+            }),
+            Op::Shl => ops.push(Operation::LShift {
+                lhs: lhs_reg,
+                rhs: rhs_reg,
+                out,
+            }),
+            Op::Shr => ops.push(Operation::RShift {
+                lhs: lhs_reg,
+                rhs: rhs_reg,
+                out,
+            }),
             Op::Eq => ops.push(Operation::CmpEq {
                 lhs: lhs_reg,
                 rhs: rhs_reg,
-                out: out,
+                out,
             }),
             Op::Lt => ops.push(Operation::CmpLt {
                 lhs: lhs_reg,
                 rhs: rhs_reg,
-                out: out,
+                out,
             }),
             Op::Lte => ops.push(Operation::CmpLte {
                 lhs: lhs_reg,
                 rhs: rhs_reg,
-                out: out,
+                out,
             }),
             Op::Gt => ops.push(Operation::CmpGt {
                 lhs: lhs_reg,
                 rhs: rhs_reg,
-                out: out,
+                out,
             }),
             Op::Gte => ops.push(Operation::CmpGte {
                 lhs: lhs_reg,
                 rhs: rhs_reg,
-                out: out,
+                out,
             }),
-            Op::Mod => {
-                let div_out = self.next_free_reg_addr();
-                ops.push(Operation::Div {
-                    lhs: lhs_reg,
-                    rhs: rhs_reg,
-                    out: div_out,
-                });
-                let mul_out = self.next_free_reg_addr();
-                ops.push(Operation::Mul {
-                    lhs: rhs_reg,
-                    rhs: div_out,
-                    out: mul_out,
-                });
-                ops.push(Operation::Sub {
-                    lhs: lhs_reg,
-                    rhs: mul_out,
-                    out,
-                });
-            }
+            Op::Mod => ops.push(Operation::Mod {
+                lhs: lhs_reg,
+                rhs: rhs_reg,
+                out,
+            }),
+            Op::Coalesce => unreachable!("?? is built by build_expr_coalesce"),
         };
 
-        Ok((out, ops))
+        ops
     }
 
+    /**
+     * Locals (such as arguments) shadow constants of the same name.
+     */
     fn build_expr_name(&mut self, name: &str) -> Result<OutRegAndOps, Error> {
+        if let Some(value) = self.local_constant(name).cloned() {
+            return self.build_const_value(&value);
+        }
+
         let addr = self.get_variable_reg_addr(name);
         Ok((addr, vec![]))
     }
 
-    fn build_expr_int(&mut self, val: i32) -> Result<OutRegAndOps, Error> {
+    fn build_const_value(&mut self, value: &ConstValue) -> Result<OutRegAndOps, Error> {
+        let val = match value {
+            ConstValue::Int(v) => *v,
+            ConstValue::Bool(b) => *b as ImmVal,
+            ConstValue::Str(_) => return Err("Strings are not supported in IR".into()),
+            ConstValue::Float(v) => return self.build_expr_float(*v),
+            ConstValue::BigInt(v) => {
+                let out = self.next_free_reg_addr();
+                return Ok((
+                    out,
+                    vec![Operation::LoadBigI {
+                        val: v.clone(),
+                        out,
+                    }],
+                ));
+            }
+        };
+        let out = self.next_free_reg_addr();
+        Ok((out, vec![Operation::LoadI { val, out }]))
+    }
+
+    fn build_expr_int(&mut self, val: i64) -> Result<OutRegAndOps, Error> {
         let out = self.next_free_reg_addr();
         let op = Operation::LoadI { val, out };
         Ok((out, vec![op]))
     }
 
+    fn build_expr_float(&mut self, val: f64) -> Result<OutRegAndOps, Error> {
+        let out = self.next_free_reg_addr();
+        let op = Operation::LoadF { val, out };
+        Ok((out, vec![op]))
+    }
+
+    fn build_expr_null(&mut self) -> Result<OutRegAndOps, Error> {
+        let out = self.next_free_reg_addr();
+        Ok((out, vec![Operation::LoadNull { out }]))
+    }
+
     fn build_expr_bool(&mut self, val: bool) -> Result<OutRegAndOps, Error> {
         let out = self.next_free_reg_addr();
         let op = Operation::LoadI {
@@ -406,7 +2212,7 @@ impl IRBuilder {
     }
 
     fn next_free_reg_addr(&mut self) -> Reg {
-        if self.frames.len() < 1 {
+        if self.frames.is_empty() {
             panic!("Missing frames");
         }
 
@@ -420,15 +2226,36 @@ impl IRBuilder {
         }
     }
 
+    fn constant_int(&self, expr: &AstExpr) -> Option<ImmVal> {
+        match expr {
+            AstExpr::Name(name) => match self.local_constant(name) {
+                Some(ConstValue::Int(v)) => Some(*v),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /**
+     * The constant a name refers to, unless a local of the same name shadows it.
+     */
+    fn local_constant(&self, name: &str) -> Option<&ConstValue> {
+        if self.frames.last().unwrap().variables.contains_key(name) {
+            return None;
+        }
+        self.constants.get(name)
+    }
+
+    fn check_not_constant(&self, name: &str) -> Result<(), Error> {
+        if self.constants.contains_key(name) {
+            return Err(format!("Cannot assign to constant '{}'", name).into());
+        }
+        Ok(())
+    }
+
     fn get_variable_reg_addr(&mut self, name: &str) -> Reg {
-        if self
-            .frames
-            .last()
-            .unwrap()
-            .variables
-            .contains_key(name.into())
-        {
-            self.frames.last().unwrap().variables[name.into()]
+        if self.frames.last().unwrap().variables.contains_key(name) {
+            self.frames.last().unwrap().variables[name]
         } else {
             let addr = self.next_free_reg_addr();
             self.frames
@@ -447,6 +2274,22 @@ impl IRBuilder {
     }
 }
 
+fn immediate_op(op: &Op, lhs: Reg, rhs: ImmVal, out: Reg) -> Option<Operation> {
+    match op {
+        Op::Add => Some(Operation::AddI { lhs, rhs, out }),
+        Op::Sub => Some(Operation::SubI { lhs, rhs, out }),
+        Op::Mul => Some(Operation::MulI { lhs, rhs, out }),
+        Op::Div => Some(Operation::DivI { lhs, rhs, out }),
+        Op::Pow => Some(Operation::PowI { lhs, rhs, out }),
+        Op::BitAnd => Some(Operation::AndI { lhs, rhs, out }),
+        Op::BitOr => Some(Operation::OrI { lhs, rhs, out }),
+        Op::BitXor => Some(Operation::XorI { lhs, rhs, out }),
+        Op::Shl => Some(Operation::LShiftI { lhs, rhs, out }),
+        Op::Shr => Some(Operation::RShiftI { lhs, rhs, out }),
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::ir_builder::*;
@@ -603,9 +2446,8 @@ mod test {
                 },
                 Operation::JumpI(Label::Numbered(1)), // jump to end
                 Operation::Label(Label::Numbered(2)), // if false
-                Operation::LoadI {
-                    // 0 -> r0
-                    val: 0,
+                Operation::LoadNull {
+                    // null -> r0
                     out: Reg::Global(0)
                 },
                 Operation::JumpI(Label::Numbered(1)), // jump to end
@@ -691,14 +2533,17 @@ mod test {
     fn test_loop() {
         assert_eq!(
             vec![
+                Operation::LoadNull {
+                    out: Reg::Global(0)
+                },
                 Operation::Label(Label::Numbered(0)),
                 Operation::LoadI {
                     val: 1,
-                    out: Reg::Global(0)
+                    out: Reg::Global(1)
                 },
                 Operation::I2i {
-                    lhs: Reg::Global(0),
-                    rhs: Reg::Global(1)
+                    lhs: Reg::Global(1),
+                    rhs: Reg::Global(2)
                 },
                 Operation::JumpI(Label::Numbered(0)),
                 Operation::Label(Label::Numbered(1))
@@ -711,6 +2556,9 @@ mod test {
     fn test_break() {
         assert_eq!(
             vec![
+                Operation::LoadNull {
+                    out: Reg::Global(0)
+                },
                 Operation::Label(Label::Numbered(0)),
                 Operation::JumpI(Label::Numbered(1)),
                 Operation::JumpI(Label::Numbered(0)),
@@ -732,23 +2580,342 @@ mod test {
                     val: 3,
                     out: Reg::Global(1)
                 },
-                Operation::Div {
+                Operation::Mod {
                     lhs: Reg::Global(0),
                     rhs: Reg::Global(1),
-                    out: Reg::Global(3)
+                    out: Reg::Global(2)
+                }
+            ],
+            ir_this("7 % 3;").instructions
+        );
+        assert_eq!(HashMap::from([(2, Loc::new(1, 3))]), ir_this("7 % 3;").locs);
+    }
+
+    #[test]
+    fn test_op_assignment() {
+        assert_eq!(
+            vec![
+                Operation::LoadI {
+                    val: 1,
+                    out: Reg::Global(0)
+                },
+                Operation::I2i {
+                    lhs: Reg::Global(0),
+                    rhs: Reg::Global(1)
+                },
+                Operation::AddI {
+                    lhs: Reg::Global(1),
+                    rhs: 2,
+                    out: Reg::Global(1)
                 },
                 Operation::Mul {
                     lhs: Reg::Global(1),
-                    rhs: Reg::Global(3),
+                    rhs: Reg::Global(1),
+                    out: Reg::Global(1)
+                },
+            ],
+            ir_this("a = 1; a += 2; a *= a;").instructions
+        );
+        assert_eq!("Variable not found", ir_error("a = 1; b += a;"));
+    }
+
+    #[test]
+    fn test_module_fn_labels() {
+        let ast_root = AstProgram {
+            statements: vec![
+                AstStatement::Module {
+                    name: "math",
+                    statements: vec![AstStatement::FnDef {
+                        name: "one",
+                        args: vec![],
+                        ret: None,
+                        block: AstBlock(vec![AstBlockLine::Expr(AstExpr::Int(1))]),
+                    }],
+                },
+                AstStatement::BlockLine(AstBlockLine::Expr(AstExpr::FnCall {
+                    module: Some("math"),
+                    name: "one",
+                    args: vec![],
+                    named_args: vec![],
+                })),
+            ],
+        };
+        let ops = IRBuilder::new().build(ast_root).unwrap().instructions;
+        assert!(ops.contains(&Operation::Label(Label::Namespaced(
+            "math".into(),
+            "one".into()
+        ))));
+        assert!(ops.contains(&Operation::Call(Label::Namespaced(
+            "math".into(),
+            "one".into()
+        ))));
+
+        let reader = Box::new(StrReader::new("math::one();"));
+        let lexemes = Lexer::new(reader).read_any().unwrap();
+        let ast_root = Parser::new(lexemes.into()).build_ast().unwrap();
+        assert_eq!(
+            "Missing function 'math::one'",
+            IRBuilder::new().build(ast_root).unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_strings_rejected() {
+        for source in [
+            r#"x = 1; if (x > 2) { y = "never"; }"#,
+            r#"fn f() { "unused"; } 1;"#,
+            r#"x = "a{1}";"#,
+            r#"fn f(s) { match (s) { "a" => { 1; } _ => { 2; } } } f(1);"#,
+            r#"const S = "s"; S;"#,
+        ] {
+            assert_eq!("Strings are not supported in IR", ir_error(source));
+        }
+    }
+
+    #[test]
+    fn test_constants() {
+        assert_eq!(
+            vec![
+                Operation::LoadI {
+                    val: 1,
+                    out: Reg::Global(0)
+                },
+                Operation::I2i {
+                    lhs: Reg::Global(0),
+                    rhs: Reg::Global(1)
+                },
+                Operation::AddI {
+                    lhs: Reg::Global(1),
+                    rhs: 6,
+                    out: Reg::Global(2)
+                },
+                Operation::MulI {
+                    lhs: Reg::Global(1),
+                    rhs: 6,
+                    out: Reg::Global(3)
+                },
+                Operation::LoadI {
+                    val: 6,
                     out: Reg::Global(4)
                 },
                 Operation::Sub {
-                    lhs: Reg::Global(0),
-                    rhs: Reg::Global(4),
+                    lhs: Reg::Global(4),
+                    rhs: Reg::Global(1),
+                    out: Reg::Global(5)
+                },
+            ],
+            ir_this("const N = 2 * 3; a = 1; a + N; N * a; N - a;").instructions
+        );
+
+        let reader = Box::new(StrReader::new("const N = 1; fn f() { N = 2; }"));
+        let lexemes = Lexer::new(reader).read_any().unwrap();
+        let ast_root = Parser::new(lexemes.into()).build_ast().unwrap();
+        assert_eq!(
+            "Cannot assign to constant 'N'",
+            IRBuilder::new().build(ast_root).unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_array() {
+        assert_eq!(
+            vec![
+                Operation::LoadI {
+                    val: 7,
+                    out: Reg::Global(0)
+                },
+                Operation::LoadI {
+                    // header size -> r1
+                    val: 3,
+                    out: Reg::Global(1)
+                },
+                Operation::Alloc {
+                    // header -> r2
+                    size: Reg::Global(1),
                     out: Reg::Global(2)
-                }
+                },
+                Operation::LoadI {
+                    // length -> r3
+                    val: 1,
+                    out: Reg::Global(3)
+                },
+                Operation::Alloc {
+                    // elements -> r4
+                    size: Reg::Global(3),
+                    out: Reg::Global(4)
+                },
+                Operation::Store {
+                    reg: Reg::Global(3),
+                    addr: Reg::Global(2)
+                },
+                Operation::StoreAI {
+                    reg: Reg::Global(3),
+                    addr: Reg::Global(2),
+                    offs: 1
+                },
+                Operation::StoreAI {
+                    reg: Reg::Global(4),
+                    addr: Reg::Global(2),
+                    offs: 2
+                },
+                Operation::LoadI {
+                    val: 0,
+                    out: Reg::Global(5)
+                },
+                Operation::StoreAO {
+                    reg: Reg::Global(0),
+                    addr: Reg::Global(4),
+                    offs: Reg::Global(5)
+                },
             ],
-            ir_this("7 % 3;").instructions
+            ir_this("[7];").instructions
+        );
+    }
+
+    #[test]
+    fn test_struct() {
+        assert_eq!(
+            vec![
+                Operation::LoadI {
+                    val: 1,
+                    out: Reg::Global(0)
+                },
+                Operation::LoadI {
+                    val: 2,
+                    out: Reg::Global(1)
+                },
+                Operation::LoadI {
+                    // size -> r2
+                    val: 3,
+                    out: Reg::Global(2)
+                },
+                Operation::Alloc {
+                    size: Reg::Global(2),
+                    out: Reg::Global(3)
+                },
+                Operation::LoadI {
+                    // tag -> r4
+                    val: 0,
+                    out: Reg::Global(4)
+                },
+                Operation::StoreAI {
+                    reg: Reg::Global(4),
+                    addr: Reg::Global(3),
+                    offs: 0
+                },
+                Operation::StoreAI {
+                    // y
+                    reg: Reg::Global(0),
+                    addr: Reg::Global(3),
+                    offs: 2
+                },
+                Operation::StoreAI {
+                    // x
+                    reg: Reg::Global(1),
+                    addr: Reg::Global(3),
+                    offs: 1
+                },
+                Operation::LoadAI {
+                    addr: Reg::Global(3),
+                    offs: 2,
+                    out: Reg::Global(5)
+                },
+            ],
+            ir_this("struct Point { x, y } Point { y: 1, x: 2 }.y;").instructions
+        );
+    }
+
+    #[test]
+    fn test_struct_field_offset_by_tag() {
+        let ops =
+            ir_this("struct A { x, y } struct B { y } a = A { x: 1, y: 2 }; a.y;").instructions;
+        assert!(ops.contains(&Operation::Abort {
+            msg: "Record has no field 'y'".into(),
+            args: vec![],
+        }));
+
+        let reader = Box::new(StrReader::new(
+            "struct Point { x, y } Point { x: 1, x: 2 };",
+        ));
+        let lexemes = Lexer::new(reader).read_any().unwrap();
+        let ast_root = Parser::new(lexemes.into()).build_ast().unwrap();
+        assert_eq!(
+            "Missing field 'y' for struct 'Point'",
+            IRBuilder::new().build(ast_root).unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_range_errors() {
+        for (source, err) in [
+            (
+                "for x in [1, 2] { x; }",
+                "Only ranges can be iterated in IR",
+            ),
+            ("r = 0..3;", "Ranges are only supported in for loops in IR"),
+            (
+                "fn g() { yield 1; } g();",
+                "Generators are not supported in IR",
+            ),
+        ] {
+            let reader = Box::new(StrReader::new(source));
+            let lexemes = Lexer::new(reader).read_any().unwrap();
+            let ast_root = Parser::new(lexemes.into()).build_ast().unwrap();
+            assert_eq!(
+                err,
+                IRBuilder::new().build(ast_root).unwrap_err().to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn test_tuple_errors() {
+        for (source, err) in [
+            (
+                "t = (1, 2);",
+                "Tuples are only supported as return values and in destructuring in IR",
+            ),
+            (
+                "fn f() { (1, 2); } x = f();",
+                "Function 'f' returns 2 values, which must be destructured in IR",
+            ),
+            (
+                "fn f() { (1, 2); } (a, b, c) = f();",
+                "Cannot destructure 2 values returned by 'f' into 3 variables",
+            ),
+            (
+                "fn f(t) { (a, b) = t; a; }",
+                "Only tuple literals and function calls can be destructured in IR",
+            ),
+            (
+                "fn f(c) { if (c) { (1, 2); } else { (2, 1); } }",
+                "Function 'f' must end with a tuple literal to return several values in IR",
+            ),
+            (
+                "fn f() { (1, 2); } fn g() { f(); }",
+                "Function 'g' must end with a tuple literal to return several values in IR",
+            ),
+        ] {
+            let reader = Box::new(StrReader::new(source));
+            let lexemes = Lexer::new(reader).read_any().unwrap();
+            let ast_root = Parser::new(lexemes.into()).build_ast().unwrap();
+            assert_eq!(
+                err,
+                IRBuilder::new().build(ast_root).unwrap_err().to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn test_match_exhaustiveness() {
+        let reader = Box::new(StrReader::new(
+            "enum E { A, B(x) } match (E::A) { E::A => { 1; } }",
+        ));
+        let lexemes = Lexer::new(reader).read_any().unwrap();
+        let ast_root = Parser::new(lexemes.into()).build_ast().unwrap();
+        assert_eq!(
+            "Non-exhaustive match: missing E::B",
+            IRBuilder::new().build(ast_root).unwrap_err().to_string()
         );
     }
 
@@ -758,4 +2925,11 @@ mod test {
         let ast_root = Parser::new(lexemes.into()).build_ast().unwrap();
         IRBuilder::new().build(ast_root).unwrap()
     }
+
+    fn ir_error(input: &'static str) -> String {
+        let reader = Box::new(StrReader::new(input));
+        let lexemes = Lexer::new(reader).read_any().unwrap();
+        let ast_root = Parser::new(lexemes.into()).build_ast().unwrap();
+        IRBuilder::new().build(ast_root).unwrap_err().to_string()
+    }
 }
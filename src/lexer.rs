@@ -4,46 +4,107 @@ use crate::source_reader::*;
 #[derive(Debug, PartialEq)]
 pub enum Lexeme<'a> {
     Name(&'a str),
-    Int(i32),
+    Int(i64),
+    Float(f64),
     Str(&'a str),
+    StrTemplate(Vec<StrPart<'a>>),
     True,
     False,
+    Null,
     Fn,
     If,
     Else,
     Loop,
+    For,
+    In,
     Break,
+    Continue,
+    // `'outer`, without the quote.
+    LoopLabel(&'a str),
+    Throw,
+    Try,
+    Catch,
+    Yield,
+    Struct,
+    Enum,
+    Match,
+    Import,
+    Let,
+    Const,
     ParenOpen,
     ParenClose,
     BraceOpen,
     BraceClose,
+    BracketOpen,
+    BracketClose,
     Semicolon,
+    Colon,
+    PathSep,
     Comma,
+    Dot,
+    // `..` and `..=`
+    DotDot,
+    DotDotEq,
+    Ellipsis,
     Assign,
+    OpAssign(Op),
+    FatArrow,
+    Arrow,
+    // `|>`
+    Pipe,
     Op(Op),
+    // Bitwise not, the only prefix operator.
+    Tilde,
+}
+
+/**
+ * Part of a string with interpolation: `"sum: {a + b}"`. Expressions keep the location of each
+ * lexeme, so errors can point inside the string.
+ */
+#[derive(Debug, PartialEq)]
+pub enum StrPart<'a> {
+    Literal(String),
+    Expr(Vec<(Lexeme<'a>, Loc)>),
 }
 
 pub struct Lexer<'a> {
-    reader: Box<dyn SourceReader<'a>>,
+    reader: Box<dyn SourceReader<'a> + 'a>,
 }
 
 impl<'a> Lexer<'a> {
-    pub fn new(reader: Box<dyn SourceReader<'a>>) -> Lexer<'a> {
+    pub fn new(reader: Box<dyn SourceReader<'a> + 'a>) -> Lexer<'a> {
         Lexer { reader }
     }
 
-    pub fn read_any(&mut self) -> Result<Vec<Lexeme<'a>>, Error> {
+    pub fn read_any(&mut self) -> Result<Vec<(Lexeme<'a>, Loc)>, Error> {
         let mut lexemes = vec![];
 
         loop {
             self.consume_whitespace();
 
+            if self.reader.is_eof() {
+                break;
+            }
+
+            let loc = self.reader.loc();
             let lexeme = match self.reader.peek() {
                 None => break,
                 Some(c) => match c {
                     '0'..='9' => self.read_number()?,
-                    'a'..='z' => self.read_word()?,
+                    'a'..='z' | 'A'..='Z' | '_' => self.read_word()?,
                     '"' => self.read_string()?,
+                    '\'' => {
+                        self.reader.next();
+                        match self
+                            .reader
+                            .read_until(|c| c.is_ascii_alphanumeric() || c == '_')
+                        {
+                            Some(name) => Lexeme::LoopLabel(name),
+                            None => {
+                                return Err(format!("Expected loop label name at {}", loc).into())
+                            }
+                        }
+                    }
                     '(' => {
                         self.reader.next();
                         Lexeme::ParenOpen
@@ -64,10 +125,48 @@ impl<'a> Lexer<'a> {
                         self.reader.next();
                         Lexeme::BraceClose
                     }
+                    '[' => {
+                        self.reader.next();
+                        Lexeme::BracketOpen
+                    }
+                    ']' => {
+                        self.reader.next();
+                        Lexeme::BracketClose
+                    }
+                    ':' => {
+                        self.reader.next();
+                        match self.reader.peek() {
+                            Some(':') => {
+                                self.reader.next();
+                                Lexeme::PathSep
+                            }
+                            _ => Lexeme::Colon,
+                        }
+                    }
                     ',' => {
                         self.reader.next();
                         Lexeme::Comma
                     }
+                    '.' => {
+                        self.reader.next();
+                        match self.reader.peek() {
+                            Some('.') => {
+                                self.reader.next();
+                                match self.reader.peek() {
+                                    Some('.') => {
+                                        self.reader.next();
+                                        Lexeme::Ellipsis
+                                    }
+                                    Some('=') => {
+                                        self.reader.next();
+                                        Lexeme::DotDotEq
+                                    }
+                                    _ => Lexeme::DotDot,
+                                }
+                            }
+                            _ => Lexeme::Dot,
+                        }
+                    }
                     '=' => {
                         self.reader.next();
                         match self.reader.peek() {
@@ -75,28 +174,74 @@ impl<'a> Lexer<'a> {
                                 self.reader.next();
                                 Lexeme::Op(Op::Eq)
                             }
+                            Some('>') => {
+                                self.reader.next();
+                                Lexeme::FatArrow
+                            }
                             _ => Lexeme::Assign,
                         }
                     }
                     '+' => {
                         self.reader.next();
-                        Lexeme::Op(Op::Add)
+                        self.read_op_or_op_assign(Op::Add)
                     }
                     '-' => {
                         self.reader.next();
-                        Lexeme::Op(Op::Sub)
+                        match self.reader.peek() {
+                            Some('>') => {
+                                self.reader.next();
+                                Lexeme::Arrow
+                            }
+                            _ => self.read_op_or_op_assign(Op::Sub),
+                        }
                     }
                     '*' => {
                         self.reader.next();
-                        Lexeme::Op(Op::Mul)
+                        match self.reader.peek() {
+                            Some('*') => {
+                                self.reader.next();
+                                self.read_op_or_op_assign(Op::Pow)
+                            }
+                            _ => self.read_op_or_op_assign(Op::Mul),
+                        }
                     }
                     '/' => {
                         self.reader.next();
-                        Lexeme::Op(Op::Div)
+                        self.read_op_or_op_assign(Op::Div)
                     }
                     '%' => {
                         self.reader.next();
-                        Lexeme::Op(Op::Mod)
+                        self.read_op_or_op_assign(Op::Mod)
+                    }
+                    '&' => {
+                        self.reader.next();
+                        self.read_op_or_op_assign(Op::BitAnd)
+                    }
+                    '|' => {
+                        self.reader.next();
+                        match self.reader.peek() {
+                            Some('>') => {
+                                self.reader.next();
+                                Lexeme::Pipe
+                            }
+                            _ => self.read_op_or_op_assign(Op::BitOr),
+                        }
+                    }
+                    '^' => {
+                        self.reader.next();
+                        self.read_op_or_op_assign(Op::BitXor)
+                    }
+                    '~' => {
+                        self.reader.next();
+                        Lexeme::Tilde
+                    }
+                    '?' => {
+                        self.reader.next();
+                        if self.reader.peek() != Some('?') {
+                            return Err(format!("Invalid char during lexing: ? at {}", loc).into());
+                        }
+                        self.reader.next();
+                        Lexeme::Op(Op::Coalesce)
                     }
                     '<' => {
                         self.reader.next();
@@ -105,6 +250,10 @@ impl<'a> Lexer<'a> {
                                 self.reader.next();
                                 Lexeme::Op(Op::Lte)
                             }
+                            Some('<') => {
+                                self.reader.next();
+                                self.read_op_or_op_assign(Op::Shl)
+                            }
                             _ => Lexeme::Op(Op::Lt),
                         }
                     }
@@ -115,37 +264,69 @@ impl<'a> Lexer<'a> {
                                 self.reader.next();
                                 Lexeme::Op(Op::Gte)
                             }
+                            Some('>') => {
+                                self.reader.next();
+                                self.read_op_or_op_assign(Op::Shr)
+                            }
                             _ => Lexeme::Op(Op::Gt),
                         }
                     }
-                    _ => return Err(format!("Invalid char during lexing: {}", c).into()),
+                    _ => return Err(format!("Invalid char during lexing: {} at {}", c, loc).into()),
                 },
             };
 
-            lexemes.push(lexeme);
+            lexemes.push((lexeme, loc));
         }
 
         Ok(lexemes)
     }
 
+    fn read_op_or_op_assign(&mut self, op: Op) -> Lexeme<'a> {
+        match self.reader.peek() {
+            Some('=') => {
+                self.reader.next();
+                Lexeme::OpAssign(op)
+            }
+            _ => Lexeme::Op(op),
+        }
+    }
+
     fn consume_whitespace(&mut self) {
         let _ = self.reader.read_until(|c| c.is_whitespace());
     }
 
+    /**
+     * `12` or `1.5`. A dot not followed by a digit is left alone: `0..3`.
+     */
     fn read_number(&mut self) -> Result<Lexeme<'a>, Error> {
-        self.reader
+        let int_part = self
+            .reader
             .read_until(|c| c.is_ascii_digit())
-            .ok_or("Empty number".into())
-            .and_then(|slice| {
-                i32::from_str_radix(slice, 10)
-                    .map(|num| Lexeme::Int(num))
-                    .map_err(|_| "Failed converting string to number".into())
-            })
+            .ok_or::<Error>("Empty number".into())?;
+
+        let is_float = self.reader.peek() == Some('.')
+            && self.reader.peek_nth(1).is_some_and(|c| c.is_ascii_digit());
+        if !is_float {
+            return int_part
+                .parse::<i64>()
+                .map(Lexeme::Int)
+                .map_err(|_| "Failed converting string to number".into());
+        }
+
+        self.reader.next();
+        let fraction = self
+            .reader
+            .read_until(|c| c.is_ascii_digit())
+            .ok_or::<Error>("Empty number".into())?;
+        format!("{}.{}", int_part, fraction)
+            .parse::<f64>()
+            .map(Lexeme::Float)
+            .map_err(|_| "Failed converting string to number".into())
     }
 
     fn read_word(&mut self) -> Result<Lexeme<'a>, Error> {
         self.reader
-            .read_until(|c| c.is_ascii_alphanumeric())
+            .read_until(|c| c.is_ascii_alphanumeric() || c == '_')
             .ok_or("Empty name".into())
             .map(|slice| match slice {
                 "fn" => Lexeme::Fn,
@@ -153,8 +334,22 @@ impl<'a> Lexer<'a> {
                 "else" => Lexeme::Else,
                 "true" => Lexeme::True,
                 "false" => Lexeme::False,
+                "null" => Lexeme::Null,
                 "loop" => Lexeme::Loop,
+                "for" => Lexeme::For,
+                "in" => Lexeme::In,
                 "break" => Lexeme::Break,
+                "continue" => Lexeme::Continue,
+                "throw" => Lexeme::Throw,
+                "try" => Lexeme::Try,
+                "catch" => Lexeme::Catch,
+                "yield" => Lexeme::Yield,
+                "struct" => Lexeme::Struct,
+                "enum" => Lexeme::Enum,
+                "match" => Lexeme::Match,
+                "import" => Lexeme::Import,
+                "let" => Lexeme::Let,
+                "const" => Lexeme::Const,
                 _ => Lexeme::Name(slice),
             })
     }
@@ -164,14 +359,135 @@ impl<'a> Lexer<'a> {
             return Err("String must start with \"".into());
         }
 
-        let str = self.reader.read_until(|c| c != '"').unwrap_or("");
+        let start = self.reader.loc();
+
+        // Quotes of strings inside interpolated expressions do not end the string.
+        let mut depth = 0;
+        let mut in_str = false;
+        let mut just_opened = false;
+        let str = self
+            .reader
+            .read_while(&mut |c| {
+                let was_just_opened = just_opened;
+                just_opened = false;
+                match c {
+                    _ if in_str => in_str = c != '"',
+                    '"' if depth == 0 => return false,
+                    '"' => in_str = true,
+                    // `{{` is an escaped `{`.
+                    '{' if was_just_opened => depth -= 1,
+                    '{' => {
+                        depth += 1;
+                        just_opened = depth == 1;
+                    }
+                    '}' if depth > 0 => depth -= 1,
+                    _ => {}
+                };
+                true
+            })
+            .unwrap_or("");
 
         if self.reader.next() != Some('"') {
-            return Err("String must end with \"".into());
+            return Err(format!("String must end with \" (started at {})", start).into());
+        }
+
+        if !str.contains(['{', '}']) {
+            return Ok(Lexeme::Str(str));
+        }
+
+        Ok(Lexeme::StrTemplate(read_str_parts(str, start)?))
+    }
+}
+
+/**
+ * Splits the content of a string (starting at `start`) into literals and interpolated expressions.
+ *
+ * Every `{` starts an interpolation, so literal braces must be escaped as `{{` and `}}`;
+ * a lone `}` is an error. Strings with braces written before templating need updating.
+ */
+fn read_str_parts<'a>(str: &'a str, start: Loc) -> Result<Vec<StrPart<'a>>, Error> {
+    let mut parts = vec![];
+    let mut literal = String::new();
+    let mut loc = start;
+
+    let mut chars = str.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let brace_loc = loc;
+        loc.advance(c);
+        if c == '}' {
+            if let Some((_, '}')) = chars.peek() {
+                chars.next();
+                loc.advance('}');
+                literal.push('}');
+                continue;
+            }
+            return Err(format!("Unmatched }} in string at {}", brace_loc).into());
+        }
+        if c != '{' {
+            literal.push(c);
+            continue;
+        }
+
+        if let Some((_, '{')) = chars.peek() {
+            chars.next();
+            loc.advance('{');
+            literal.push('{');
+            continue;
+        }
+
+        let expr_loc = loc;
+        let expr_len = interpolation_len(&str[i + 1..]).ok_or(format!(
+            "Unterminated interpolation in string at {}",
+            brace_loc
+        ))?;
+        let expr = &str[i + 1..i + 1 + expr_len];
+        if expr.trim().is_empty() {
+            return Err(format!("Empty interpolation in string at {}", brace_loc).into());
+        }
+
+        for c in expr.chars().chain(std::iter::once('}')) {
+            loc.advance(c);
+        }
+        while let Some((j, _)) = chars.peek() {
+            if *j > i + 1 + expr_len {
+                break;
+            }
+            chars.next();
+        }
+
+        if !literal.is_empty() {
+            parts.push(StrPart::Literal(std::mem::take(&mut literal)));
         }
+        let reader = Box::new(StrReader::new_at(expr, expr_loc));
+        parts.push(StrPart::Expr(Lexer::new(reader).read_any()?));
+    }
 
-        Ok(Lexeme::Str(str))
+    if !literal.is_empty() {
+        parts.push(StrPart::Literal(literal));
     }
+
+    Ok(parts)
+}
+
+/**
+ * Byte length of an interpolated expression up to its closing brace.
+ */
+fn interpolation_len(str: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut in_str = false;
+
+    for (i, c) in str.char_indices() {
+        match c {
+            _ if in_str => in_str = c != '"',
+            '"' => in_str = true,
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(i),
+            '}' => depth -= 1,
+            _ => {}
+        };
+    }
+
+    None
 }
 
 #[cfg(test)]
@@ -186,6 +502,10 @@ mod test {
     #[test]
     fn test_name() {
         assert_eq!(vec![Lexeme::Name("hi")], lex_this("\thi \n").unwrap());
+        assert_eq!(
+            vec![Lexeme::Name("Point"), Lexeme::Name("_snake_case2")],
+            lex_this("\tPoint _snake_case2\n").unwrap()
+        );
     }
 
     #[test]
@@ -193,6 +513,63 @@ mod test {
         assert_eq!(vec![Lexeme::Int(1024)], lex_this("\t1024 \n").unwrap());
     }
 
+    #[test]
+    fn test_float() {
+        assert_eq!(
+            vec![
+                Lexeme::Float(1.5),
+                Lexeme::Float(0.25),
+                Lexeme::Int(0),
+                Lexeme::DotDot,
+                Lexeme::Int(3),
+                Lexeme::Int(2),
+                Lexeme::Dot,
+                Lexeme::Name("x"),
+            ],
+            lex_this("1.5 0.25 0..3 2.x").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_null_and_coalesce() {
+        assert_eq!(
+            vec![
+                Lexeme::Name("a"),
+                Lexeme::Op(Op::Coalesce),
+                Lexeme::Null,
+                Lexeme::Name("nullable"),
+            ],
+            lex_this("a ?? null nullable").unwrap()
+        );
+        assert_eq!(
+            "Invalid char during lexing: ? at 1:3",
+            lex_this("a ? b").unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_pipe() {
+        assert_eq!(
+            vec![
+                Lexeme::Name("a"),
+                Lexeme::Pipe,
+                Lexeme::Op(Op::BitOr),
+                Lexeme::OpAssign(Op::BitOr),
+                Lexeme::Op(Op::Gt),
+            ],
+            lex_this("a |> | |= >").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_int_64() {
+        assert_eq!(
+            vec![Lexeme::Int(i64::MAX)],
+            lex_this("9223372036854775807").unwrap()
+        );
+        assert!(lex_this("9223372036854775808").is_err());
+    }
+
     #[test]
     fn test_str() {
         assert_eq!(
@@ -209,9 +586,15 @@ mod test {
                 Lexeme::If,
                 Lexeme::Else,
                 Lexeme::Loop,
-                Lexeme::Break
+                Lexeme::Break,
+                Lexeme::Struct,
+                Lexeme::Enum,
+                Lexeme::Match,
+                Lexeme::Import,
+                Lexeme::Let,
+                Lexeme::Const
             ],
-            lex_this("\tfn if else loop break\n").unwrap()
+            lex_this("\tfn if else loop break struct enum match import let const\n").unwrap()
         );
     }
 
@@ -235,16 +618,133 @@ mod test {
         assert_eq!(vec![Lexeme::BraceClose], lex_this("\t} \n").unwrap());
     }
 
+    #[test]
+    fn test_brackets() {
+        assert_eq!(
+            vec![Lexeme::BracketOpen, Lexeme::BracketClose],
+            lex_this("\t[ ] \n").unwrap()
+        );
+    }
+
     #[test]
     fn test_semicolon() {
         assert_eq!(vec![Lexeme::Semicolon], lex_this("\t; \n").unwrap());
     }
 
+    #[test]
+    fn test_colon() {
+        assert_eq!(vec![Lexeme::Colon], lex_this("\t: \n").unwrap());
+    }
+
+    #[test]
+    fn test_path_sep() {
+        assert_eq!(
+            vec![
+                Lexeme::Name("Shape"),
+                Lexeme::PathSep,
+                Lexeme::Name("Circle"),
+                Lexeme::Colon
+            ],
+            lex_this("\tShape::Circle : \n").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_fat_arrow() {
+        assert_eq!(
+            vec![Lexeme::FatArrow, Lexeme::Assign, Lexeme::Op(Op::Eq)],
+            lex_this("\t=> = == \n").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_arrow() {
+        assert_eq!(
+            vec![
+                Lexeme::Arrow,
+                Lexeme::Op(Op::Sub),
+                Lexeme::OpAssign(Op::Sub)
+            ],
+            lex_this("\t-> - -= \n").unwrap()
+        );
+    }
+
     #[test]
     fn test_comma() {
         assert_eq!(vec![Lexeme::Comma], lex_this("\t, \n").unwrap());
     }
 
+    #[test]
+    fn test_dot() {
+        assert_eq!(
+            vec![Lexeme::Name("p"), Lexeme::Dot, Lexeme::Name("x")],
+            lex_this("\tp.x \n").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_ellipsis() {
+        assert_eq!(
+            vec![Lexeme::Ellipsis, Lexeme::Name("rest")],
+            lex_this("...rest").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_range() {
+        assert_eq!(
+            vec![
+                Lexeme::For,
+                Lexeme::Name("i"),
+                Lexeme::In,
+                Lexeme::Int(0),
+                Lexeme::DotDot,
+                Lexeme::Name("n"),
+                Lexeme::Int(1),
+                Lexeme::DotDotEq,
+                Lexeme::Int(3),
+            ],
+            lex_this("for i in 0..n 1..=3").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_loop_label() {
+        assert_eq!(
+            vec![
+                Lexeme::LoopLabel("outer"),
+                Lexeme::Colon,
+                Lexeme::Loop,
+                Lexeme::Continue,
+                Lexeme::LoopLabel("outer"),
+            ],
+            lex_this("'outer: loop continue 'outer").unwrap()
+        );
+        assert_eq!(
+            "Expected loop label name at 1:7",
+            lex_this("break ';").unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_try_catch() {
+        assert_eq!(
+            vec![
+                Lexeme::Try,
+                Lexeme::BraceOpen,
+                Lexeme::Throw,
+                Lexeme::Int(1),
+                Lexeme::Semicolon,
+                Lexeme::BraceClose,
+                Lexeme::Catch,
+                Lexeme::ParenOpen,
+                Lexeme::Name("e"),
+                Lexeme::ParenClose,
+            ],
+            lex_this("try { throw 1; } catch (e)").unwrap()
+        );
+    }
+
     #[test]
     fn test_assign() {
         assert_eq!(vec![Lexeme::Assign], lex_this("\t= \n").unwrap());
@@ -264,6 +764,25 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_bitwise_ops() {
+        assert_eq!(
+            vec![
+                Lexeme::Op(Op::Pow),
+                Lexeme::Op(Op::BitAnd),
+                Lexeme::Op(Op::BitOr),
+                Lexeme::Op(Op::BitXor),
+                Lexeme::Op(Op::Shl),
+                Lexeme::Op(Op::Shr),
+                Lexeme::Tilde,
+                Lexeme::OpAssign(Op::Shl),
+                Lexeme::OpAssign(Op::Pow),
+                Lexeme::OpAssign(Op::BitXor),
+            ],
+            lex_this("** & | ^ << >> ~ <<= **= ^=").unwrap()
+        );
+    }
+
     #[test]
     fn test_op_eq_and_assign() {
         assert_eq!(
@@ -277,6 +796,22 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_op_assign() {
+        assert_eq!(
+            vec![
+                Lexeme::OpAssign(Op::Add),
+                Lexeme::OpAssign(Op::Sub),
+                Lexeme::OpAssign(Op::Mul),
+                Lexeme::OpAssign(Op::Div),
+                Lexeme::OpAssign(Op::Mod),
+                Lexeme::Op(Op::Add),
+                Lexeme::Assign
+            ],
+            lex_this("\t+= -= *= /= %= + = \n").unwrap()
+        );
+    }
+
     #[test]
     fn test_compare() {
         assert_eq!(
@@ -316,8 +851,57 @@ mod test {
         );
     }
 
-    fn lex_this(input: &'static str) -> Result<Vec<Lexeme>, Error> {
+    #[test]
+    fn test_str_template() {
+        assert_eq!(
+            vec![Lexeme::StrTemplate(vec![
+                StrPart::Literal("a {".into()),
+                StrPart::Expr(vec![
+                    (Lexeme::Name("x"), Loc::new(1, 7)),
+                    (Lexeme::Op(Op::Add), Loc::new(1, 9)),
+                    (
+                        Lexeme::StrTemplate(vec![StrPart::Literal("}".into())]),
+                        Loc::new(1, 11),
+                    ),
+                ]),
+                StrPart::Literal("!".into()),
+            ])],
+            lex_this(r#""a {{{x + "}}"}!""#).unwrap()
+        );
+        assert_eq!(
+            vec![Lexeme::StrTemplate(vec![
+                StrPart::Expr(vec![(Lexeme::Name("m"), Loc::new(2, 4))]),
+                StrPart::Expr(vec![
+                    (Lexeme::BraceOpen, Loc::new(2, 8)),
+                    (Lexeme::BraceClose, Loc::new(2, 9)),
+                ]),
+            ])],
+            lex_this("\n \"{m}{ {} }\"").unwrap()
+        );
+        assert_eq!(
+            vec![Lexeme::StrTemplate(vec![StrPart::Literal("{a}".into())])],
+            lex_this(r#""{{a}}""#).unwrap()
+        );
+        assert_eq!(
+            "Unmatched } in string at 1:3",
+            lex_this(r#""a}b""#).unwrap_err().to_string()
+        );
+        assert_eq!(
+            "Empty interpolation in string at 1:3",
+            lex_this(r#""a{ }""#).unwrap_err().to_string()
+        );
+        assert_eq!(
+            "Invalid char during lexing: $ at 1:6",
+            lex_this(r#""ab {$}""#).unwrap_err().to_string()
+        );
+    }
+
+    fn lex_this(input: &'static str) -> Result<Vec<Lexeme<'static>>, Error> {
         let reader = Box::new(StrReader::new(input));
-        Lexer::new(reader).read_any()
+        Ok(Lexer::new(reader)
+            .read_any()?
+            .into_iter()
+            .map(|(lexeme, _)| lexeme)
+            .collect())
     }
 }
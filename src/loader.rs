@@ -0,0 +1,385 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use typed_arena::Arena;
+
+use crate::ast::*;
+use crate::lexer::*;
+use crate::parser::*;
+use crate::shared::*;
+use crate::source_reader::*;
+
+/**
+ * Resolves `import "path";` statements of a program. Every imported file becomes a module named
+ * after the file stem, and is included once (before the code importing it) however many times it
+ * is imported. Import paths are looked up relative to the importing file first, then in the
+ * search paths.
+ *
+ * Only functions are namespaced by their module: structs, enums and constants share one namespace,
+ * so defining one of them in more than one place is an error.
+ */
+pub struct Loader {
+    search_paths: Vec<PathBuf>,
+    // Sources and module names of the loaded files, the programs borrow from them.
+    sources: Arena<String>,
+}
+
+impl Loader {
+    pub fn new(search_paths: Vec<PathBuf>) -> Loader {
+        Loader {
+            search_paths,
+            sources: Arena::new(),
+        }
+    }
+
+    pub fn load_file<'a>(&'a self, path: &Path) -> Result<AstProgram<'a>, Error> {
+        let path = canonical_path(path)?;
+
+        let mut load = Load::new(self);
+        load.loading.push(path.clone());
+        let statements = load.load_statements(&path)?;
+        load.loading.pop();
+
+        load.link(statements)
+    }
+
+    pub fn load_str<'a>(&'a self, source: &'a str) -> Result<AstProgram<'a>, Error> {
+        let mut load = Load::new(self);
+        let statements = load.resolve_imports(parse(source)?, None)?;
+        load.link(statements)
+    }
+
+    fn find(&self, import: &str, dir: Option<&Path>) -> Result<PathBuf, Error> {
+        dir.into_iter()
+            .chain(self.search_paths.iter().map(|path| path.as_path()))
+            .map(|base| base.join(import))
+            .find(|path| path.is_file())
+            .ok_or(format!("Cannot find import '{}'", import).into())
+            .and_then(|path| canonical_path(&path))
+    }
+}
+
+/**
+ * The state of loading one program.
+ */
+struct Load<'a> {
+    loader: &'a Loader,
+    // Module name of the already loaded files.
+    loaded: HashMap<PathBuf, String>,
+    // Files being loaded, to detect import cycles.
+    loading: Vec<PathBuf>,
+    modules: Vec<AstStatement<'a>>,
+}
+
+impl<'a> Load<'a> {
+    fn new(loader: &'a Loader) -> Load<'a> {
+        Load {
+            loader,
+            loaded: HashMap::new(),
+            loading: vec![],
+            modules: vec![],
+        }
+    }
+
+    fn link(self, statements: Vec<AstStatement<'a>>) -> Result<AstProgram<'a>, Error> {
+        let mut all_statements = self.modules;
+        all_statements.extend(statements);
+
+        check_unique_type_and_const_names(&all_statements)?;
+
+        Ok(AstProgram {
+            statements: all_statements,
+        })
+    }
+
+    fn load_statements(&mut self, path: &Path) -> Result<Vec<AstStatement<'a>>, Error> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|err| format!("Cannot read '{}': {}", path.display(), err))?;
+        let source = self.loader.sources.alloc(source).as_str();
+
+        let statements =
+            parse(source).map_err(|err| format!("In '{}': {}", path.display(), err))?;
+
+        self.resolve_imports(statements, path.parent())
+    }
+
+    fn resolve_imports(
+        &mut self,
+        statements: Vec<AstStatement<'a>>,
+        dir: Option<&Path>,
+    ) -> Result<Vec<AstStatement<'a>>, Error> {
+        let mut rest = vec![];
+
+        for statement in statements {
+            match statement {
+                AstStatement::Import(import) => {
+                    let path = self.loader.find(import, dir)?;
+                    self.load_module(path)?;
+                }
+                other => rest.push(other),
+            }
+        }
+
+        Ok(rest)
+    }
+
+    fn load_module(&mut self, path: PathBuf) -> Result<(), Error> {
+        if let Some(pos) = self.loading.iter().position(|loading| loading == &path) {
+            let cycle = self.loading[pos..]
+                .iter()
+                .chain(std::iter::once(&path))
+                .map(|path| path.display().to_string())
+                .collect::<Vec<String>>();
+            return Err(format!("Import cycle: {}", cycle.join(" -> ")).into());
+        }
+
+        if self.loaded.contains_key(&path) {
+            return Ok(());
+        }
+
+        let name = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .ok_or(format!("Invalid module file name '{}'", path.display()))?
+            .to_string();
+        if self.loaded.values().any(|loaded_name| loaded_name == &name) {
+            return Err(format!("Duplicate module '{}'", name).into());
+        }
+
+        self.loading.push(path.clone());
+        let statements = self.load_statements(&path)?;
+        self.loading.pop();
+
+        if statements
+            .iter()
+            .any(|statement| matches!(statement, AstStatement::BlockLine(_)))
+        {
+            return Err(format!("Module '{}' may only contain definitions", name).into());
+        }
+
+        self.loaded.insert(path, name.clone());
+        self.modules.push(AstStatement::Module {
+            name: self.loader.sources.alloc(name).as_str(),
+            statements,
+        });
+
+        Ok(())
+    }
+}
+
+/**
+ * Structs, enums and constants are not namespaced, so the same name in two modules would collide.
+ */
+fn check_unique_type_and_const_names(statements: &[AstStatement]) -> Result<(), Error> {
+    let mut defined: HashMap<&str, &str> = HashMap::new();
+    let mut definitions = vec![];
+    for statement in statements {
+        match statement {
+            AstStatement::Module { name, statements } => {
+                definitions.extend(statements.iter().map(|statement| (*name, statement)))
+            }
+            statement => definitions.push(("main", statement)),
+        }
+    }
+
+    for (module, statement) in definitions {
+        let name = match statement {
+            AstStatement::StructDef { name, .. }
+            | AstStatement::EnumDef { name, .. }
+            | AstStatement::ConstDef { name, .. } => *name,
+            _ => continue,
+        };
+        if let Some(other) = defined.insert(name, module) {
+            return Err(
+                format!("'{}' is defined in both '{}' and '{}'", name, other, module).into(),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn canonical_path(path: &Path) -> Result<PathBuf, Error> {
+    path.canonicalize()
+        .map_err(|err| format!("Cannot read '{}': {}", path.display(), err).into())
+}
+
+fn parse(source: &str) -> Result<Vec<AstStatement<'_>>, Error> {
+    let reader = Box::new(StrReader::new(source));
+    let lexemes = Lexer::new(reader).read_any()?;
+    Ok(Parser::new(lexemes.into()).build_ast()?.statements)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::interpreter::*;
+    use crate::ir_builder::*;
+    use crate::loader::*;
+    use crate::vm::*;
+
+    #[test]
+    fn test_import() {
+        let dir = TestDir::new(
+            "import",
+            &[
+                (
+                    "main.l2",
+                    r#"import "lib/math.l2"; math::add(1, 2) + math::twice(3);"#,
+                ),
+                (
+                    "lib/math.l2",
+                    "fn add(a, b) { a + b; } fn twice(a) { add(a, a); }",
+                ),
+            ],
+        );
+
+        let loader = Loader::new(vec![]);
+        let program = loader.load_file(&dir.path.join("main.l2"));
+        assert_eq!(
+            Some(ExprResult::Int(9)),
+            Interpreter::new().interpret(program.unwrap()).unwrap()
+        );
+        let program = loader.load_file(&dir.path.join("main.l2"));
+        assert_eq!(
+            Some(Value::Int(9)),
+            VM::new(IRBuilder::new().build(program.unwrap()).unwrap())
+                .run()
+                .unwrap()
+        );
+
+        // Module functions are not visible without the module name.
+        let loader = Loader::new(vec![dir.path.join("lib")]);
+        let program = loader.load_str(r#"import "math.l2"; twice(3);"#).unwrap();
+        assert_eq!(
+            "Missing function",
+            Interpreter::new()
+                .interpret(program)
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn test_same_name_in_modules() {
+        let dir = TestDir::new(
+            "same_name",
+            &[
+                ("a.l2", "fn value() { 1; } fn get() { value(); }"),
+                (
+                    "b.l2",
+                    r#"import "a.l2"; fn value() { 20; } fn get() { value(); }"#,
+                ),
+                ("point_a.l2", "struct Point { x, y }"),
+                ("point_b.l2", "struct Point { x }"),
+                ("limit.l2", "const LIMIT = 3;"),
+            ],
+        );
+        let source = r#"
+            import "a.l2";
+            import "b.l2";
+            fn value() { 300; }
+            a::get() + b::get() + value();
+        "#;
+
+        let loader = Loader::new(vec![dir.path.clone()]);
+        let program = loader.load_str(source).unwrap();
+        assert_eq!(
+            Some(ExprResult::Int(321)),
+            Interpreter::new().interpret(program).unwrap()
+        );
+        let program = loader.load_str(source).unwrap();
+        assert_eq!(
+            Some(Value::Int(321)),
+            VM::new(IRBuilder::new().build(program).unwrap())
+                .run()
+                .unwrap()
+        );
+
+        // Only functions are namespaced.
+        assert_eq!(
+            "'Point' is defined in both 'point_a' and 'point_b'",
+            loader
+                .load_str(r#"import "point_a.l2"; import "point_b.l2";"#)
+                .unwrap_err()
+                .to_string()
+        );
+        assert_eq!(
+            "'LIMIT' is defined in both 'limit' and 'main'",
+            loader
+                .load_str(r#"import "limit.l2"; const LIMIT = 4;"#)
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn test_import_errors() {
+        let dir = TestDir::new(
+            "errors",
+            &[
+                ("cycle_a.l2", r#"import "cycle_b.l2";"#),
+                ("cycle_b.l2", r#"import "cycle_a.l2";"#),
+                ("code.l2", "1;"),
+            ],
+        );
+        let loader = Loader::new(vec![dir.path.clone()]);
+
+        let err = loader
+            .load_str(r#"import "cycle_a.l2";"#)
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("Import cycle: "), "{}", err);
+        assert!(err.ends_with("cycle_a.l2"), "{}", err);
+
+        assert_eq!(
+            "Cannot find import 'missing.l2'",
+            loader
+                .load_str(r#"import "missing.l2";"#)
+                .unwrap_err()
+                .to_string()
+        );
+        assert_eq!(
+            "Module 'code' may only contain definitions",
+            loader
+                .load_str(r#"import "code.l2";"#)
+                .unwrap_err()
+                .to_string()
+        );
+        assert_eq!(
+            "Missing function 'code::f'",
+            Interpreter::new()
+                .interpret(loader.load_str("code::f();").unwrap())
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    /**
+     * Source files in a temporary directory, removed when dropped.
+     */
+    struct TestDir {
+        path: PathBuf,
+    }
+
+    impl TestDir {
+        fn new(name: &str, files: &[(&str, &str)]) -> TestDir {
+            let path = std::env::temp_dir().join(format!(
+                "langtwo_loader_{}_{}",
+                std::process::id(),
+                name
+            ));
+            for (file, source) in files {
+                let file = path.join(file);
+                std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+                std::fs::write(file, source).unwrap();
+            }
+            TestDir { path }
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.path);
+        }
+    }
+}
@@ -7,35 +7,61 @@ mod interpreter;
 mod ir;
 mod ir_builder;
 mod lexer;
+mod loader;
 mod parser;
 mod shared;
 mod source_reader;
+mod type_checker;
 mod vm;
 
+use std::path::PathBuf;
+
 use crate::ast::AstDump;
 use crate::interpreter::*;
 use crate::ir_builder::*;
-use crate::lexer::*;
-use crate::parser::*;
-use crate::source_reader::*;
+use crate::loader::*;
+use crate::type_checker::*;
+use crate::vm::*;
 
 fn print_help_and_exit() {
-    panic!("Call: `./bin interpret` or `./bin ir`");
+    panic!("Call: `./bin interpret|ir [FILE] [--path DIR]...`");
+}
+
+fn interpret_file(loader: &Loader, file: &str) {
+    let ast_root = loader.load_file(&PathBuf::from(file)).unwrap();
+
+    TypeChecker::new().check(&ast_root).unwrap();
+
+    let mut interpreter = Interpreter::new();
+    let interpret_result = interpreter.interpret(ast_root);
+    dbg!(&interpret_result);
 }
 
-fn interpret_example() {
-    let reader = Box::new(StrReader::new(
-        r#"
+fn ir_file(loader: &Loader, file: &str) {
+    let ast_root = loader.load_file(&PathBuf::from(file)).unwrap();
+
+    let mut ir = IRBuilder::new();
+    let ir_result = ir.build(ast_root);
+    dbg!(&ir_result);
+
+    let mut vm = VM::new(ir_result.unwrap());
+    let vm_result = vm.run();
+    dbg!(&vm_result);
+}
+
+fn interpret_example(loader: &Loader) {
+    let ast_root = loader
+        .load_str(
+            r#"
 fn fizzbuzz(i, limit) {
-    print(i);
-    print(" ");
+    word = "";
     if (i % 3 == 0) {
-        print("fizz");
+        word += "fizz";
     }
     if (i % 5 == 0) {
-        print("buzz");
+        word += "buzz";
     }
-    print(" ");
+    println(i, word);
 
     if (i < limit) {
         fizzbuzz(i + 1, limit);
@@ -46,32 +72,27 @@ fn fizzbuzz(i, limit) {
 
 fizzbuzz(1, 100);
 "#,
-    ));
-    let lex_result = Lexer::new(reader).read_any();
-    dbg!(&lex_result);
-
-    let mut parser = Parser::new(lex_result.unwrap().into());
-    let ast_root = parser.build_ast().unwrap();
+        )
+        .unwrap();
     dbg!(&ast_root);
 
     println!("---\n\n{}\n\n---", ast_root.ast_dump(0));
 
+    TypeChecker::new().check(&ast_root).unwrap();
+
     let mut interpreter = Interpreter::new();
     let interpret_result = interpreter.interpret(ast_root);
     dbg!(&interpret_result);
 }
 
-fn ir_example() {
-    let reader = Box::new(StrReader::new(
-        r#"
+fn ir_example(loader: &Loader) {
+    let ast_root = loader
+        .load_str(
+            r#"
         1 + 2;
 "#,
-    ));
-    let lex_result = Lexer::new(reader).read_any();
-    dbg!(&lex_result);
-
-    let mut parser = Parser::new(lex_result.unwrap().into());
-    let ast_root = parser.build_ast().unwrap();
+        )
+        .unwrap();
     dbg!(&ast_root);
 
     println!("---\n\n{}\n\n---", ast_root.ast_dump(0));
@@ -79,21 +100,42 @@ fn ir_example() {
     let mut ir = IRBuilder::new();
     let ir_result = ir.build(ast_root);
     dbg!(&ir_result);
+
+    let mut vm = VM::new(ir_result.unwrap());
+    let vm_result = vm.run();
+    dbg!(&vm_result);
 }
 
 fn main() {
     pretty_env_logger::init();
     info!("Start LangTwo");
 
-    let args = std::env::args();
-    if args.len() != 2 {
-        eprintln!("Expected 1 argument.");
+    let mut args = std::env::args().skip(1);
+    let mode = args.next().unwrap_or_else(|| {
+        eprintln!("Expected a mode.");
         print_help_and_exit();
+        unreachable!()
+    });
+
+    let mut file = None;
+    let mut search_paths = vec![];
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--path" => match args.next() {
+                Some(path) => search_paths.push(PathBuf::from(path)),
+                None => print_help_and_exit(),
+            },
+            _ if file.is_none() => file = Some(arg),
+            _ => print_help_and_exit(),
+        }
     }
 
-    match args.into_iter().last().unwrap().as_str() {
-        "interpret" => interpret_example(),
-        "ir" => ir_example(),
+    let loader = Loader::new(search_paths);
+    match (mode.as_str(), file) {
+        ("interpret", None) => interpret_example(&loader),
+        ("ir", None) => ir_example(&loader),
+        ("interpret", Some(file)) => interpret_file(&loader, &file),
+        ("ir", Some(file)) => ir_file(&loader, &file),
         _ => print_help_and_exit(),
     };
 }
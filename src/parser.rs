@@ -28,11 +28,20 @@ macro_rules! assert_lexeme {
 
 pub struct Parser<'s> {
     lexemes: VecDeque<Lexeme<'s>>,
+    // Where each of the lexemes starts, popped along with them.
+    locs: VecDeque<Loc>,
+    // Labels of the enclosing loops, so `break 'label;` can be checked while parsing.
+    loop_labels: Vec<Option<&'s str>>,
 }
 
 impl<'s> Parser<'s> {
-    pub fn new(lexemes: VecDeque<Lexeme<'s>>) -> Parser<'s> {
-        Parser { lexemes }
+    pub fn new(lexemes: VecDeque<(Lexeme<'s>, Loc)>) -> Parser<'s> {
+        let (lexemes, locs) = lexemes.into_iter().unzip();
+        Parser {
+            lexemes,
+            locs,
+            loop_labels: vec![],
+        }
     }
 
     pub fn build_ast(&mut self) -> Result<AstProgram<'s>, Error> {
@@ -57,6 +66,10 @@ impl<'s> Parser<'s> {
 
         match self.peek() {
             Some(&Lexeme::Fn) => self.build_fn_def(),
+            Some(&Lexeme::Struct) => self.build_struct_def(),
+            Some(&Lexeme::Enum) => self.build_enum_def(),
+            Some(&Lexeme::Import) => self.build_import(),
+            Some(&Lexeme::Const) => self.build_const_def(),
             Some(_) => Ok(AstStatement::BlockLine(self.build_block_line()?)),
             None => Err("Reached end before reading statement".into()),
         }
@@ -80,10 +93,37 @@ impl<'s> Parser<'s> {
             // Pattern match to skip args.
         } else {
             loop {
-                match self.pop() {
-                    Some(Lexeme::Name(name)) => args.push(name),
+                let variadic = matches!(self.peek(), Some(Lexeme::Ellipsis));
+                if variadic {
+                    self.pop();
+                }
+                let name = match self.pop() {
+                    Some(Lexeme::Name(name)) => name,
                     _ => return Err("Expected argument name".into()),
                 };
+                let ty = self.build_optional_type_annotation()?;
+                let mut default = None;
+                if let Some(Lexeme::Assign) = self.peek() {
+                    if variadic {
+                        return Err(format!(
+                            "Variadic argument '{}' cannot have a default value",
+                            name
+                        )
+                        .into());
+                    }
+                    self.pop();
+                    default = Some(self.build_expr()?);
+                }
+                args.push(AstFnArg {
+                    name,
+                    ty,
+                    default,
+                    variadic,
+                });
+
+                if variadic && self.peek() != Some(&Lexeme::ParenClose) {
+                    return Err("Only the last argument can be variadic".into());
+                }
 
                 if let Some(Lexeme::Comma) = self.peek() {
                     self.pop();
@@ -96,203 +136,201 @@ impl<'s> Parser<'s> {
 
         assert_lexeme!(self, Lexeme::ParenClose, "Expected paren close");
 
+        let mut ret = None;
+        if let Some(Lexeme::Arrow) = self.peek() {
+            self.pop();
+            ret = Some(self.build_type()?);
+        }
+
         let block = self.build_block()?;
+        check_yield_positions(&block)?;
 
-        Ok(AstStatement::FnDef { name, args, block })
+        Ok(AstStatement::FnDef {
+            name,
+            args,
+            ret,
+            block,
+        })
     }
 
-    fn build_block_line(&mut self) -> Result<AstBlockLine<'s>, Error> {
-        debug!("Build: block line");
+    /**
+     * Reads `: type` if present.
+     */
+    fn build_optional_type_annotation(&mut self) -> Result<Option<AstType<'s>>, Error> {
+        if let Some(Lexeme::Colon) = self.peek() {
+            self.pop();
+            Ok(Some(self.build_type()?))
+        } else {
+            Ok(None)
+        }
+    }
 
-        let line = match self.peek() {
-            Some(Lexeme::Loop) => {
-                assert_lexeme!(self, Lexeme::Loop, "Expected keyword loop");
-                let loop_block = self.build_block()?;
-                AstBlockLine::Loop(loop_block)
+    fn build_type(&mut self) -> Result<AstType<'s>, Error> {
+        debug!("Build: type");
+
+        match self.pop() {
+            Some(Lexeme::Name("int")) => Ok(AstType::Int),
+            Some(Lexeme::Name("float")) => Ok(AstType::Float),
+            Some(Lexeme::Name("str")) => Ok(AstType::Str),
+            Some(Lexeme::Name("bool")) => Ok(AstType::Bool),
+            Some(Lexeme::Name("any")) => Ok(AstType::Any),
+            Some(Lexeme::Name(name)) if is_type_name(name) => Ok(AstType::Named(name)),
+            Some(Lexeme::Name(name)) => Err(format!("Unknown type '{}'", name).into()),
+            Some(Lexeme::BracketOpen) => {
+                let item_type = self.build_type()?;
+                assert_lexeme!(self, Lexeme::BracketClose, "Expected bracket close");
+                Ok(AstType::Array(Box::new(item_type)))
             }
-            Some(Lexeme::Break) => {
-                assert_lexeme!(self, Lexeme::Break, "Expected keyword break");
-                assert_lexeme!(self, Lexeme::Semicolon, "Expected semicolon");
-                AstBlockLine::Break
+            Some(Lexeme::BraceOpen) => {
+                let value_type = self.build_type()?;
+                assert_lexeme!(self, Lexeme::BraceClose, "Expected brace close");
+                Ok(AstType::Map(Box::new(value_type)))
             }
-            _ => {
-                let expr = self.build_expr()?;
+            Some(Lexeme::ParenOpen) => {
+                let mut item_types = vec![self.build_type()?];
+                while let Some(Lexeme::Comma) = self.peek() {
+                    self.pop();
+                    item_types.push(self.build_type()?);
+                }
+                assert_lexeme!(self, Lexeme::ParenClose, "Expected paren close");
+                Ok(AstType::Tuple(item_types))
+            }
+            _ => Err("Expected type".into()),
+        }
+    }
 
-                // Does it need a semicolon?
-                match expr {
-                    AstExpr::If { .. } => {}
-                    _ => {
-                        assert_lexeme!(self, Lexeme::Semicolon, "Expected semicolon");
-                    }
-                };
+    fn build_import(&mut self) -> Result<AstStatement<'s>, Error> {
+        debug!("Build: import");
 
-                AstBlockLine::Expr(expr)
-            }
+        assert_lexeme!(self, Lexeme::Import, "Expected keyword import");
+
+        let path = match self.pop() {
+            Some(Lexeme::Str(path)) => path,
+            _ => return Err("Expected import path".into()),
         };
 
-        Ok(line)
-    }
+        assert_lexeme!(self, Lexeme::Semicolon, "Expected semicolon after import");
 
-    fn build_expr(&mut self) -> Result<AstExpr<'s>, Error> {
-        debug!("Build: expr");
+        Ok(AstStatement::Import(path))
+    }
 
-        let expr = match self.peek() {
-            Some(Lexeme::Int(_)) => self.build_expr_int(),
-            Some(Lexeme::Str(_)) => self.build_expr_str(),
-            Some(Lexeme::True) => {
-                self.pop();
-                Ok(AstExpr::Boolean(true))
-            }
-            Some(Lexeme::False) => {
-                self.pop();
-                Ok(AstExpr::Boolean(false))
-            }
-            Some(Lexeme::Name(_)) => match self.peekn(1) {
-                Some(Lexeme::ParenOpen) => self.build_expr_fn_call(),
-                Some(Lexeme::Assign) => self.build_expr_assignment(),
-                _ => self.build_expr_name(),
-            },
-            Some(Lexeme::If) => self.build_expr_if(),
-            Some(Lexeme::ParenOpen) => self.build_expr_paren_expr(),
-            _ => Err("Cannot build expression".into()),
-        }?;
+    fn build_const_def(&mut self) -> Result<AstStatement<'s>, Error> {
+        debug!("Build: const def");
 
-        match self.peek() {
-            Some(Lexeme::Op(op)) => {
-                let op = op.clone();
-                self.pop();
-                let rhs = self.build_expr()?;
+        assert_lexeme!(self, Lexeme::Const, "Expected keyword const");
 
-                Ok(self.reorder_binop_precedence(AstExpr::BinOp {
-                    lhs: Box::new(expr),
-                    op,
-                    rhs: Box::new(rhs),
-                }))
-            }
-            _ => Ok(expr),
-        }
-    }
+        let name = match self.pop() {
+            Some(Lexeme::Name(name)) => name,
+            _ => return Err("Expected constant name".into()),
+        };
 
-    fn build_expr_paren_expr(&mut self) -> Result<AstExpr<'s>, Error> {
-        assert_lexeme!(self, Lexeme::ParenOpen, "Expected paren open");
+        assert_lexeme!(self, Lexeme::Assign, "Expected assign");
 
         let expr = self.build_expr()?;
 
-        assert_lexeme!(self, Lexeme::ParenClose, "Expected paren close");
+        assert_lexeme!(self, Lexeme::Semicolon, "Expected semicolon after constant");
 
-        Ok(AstExpr::ParenExpr(Box::new(expr)))
+        Ok(AstStatement::ConstDef { name, expr })
     }
 
-    fn build_expr_if(&mut self) -> Result<AstExpr<'s>, Error> {
-        debug!("Build: expr/if");
-
-        assert_lexeme!(self, Lexeme::If, "Expected keyword if");
-        assert_lexeme!(self, Lexeme::ParenOpen, "Expected paren open");
+    fn build_struct_def(&mut self) -> Result<AstStatement<'s>, Error> {
+        debug!("Build: struct def");
 
-        let cond = self.build_expr()?;
+        assert_lexeme!(self, Lexeme::Struct, "Expected keyword struct");
 
-        assert_lexeme!(self, Lexeme::ParenClose, "Expected paren close");
+        let name = match self.pop() {
+            Some(Lexeme::Name(s)) => s,
+            _ => return Err("Expected struct name".into()),
+        };
 
-        let true_block = self.build_block()?;
+        assert_lexeme!(self, Lexeme::BraceOpen, "Expected brace open");
 
-        let false_block = match self.peek() {
-            Some(Lexeme::Else) => {
-                assert_lexeme!(self, Lexeme::Else, "Expected keyword else");
-                Some(self.build_block()?)
-            }
-            _ => None,
-        };
+        let mut fields = vec![];
 
-        Ok(AstExpr::If {
-            cond: Box::new(cond),
-            true_block,
-            false_block,
-        })
-    }
+        if let Some(Lexeme::BraceClose) = self.peek() {
+            // Struct without fields.
+        } else {
+            loop {
+                match self.pop() {
+                    Some(Lexeme::Name(field)) => fields.push(field),
+                    _ => return Err("Expected field name".into()),
+                };
 
-    fn build_block(&mut self) -> Result<AstBlock<'s>, Error> {
-        assert_lexeme!(self, Lexeme::BraceOpen, "Expected brace open");
+                if let Some(Lexeme::Comma) = self.peek() {
+                    self.pop();
+                    continue;
+                }
 
-        let mut block_lines = vec![];
-        loop {
-            if let Some(&Lexeme::BraceClose) = self.peek() {
                 break;
             }
-
-            let statement = self.build_block_line()?;
-            block_lines.push(statement);
         }
 
         assert_lexeme!(self, Lexeme::BraceClose, "Expected brace close");
 
-        Ok(AstBlock(block_lines))
+        Ok(AstStatement::StructDef { name, fields })
     }
 
-    fn build_expr_assignment(&mut self) -> Result<AstExpr<'s>, Error> {
-        debug!("Build: expr/assign");
-
-        let varname = match self.pop() {
-            Some(Lexeme::Name(name)) => name,
-            _ => return Err("Expected name for assignment".into()),
-        };
-
-        assert_lexeme!(self, Lexeme::Assign, "Expected assign");
+    fn build_enum_def(&mut self) -> Result<AstStatement<'s>, Error> {
+        debug!("Build: enum def");
 
-        let expr = self.build_expr()?;
+        assert_lexeme!(self, Lexeme::Enum, "Expected keyword enum");
 
-        Ok(AstExpr::Assignment {
-            varname,
-            expr: Box::new(expr),
-        })
-    }
+        let name = match self.pop() {
+            Some(Lexeme::Name(s)) => s,
+            _ => return Err("Expected enum name".into()),
+        };
 
-    fn build_expr_int(&mut self) -> Result<AstExpr<'s>, Error> {
-        debug!("Build: expr/int");
+        assert_lexeme!(self, Lexeme::BraceOpen, "Expected brace open");
 
-        match self.pop() {
-            Some(Lexeme::Int(n)) => Ok(AstExpr::Int(n)),
-            _ => Err("Expected integer".into()),
-        }
-    }
+        let mut variants = vec![];
 
-    fn build_expr_str(&mut self) -> Result<AstExpr<'s>, Error> {
-        debug!("Build: expr/str");
+        loop {
+            let variant_name = match self.pop() {
+                Some(Lexeme::Name(variant_name)) => variant_name,
+                _ => return Err("Expected variant name".into()),
+            };
+
+            let fields = match self.peek() {
+                Some(Lexeme::ParenOpen) => self.build_name_list()?,
+                _ => vec![],
+            };
+
+            variants.push(AstEnumVariant {
+                name: variant_name,
+                fields,
+            });
+
+            if let Some(Lexeme::Comma) = self.peek() {
+                self.pop();
+                continue;
+            }
 
-        match self.pop() {
-            Some(Lexeme::Str(s)) => Ok(AstExpr::Str(s)),
-            _ => Err("Expected string".into()),
+            break;
         }
-    }
 
-    fn build_expr_name(&mut self) -> Result<AstExpr<'s>, Error> {
-        debug!("Build: expr/name");
+        assert_lexeme!(self, Lexeme::BraceClose, "Expected brace close");
 
-        match self.pop() {
-            Some(Lexeme::Name(s)) => Ok(AstExpr::Name(s)),
-            _ => Err("Expected name".into()),
-        }
+        Ok(AstStatement::EnumDef { name, variants })
     }
 
-    fn build_expr_fn_call(&mut self) -> Result<AstExpr<'s>, Error> {
-        debug!("Build: expr/fn-call");
-
-        let name = match self.pop() {
-            Some(Lexeme::Name(name)) => name,
-            _ => return Err("Expected name".into()),
-        };
-
+    /**
+     * Reads `(name1, name2, ...)`.
+     */
+    fn build_name_list(&mut self) -> Result<Vec<&'s str>, Error> {
         assert_lexeme!(self, Lexeme::ParenOpen, "Expected paren open");
 
-        let mut args = vec![];
+        let mut names = vec![];
 
         if let Some(Lexeme::ParenClose) = self.peek() {
-            // Just for pattern matching, skip arg collection.
+            // Empty list.
         } else {
             loop {
-                let arg = self.build_expr()?;
-                args.push(arg);
+                match self.pop() {
+                    Some(Lexeme::Name(name)) => names.push(name),
+                    _ => return Err("Expected name".into()),
+                };
 
-                if let Some(&Lexeme::Comma) = self.peek() {
+                if let Some(Lexeme::Comma) = self.peek() {
                     self.pop();
                     continue;
                 }
@@ -303,378 +341,1999 @@ impl<'s> Parser<'s> {
 
         assert_lexeme!(self, Lexeme::ParenClose, "Expected paren close");
 
-        Ok(AstExpr::FnCall { name, args })
+        Ok(names)
     }
 
-    fn reorder_binop_precedence(&self, expr: AstExpr<'s>) -> AstExpr<'s> {
-        match expr {
-            AstExpr::BinOp { lhs, op, rhs } => match *rhs {
-                AstExpr::BinOp {
-                    lhs: rhs_lhs,
-                    op: rhs_op,
-                    rhs: rhs_rhs,
-                } => {
-                    if op.precedence() > rhs_op.precedence() {
-                        // Wrong precendence. Needs to rotate the branches (recursively to the left subtree):
-                        //   1            2
-                        //  / \          / \
-                        // A   2   =>   1   C
-                        //    / \      / \
-                        //   B   C    A   B
-                        AstExpr::BinOp {
-                            lhs: Box::new(self.reorder_binop_precedence(AstExpr::BinOp {
-                                lhs,
-                                op,
-                                rhs: rhs_lhs,
-                            })),
-                            op: rhs_op,
-                            rhs: rhs_rhs,
-                        }
-                    } else {
-                        AstExpr::BinOp {
-                            lhs,
-                            op,
-                            rhs: Box::new(AstExpr::BinOp {
-                                lhs: rhs_lhs,
-                                op: rhs_op,
-                                rhs: rhs_rhs,
-                            }),
-                        }
-                    }
-                }
-                _ => AstExpr::BinOp { lhs, op, rhs },
-            },
-            other => other,
-        }
-    }
+    fn build_block_line(&mut self) -> Result<AstBlockLine<'s>, Error> {
+        debug!("Build: block line");
 
-    fn is_end(&self) -> bool {
-        self.lexemes.is_empty()
-    }
+        let line = match self.peek() {
+            Some(Lexeme::Break) => {
+                assert_lexeme!(self, Lexeme::Break, "Expected keyword break");
+                let label = self.build_loop_label_target()?;
+                let value = match self.peek() {
+                    Some(Lexeme::Semicolon) => None,
+                    _ => Some(self.build_expr()?),
+                };
+                assert_lexeme!(self, Lexeme::Semicolon, "Expected semicolon");
+                AstBlockLine::Break { label, value }
+            }
+            Some(Lexeme::Continue) => {
+                assert_lexeme!(self, Lexeme::Continue, "Expected keyword continue");
+                let label = self.build_loop_label_target()?;
+                assert_lexeme!(self, Lexeme::Semicolon, "Expected semicolon");
+                AstBlockLine::Continue(label)
+            }
+            Some(Lexeme::Throw) => {
+                assert_lexeme!(self, Lexeme::Throw, "Expected keyword throw");
+                let value = self.build_expr()?;
+                assert_lexeme!(self, Lexeme::Semicolon, "Expected semicolon");
+                AstBlockLine::Throw(value)
+            }
+            Some(Lexeme::Yield) => {
+                assert_lexeme!(self, Lexeme::Yield, "Expected keyword yield");
+                let value = self.build_expr()?;
+                assert_lexeme!(self, Lexeme::Semicolon, "Expected semicolon");
+                AstBlockLine::Yield(value)
+            }
+            // Block-like expressions end the line without a semicolon, so a following line
+            // starting with `[` or `(` is not taken as an index or a call on them.
+            Some(Lexeme::If) => AstBlockLine::Expr(self.build_expr_if()?),
+            Some(Lexeme::Match) => AstBlockLine::Expr(self.build_expr_match()?),
+            Some(Lexeme::Loop | Lexeme::For | Lexeme::LoopLabel(_)) => {
+                AstBlockLine::Expr(self.build_expr_loop()?)
+            }
+            Some(Lexeme::Try) => AstBlockLine::Expr(self.build_expr_try()?),
+            Some(Lexeme::BraceOpen) if !self.is_map_literal_start() => {
+                AstBlockLine::Expr(AstExpr::Block(self.build_block()?))
+            }
+            _ => {
+                let expr = self.build_expr()?;
+                assert_lexeme!(self, Lexeme::Semicolon, "Expected semicolon");
+                AstBlockLine::Expr(expr)
+            }
+        };
 
-    fn peek(&self) -> Option<&Lexeme> {
-        self.peekn(0)
+        Ok(line)
     }
 
-    fn peekn(&self, n: usize) -> Option<&Lexeme> {
-        self.lexemes.get(n)
+    /**
+     * `loop { ... }` or `for x in expr { ... }`, optionally labeled: `'outer: loop { ... }`.
+     */
+    fn build_expr_loop(&mut self) -> Result<AstExpr<'s>, Error> {
+        let label = match self.peek() {
+            Some(Lexeme::LoopLabel(label)) => {
+                let label = *label;
+                self.pop();
+                assert_lexeme!(self, Lexeme::Colon, "Expected colon after loop label");
+                Some(label)
+            }
+            _ => None,
+        };
+
+        match self.pop() {
+            Some(Lexeme::Loop) => {
+                let block = self.build_loop_block(label)?;
+                Ok(AstExpr::Loop { label, block })
+            }
+            Some(Lexeme::For) => {
+                let varname = match self.pop() {
+                    Some(Lexeme::Name(name)) => name,
+                    _ => return Err("Expected loop variable name".into()),
+                };
+                assert_lexeme!(self, Lexeme::In, "Expected keyword in");
+                let iter = self.build_expr()?;
+                let block = self.build_loop_block(label)?;
+
+                Ok(AstExpr::For {
+                    label,
+                    varname,
+                    iter: Box::new(iter),
+                    block,
+                })
+            }
+            _ => Err("Expected loop or for after loop label".into()),
+        }
     }
 
-    fn pop(&mut self) -> Option<Lexeme<'s>> {
-        self.lexemes.pop_front()
+    fn build_loop_block(&mut self, label: Option<&'s str>) -> Result<AstBlock<'s>, Error> {
+        self.loop_labels.push(label);
+        let block = self.build_block();
+        self.loop_labels.pop();
+        block
     }
-}
 
-#[cfg(test)]
-mod test {
-    use crate::lexer::*;
-    use crate::parser::*;
-    use crate::source_reader::*;
+    /**
+     * The optional label after `break` or `continue`, which must name an enclosing loop.
+     */
+    fn build_loop_label_target(&mut self) -> Result<Option<&'s str>, Error> {
+        match self.peek() {
+            Some(Lexeme::LoopLabel(label)) => {
+                let label = *label;
+                self.pop();
+                if !self.loop_labels.contains(&Some(label)) {
+                    return Err(format!("Unknown loop label: '{}", label).into());
+                }
+                Ok(Some(label))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn build_expr(&mut self) -> Result<AstExpr<'s>, Error> {
+        debug!("Build: expr");
+
+        let expr = self.build_expr_pipe()?;
+
+        // Ranges bind the weakest: `0..n + 1` is `0..(n + 1)`.
+        let inclusive = match self.peek() {
+            Some(Lexeme::DotDot) => false,
+            Some(Lexeme::DotDotEq) => true,
+            _ => return Ok(expr),
+        };
+        self.pop();
+
+        Ok(AstExpr::Range {
+            from: Box::new(expr),
+            to: Box::new(self.build_expr_pipe()?),
+            inclusive,
+        })
+    }
+
+    /**
+     * `x |> f(y)` is parsed as `f(x, y)`. Pipes bind weaker than binary operators and chain from
+     * the left: `a + 1 |> f() |> g()` is `g(f(a + 1))`. The right of a pipe must be a call, so a
+     * piped result only takes part in an operation in parentheses: `(x |> f()) + 1`.
+     */
+    fn build_expr_pipe(&mut self) -> Result<AstExpr<'s>, Error> {
+        let mut expr = self.build_expr_binop()?;
+
+        while let Some(Lexeme::Pipe) = self.peek() {
+            self.pop();
+            expr = match self.build_expr_operand()? {
+                AstExpr::FnCall {
+                    module,
+                    name,
+                    mut args,
+                    named_args,
+                } => {
+                    args.insert(0, expr);
+                    AstExpr::FnCall {
+                        module,
+                        name,
+                        args,
+                        named_args,
+                    }
+                }
+                _ => return Err("Expected a function call after |>".into()),
+            };
+
+            if let Some(Lexeme::Op(_)) = self.peek() {
+                return Err("Operator after a piped call, wrap the pipe in parentheses".into());
+            }
+        }
+
+        Ok(expr)
+    }
+
+    fn build_expr_binop(&mut self) -> Result<AstExpr<'s>, Error> {
+        let expr = self.build_expr_operand()?;
+
+        match self.peek() {
+            Some(Lexeme::Op(op)) => {
+                let op = op.clone();
+                let loc = self.loc();
+                self.pop();
+                let rhs = self.build_expr_binop()?;
+
+                Ok(self.reorder_binop_precedence(AstExpr::BinOp {
+                    lhs: Box::new(expr),
+                    op,
+                    rhs: Box::new(rhs),
+                    loc,
+                }))
+            }
+            _ => Ok(expr),
+        }
+    }
+
+    /**
+     * An expression without binary operators on its top level.
+     */
+    fn build_expr_operand(&mut self) -> Result<AstExpr<'s>, Error> {
+        let expr = match self.peek() {
+            Some(Lexeme::Int(_)) => self.build_expr_int(),
+            Some(Lexeme::Float(v)) => {
+                let v = *v;
+                self.pop();
+                Ok(AstExpr::Float(v))
+            }
+            Some(Lexeme::Str(_)) => self.build_expr_str(),
+            Some(Lexeme::StrTemplate(_)) => self.build_expr_str_template(),
+            Some(Lexeme::True) => {
+                self.pop();
+                Ok(AstExpr::Boolean(true))
+            }
+            Some(Lexeme::False) => {
+                self.pop();
+                Ok(AstExpr::Boolean(false))
+            }
+            Some(Lexeme::Null) => {
+                self.pop();
+                Ok(AstExpr::Null)
+            }
+            Some(Lexeme::Name(name)) => match self.peekn(1) {
+                Some(Lexeme::ParenOpen) => self.build_expr_fn_call(),
+                Some(Lexeme::Assign) => self.build_expr_assignment(),
+                Some(Lexeme::OpAssign(_)) => self.build_expr_op_assignment(),
+                Some(Lexeme::BraceOpen) if is_type_name(name) => self.build_expr_struct_init(),
+                Some(Lexeme::PathSep) if is_type_name(name) => self.build_expr_enum_variant(),
+                Some(Lexeme::PathSep) => self.build_expr_fn_call(),
+                _ => self.build_expr_name(),
+            },
+            Some(Lexeme::Let) => self.build_expr_let(),
+            Some(Lexeme::If) => self.build_expr_if(),
+            Some(Lexeme::Match) => self.build_expr_match(),
+            Some(Lexeme::Loop | Lexeme::For | Lexeme::LoopLabel(_)) => self.build_expr_loop(),
+            Some(Lexeme::Try) => self.build_expr_try(),
+            Some(Lexeme::ParenOpen) => self.build_expr_paren_expr(),
+            Some(Lexeme::BracketOpen) => self.build_expr_array(),
+            Some(Lexeme::BraceOpen) if self.is_map_literal_start() => self.build_expr_map(),
+            Some(Lexeme::BraceOpen) => Ok(AstExpr::Block(self.build_block()?)),
+            Some(Lexeme::Tilde) => self.build_expr_bit_not(),
+            _ => Err("Cannot build expression".into()),
+        }?;
+
+        self.build_expr_postfix(expr)
+    }
+
+    /**
+     * `~` binds tighter than any binary operator: `~a & b` is `(~a) & b`.
+     */
+    fn build_expr_bit_not(&mut self) -> Result<AstExpr<'s>, Error> {
+        assert_lexeme!(self, Lexeme::Tilde, "Expected '~'");
+        Ok(AstExpr::BitNot(Box::new(self.build_expr_operand()?)))
+    }
+
+    fn build_expr_postfix(&mut self, expr: AstExpr<'s>) -> Result<AstExpr<'s>, Error> {
+        let mut expr = expr;
+
+        loop {
+            expr = match self.peek() {
+                Some(Lexeme::BracketOpen) => self.build_expr_index(expr)?,
+                Some(Lexeme::Dot) => self.build_expr_field(expr)?,
+                _ => break,
+            };
+        }
+
+        Ok(expr)
+    }
+
+    fn build_expr_index(&mut self, expr: AstExpr<'s>) -> Result<AstExpr<'s>, Error> {
+        debug!("Build: expr/index");
+
+        assert_lexeme!(self, Lexeme::BracketOpen, "Expected bracket open");
+        let index = self.build_expr()?;
+        assert_lexeme!(self, Lexeme::BracketClose, "Expected bracket close");
+
+        if let Some(Lexeme::Assign) = self.peek() {
+            assert_lexeme!(self, Lexeme::Assign, "Expected assign");
+            let value = self.build_expr()?;
+
+            return Ok(AstExpr::IndexAssignment {
+                expr: Box::new(expr),
+                index: Box::new(index),
+                value: Box::new(value),
+            });
+        }
+
+        Ok(AstExpr::Index {
+            expr: Box::new(expr),
+            index: Box::new(index),
+        })
+    }
+
+    fn build_expr_field(&mut self, expr: AstExpr<'s>) -> Result<AstExpr<'s>, Error> {
+        debug!("Build: expr/field");
+
+        assert_lexeme!(self, Lexeme::Dot, "Expected dot");
+
+        let field = match self.pop() {
+            Some(Lexeme::Name(field)) => field,
+            _ => return Err("Expected field name".into()),
+        };
+
+        if let Some(Lexeme::Assign) = self.peek() {
+            assert_lexeme!(self, Lexeme::Assign, "Expected assign");
+            let value = self.build_expr()?;
+
+            return Ok(AstExpr::FieldAssignment {
+                expr: Box::new(expr),
+                field,
+                value: Box::new(value),
+            });
+        }
+
+        Ok(AstExpr::Field {
+            expr: Box::new(expr),
+            field,
+        })
+    }
+
+    fn build_expr_struct_init(&mut self) -> Result<AstExpr<'s>, Error> {
+        debug!("Build: expr/struct-init");
+
+        let name = match self.pop() {
+            Some(Lexeme::Name(name)) => name,
+            _ => return Err("Expected struct name".into()),
+        };
+
+        assert_lexeme!(self, Lexeme::BraceOpen, "Expected brace open");
+
+        let mut fields = vec![];
+
+        if let Some(Lexeme::BraceClose) = self.peek() {
+            // Struct without fields.
+        } else {
+            loop {
+                let field = match self.pop() {
+                    Some(Lexeme::Name(field)) => field,
+                    _ => return Err("Expected field name".into()),
+                };
+                assert_lexeme!(self, Lexeme::Colon, "Expected colon");
+                let value = self.build_expr()?;
+                fields.push((field, value));
+
+                if let Some(&Lexeme::Comma) = self.peek() {
+                    self.pop();
+                    continue;
+                }
+
+                break;
+            }
+        }
+
+        assert_lexeme!(self, Lexeme::BraceClose, "Expected brace close");
+
+        Ok(AstExpr::StructInit { name, fields })
+    }
+
+    fn build_expr_enum_variant(&mut self) -> Result<AstExpr<'s>, Error> {
+        debug!("Build: expr/enum-variant");
+
+        let (enum_name, variant) = self.build_variant_path()?;
+
+        let args = match self.peek() {
+            Some(Lexeme::ParenOpen) => {
+                let (args, named_args) = self.build_call_args()?;
+                if !named_args.is_empty() {
+                    return Err("Enum variants do not take named values".into());
+                }
+                args
+            }
+            _ => vec![],
+        };
+
+        Ok(AstExpr::EnumVariant {
+            enum_name,
+            variant,
+            args,
+        })
+    }
+
+    /**
+     * Reads `Enum::Variant`.
+     */
+    fn build_variant_path(&mut self) -> Result<(&'s str, &'s str), Error> {
+        let enum_name = match self.pop() {
+            Some(Lexeme::Name(name)) => name,
+            _ => return Err("Expected enum name".into()),
+        };
+
+        assert_lexeme!(self, Lexeme::PathSep, "Expected path separator");
+
+        let variant = match self.pop() {
+            Some(Lexeme::Name(name)) => name,
+            _ => return Err("Expected variant name".into()),
+        };
+
+        Ok((enum_name, variant))
+    }
+
+    fn build_expr_match(&mut self) -> Result<AstExpr<'s>, Error> {
+        debug!("Build: expr/match");
+
+        assert_lexeme!(self, Lexeme::Match, "Expected keyword match");
+        assert_lexeme!(self, Lexeme::ParenOpen, "Expected paren open");
+
+        let expr = self.build_expr()?;
+
+        assert_lexeme!(self, Lexeme::ParenClose, "Expected paren close");
+        assert_lexeme!(self, Lexeme::BraceOpen, "Expected brace open");
+
+        let mut arms = vec![];
+        loop {
+            if let Some(&Lexeme::BraceClose) = self.peek() {
+                break;
+            }
+
+            let pattern = self.build_pattern()?;
+            assert_lexeme!(self, Lexeme::FatArrow, "Expected fat arrow");
+            let block = self.build_block()?;
+
+            arms.push(AstMatchArm { pattern, block });
+        }
+
+        assert_lexeme!(self, Lexeme::BraceClose, "Expected brace close");
+
+        Ok(AstExpr::Match {
+            expr: Box::new(expr),
+            arms,
+        })
+    }
+
+    fn build_pattern(&mut self) -> Result<AstPattern<'s>, Error> {
+        debug!("Build: pattern");
+
+        match self.peek() {
+            Some(Lexeme::Name(name)) if is_type_name(name) => {
+                let (enum_name, variant) = self.build_variant_path()?;
+
+                let bindings = match self.peek() {
+                    Some(Lexeme::ParenOpen) => self.build_name_list()?,
+                    _ => vec![],
+                };
+
+                return Ok(AstPattern::Variant {
+                    enum_name,
+                    variant,
+                    bindings,
+                });
+            }
+            _ => {}
+        };
+
+        match self.pop() {
+            Some(Lexeme::Name("_")) => Ok(AstPattern::Wildcard),
+            Some(Lexeme::Name(name)) => Ok(AstPattern::Binding(name)),
+            Some(Lexeme::Int(v)) => Ok(AstPattern::Int(v)),
+            Some(Lexeme::Str(s)) => Ok(AstPattern::Str(s)),
+            Some(Lexeme::True) => Ok(AstPattern::Boolean(true)),
+            Some(Lexeme::False) => Ok(AstPattern::Boolean(false)),
+            other => Err(format!("Invalid pattern: {:?}", other).into()),
+        }
+    }
+
+    fn build_expr_array(&mut self) -> Result<AstExpr<'s>, Error> {
+        debug!("Build: expr/array");
+
+        assert_lexeme!(self, Lexeme::BracketOpen, "Expected bracket open");
+
+        let mut items = vec![];
+
+        if let Some(Lexeme::BracketClose) = self.peek() {
+            // Empty array, nothing to collect.
+        } else {
+            loop {
+                items.push(self.build_expr()?);
+
+                if let Some(&Lexeme::Comma) = self.peek() {
+                    self.pop();
+                    continue;
+                }
+
+                break;
+            }
+        }
+
+        assert_lexeme!(self, Lexeme::BracketClose, "Expected bracket close");
+
+        Ok(AstExpr::Array(items))
+    }
+
+    fn build_expr_map(&mut self) -> Result<AstExpr<'s>, Error> {
+        debug!("Build: expr/map");
+
+        assert_lexeme!(self, Lexeme::BraceOpen, "Expected brace open");
+
+        let mut entries = vec![];
+
+        if let Some(Lexeme::BraceClose) = self.peek() {
+            // Empty map, nothing to collect.
+        } else {
+            loop {
+                let key = self.build_expr()?;
+                assert_lexeme!(self, Lexeme::Colon, "Expected colon");
+                let value = self.build_expr()?;
+                entries.push((key, value));
+
+                if let Some(&Lexeme::Comma) = self.peek() {
+                    self.pop();
+                    continue;
+                }
+
+                break;
+            }
+        }
+
+        assert_lexeme!(self, Lexeme::BraceClose, "Expected brace close");
+
+        Ok(AstExpr::Map(entries))
+    }
+
+    /**
+     * A brace opens a map literal (instead of a block) when it is empty: `{}` or when it starts
+     * with a literal or variable key and a colon: `{"key": ...`, `{12: ...` or `{key: ...`.
+     */
+    fn is_map_literal_start(&self) -> bool {
+        matches!(
+            (self.peekn(0), self.peekn(1), self.peekn(2)),
+            (Some(Lexeme::BraceOpen), Some(Lexeme::BraceClose), _)
+                | (
+                    Some(Lexeme::BraceOpen),
+                    Some(Lexeme::Str(_) | Lexeme::Int(_) | Lexeme::Name(_)),
+                    Some(Lexeme::Colon)
+                )
+        )
+    }
+
+    /**
+     * `(expr)`, or a tuple when there is a comma: `(a, b)`, `(a,)`. A tuple of names followed by
+     * `=` is a destructuring assignment.
+     */
+    fn build_expr_paren_expr(&mut self) -> Result<AstExpr<'s>, Error> {
+        assert_lexeme!(self, Lexeme::ParenOpen, "Expected paren open");
+
+        let expr = self.build_expr()?;
+
+        if let Some(Lexeme::ParenClose) = self.peek() {
+            self.pop();
+            return Ok(AstExpr::ParenExpr(Box::new(expr)));
+        }
+
+        let mut items = vec![expr];
+        loop {
+            assert_lexeme!(self, Lexeme::Comma, "Expected comma");
+            if let Some(Lexeme::ParenClose) = self.peek() {
+                break;
+            }
+            items.push(self.build_expr()?);
+            if let Some(Lexeme::ParenClose) = self.peek() {
+                break;
+            }
+        }
+        assert_lexeme!(self, Lexeme::ParenClose, "Expected paren close");
+
+        if let Some(Lexeme::Assign) = self.peek() {
+            return self.build_expr_tuple_assignment(items);
+        }
+
+        Ok(AstExpr::Tuple(items))
+    }
+
+    fn build_expr_tuple_assignment(
+        &mut self,
+        items: Vec<AstExpr<'s>>,
+    ) -> Result<AstExpr<'s>, Error> {
+        debug!("Build: expr/tuple assignment");
+
+        let varnames = items
+            .into_iter()
+            .map(|item| match item {
+                AstExpr::Name(name) => Ok(name),
+                _ => Err("Only names can be destructured".into()),
+            })
+            .collect::<Result<Vec<&str>, Error>>()?;
+
+        assert_lexeme!(self, Lexeme::Assign, "Expected assign");
+        let expr = self.build_expr()?;
+
+        Ok(AstExpr::TupleAssignment {
+            varnames,
+            expr: Box::new(expr),
+        })
+    }
+
+    fn build_expr_try(&mut self) -> Result<AstExpr<'s>, Error> {
+        debug!("Build: expr/try");
+
+        assert_lexeme!(self, Lexeme::Try, "Expected keyword try");
+        let block = self.build_block()?;
+
+        assert_lexeme!(self, Lexeme::Catch, "Expected keyword catch");
+        assert_lexeme!(self, Lexeme::ParenOpen, "Expected paren open");
+        let catch_var = match self.pop() {
+            Some(Lexeme::Name(name)) => name,
+            _ => return Err("Expected catch variable name".into()),
+        };
+        assert_lexeme!(self, Lexeme::ParenClose, "Expected paren close");
+        let catch_block = self.build_block()?;
+
+        Ok(AstExpr::Try {
+            block,
+            catch_var,
+            catch_block,
+        })
+    }
+
+    /**
+     * `else if` is sugar for an `else` block holding just the nested `if`.
+     */
+    fn build_expr_if(&mut self) -> Result<AstExpr<'s>, Error> {
+        debug!("Build: expr/if");
+
+        assert_lexeme!(self, Lexeme::If, "Expected keyword if");
+        assert_lexeme!(self, Lexeme::ParenOpen, "Expected paren open");
+
+        let cond = self.build_expr()?;
+
+        assert_lexeme!(self, Lexeme::ParenClose, "Expected paren close");
+
+        let true_block = self.build_block()?;
+
+        let false_block = match self.peek() {
+            Some(Lexeme::Else) => {
+                assert_lexeme!(self, Lexeme::Else, "Expected keyword else");
+                match self.peek() {
+                    Some(Lexeme::If) => {
+                        Some(AstBlock(vec![AstBlockLine::Expr(self.build_expr_if()?)]))
+                    }
+                    _ => Some(self.build_block()?),
+                }
+            }
+            _ => None,
+        };
+
+        Ok(AstExpr::If {
+            cond: Box::new(cond),
+            true_block,
+            false_block,
+        })
+    }
+
+    fn build_block(&mut self) -> Result<AstBlock<'s>, Error> {
+        assert_lexeme!(self, Lexeme::BraceOpen, "Expected brace open");
+
+        let mut block_lines = vec![];
+        loop {
+            if let Some(&Lexeme::BraceClose) = self.peek() {
+                break;
+            }
+
+            let statement = self.build_block_line()?;
+            block_lines.push(statement);
+        }
+
+        assert_lexeme!(self, Lexeme::BraceClose, "Expected brace close");
+
+        Ok(AstBlock(block_lines))
+    }
+
+    fn build_expr_assignment(&mut self) -> Result<AstExpr<'s>, Error> {
+        debug!("Build: expr/assign");
+
+        let varname = match self.pop() {
+            Some(Lexeme::Name(name)) => name,
+            _ => return Err("Expected name for assignment".into()),
+        };
+
+        assert_lexeme!(self, Lexeme::Assign, "Expected assign");
+
+        let expr = self.build_expr()?;
+
+        Ok(AstExpr::Assignment {
+            varname,
+            ty: None,
+            expr: Box::new(expr),
+        })
+    }
+
+    fn build_expr_let(&mut self) -> Result<AstExpr<'s>, Error> {
+        debug!("Build: expr/let");
+
+        assert_lexeme!(self, Lexeme::Let, "Expected keyword let");
+
+        let varname = match self.pop() {
+            Some(Lexeme::Name(name)) => name,
+            _ => return Err("Expected name for let".into()),
+        };
+
+        let ty = self.build_optional_type_annotation()?;
+
+        assert_lexeme!(self, Lexeme::Assign, "Expected assign");
+
+        let expr = self.build_expr()?;
+
+        Ok(AstExpr::Assignment {
+            varname,
+            ty,
+            expr: Box::new(expr),
+        })
+    }
+
+    fn build_expr_op_assignment(&mut self) -> Result<AstExpr<'s>, Error> {
+        debug!("Build: expr/opassign");
+
+        let varname = match self.pop() {
+            Some(Lexeme::Name(name)) => name,
+            _ => return Err("Expected name for assignment".into()),
+        };
+
+        let loc = self.loc();
+        let op = match self.pop() {
+            Some(Lexeme::OpAssign(op)) => op,
+            _ => return Err("Expected compound assignment".into()),
+        };
+
+        let expr = self.build_expr()?;
+
+        Ok(AstExpr::OpAssignment {
+            varname,
+            op,
+            expr: Box::new(expr),
+            loc,
+        })
+    }
+
+    fn build_expr_int(&mut self) -> Result<AstExpr<'s>, Error> {
+        debug!("Build: expr/int");
+
+        match self.pop() {
+            Some(Lexeme::Int(n)) => Ok(AstExpr::Int(n)),
+            _ => Err("Expected integer".into()),
+        }
+    }
+
+    fn build_expr_str(&mut self) -> Result<AstExpr<'s>, Error> {
+        debug!("Build: expr/str");
+
+        match self.pop() {
+            Some(Lexeme::Str(s)) => Ok(AstExpr::Str(s)),
+            _ => Err("Expected string".into()),
+        }
+    }
+
+    fn build_expr_str_template(&mut self) -> Result<AstExpr<'s>, Error> {
+        debug!("Build: expr/str template");
+
+        let parts = match self.pop() {
+            Some(Lexeme::StrTemplate(parts)) => parts,
+            _ => return Err("Expected string".into()),
+        };
+
+        parts
+            .into_iter()
+            .map(|part| match part {
+                StrPart::Literal(literal) => Ok(AstStrPart::Literal(literal)),
+                StrPart::Expr(lexemes) => Ok(AstStrPart::Expr(build_interpolated_expr(lexemes)?)),
+            })
+            .collect::<Result<Vec<AstStrPart>, Error>>()
+            .map(AstExpr::StrTemplate)
+    }
+
+    fn build_expr_name(&mut self) -> Result<AstExpr<'s>, Error> {
+        debug!("Build: expr/name");
+
+        match self.pop() {
+            Some(Lexeme::Name(s)) => Ok(AstExpr::Name(s)),
+            _ => Err("Expected name".into()),
+        }
+    }
+
+    fn build_expr_fn_call(&mut self) -> Result<AstExpr<'s>, Error> {
+        debug!("Build: expr/fn-call");
+
+        let mut module = None;
+        if let Some(Lexeme::PathSep) = self.peekn(1) {
+            module = match self.pop() {
+                Some(Lexeme::Name(module)) => Some(module),
+                _ => return Err("Expected module name".into()),
+            };
+            assert_lexeme!(self, Lexeme::PathSep, "Expected path separator");
+        }
+
+        let name = match self.pop() {
+            Some(Lexeme::Name(name)) => name,
+            _ => return Err("Expected name".into()),
+        };
+
+        let (args, named_args) = self.build_call_args()?;
+
+        Ok(AstExpr::FnCall {
+            module,
+            name,
+            args,
+            named_args,
+        })
+    }
+
+    /**
+     * Reads `(expr1, expr2, ..., name1: expr3, ...)`.
+     */
+    fn build_call_args(&mut self) -> Result<(Vec<AstExpr<'s>>, AstNamedArgs<'s>), Error> {
+        assert_lexeme!(self, Lexeme::ParenOpen, "Expected paren open");
+
+        let mut args = vec![];
+        let mut named_args = vec![];
+
+        if let Some(Lexeme::ParenClose) = self.peek() {
+            // Just for pattern matching, skip arg collection.
+        } else {
+            loop {
+                match (self.peek(), self.peekn(1)) {
+                    (Some(&Lexeme::Name(name)), Some(Lexeme::Colon)) => {
+                        self.pop();
+                        self.pop();
+                        named_args.push((name, self.build_expr()?));
+                    }
+                    _ if !named_args.is_empty() => {
+                        return Err("Positional argument after named arguments".into())
+                    }
+                    _ => args.push(self.build_expr()?),
+                }
+
+                if let Some(&Lexeme::Comma) = self.peek() {
+                    self.pop();
+                    continue;
+                }
+
+                break;
+            }
+        }
+
+        assert_lexeme!(self, Lexeme::ParenClose, "Expected paren close");
+
+        Ok((args, named_args))
+    }
+
+    fn reorder_binop_precedence(&self, expr: AstExpr<'s>) -> AstExpr<'s> {
+        match expr {
+            AstExpr::BinOp { lhs, op, rhs, loc } => match *rhs {
+                AstExpr::BinOp {
+                    lhs: rhs_lhs,
+                    op: rhs_op,
+                    rhs: rhs_rhs,
+                    loc: rhs_loc,
+                } => {
+                    let is_left_grouped =
+                        op.precedence() == rhs_op.precedence() && !op.is_right_assoc();
+                    if op.precedence() > rhs_op.precedence() || is_left_grouped {
+                        // Wrong precendence. Needs to rotate the branches (recursively to the left subtree):
+                        //   1            2
+                        //  / \          / \
+                        // A   2   =>   1   C
+                        //    / \      / \
+                        //   B   C    A   B
+                        AstExpr::BinOp {
+                            lhs: Box::new(self.reorder_binop_precedence(AstExpr::BinOp {
+                                lhs,
+                                op,
+                                rhs: rhs_lhs,
+                                loc,
+                            })),
+                            op: rhs_op,
+                            rhs: rhs_rhs,
+                            loc: rhs_loc,
+                        }
+                    } else {
+                        AstExpr::BinOp {
+                            lhs,
+                            op,
+                            rhs: Box::new(AstExpr::BinOp {
+                                lhs: rhs_lhs,
+                                op: rhs_op,
+                                rhs: rhs_rhs,
+                                loc: rhs_loc,
+                            }),
+                            loc,
+                        }
+                    }
+                }
+                _ => AstExpr::BinOp { lhs, op, rhs, loc },
+            },
+            other => other,
+        }
+    }
+
+    fn is_end(&self) -> bool {
+        self.lexemes.is_empty()
+    }
+
+    fn peek(&self) -> Option<&Lexeme<'s>> {
+        self.peekn(0)
+    }
+
+    fn peekn(&self, n: usize) -> Option<&Lexeme<'s>> {
+        self.lexemes.get(n)
+    }
+
+    fn pop(&mut self) -> Option<Lexeme<'s>> {
+        self.locs.pop_front();
+        self.lexemes.pop_front()
+    }
+
+    /**
+     * Location of the next lexeme, which must exist.
+     */
+    fn loc(&self) -> Loc {
+        self.locs[0]
+    }
+}
+
+/**
+ * Builds the expression of a string interpolation, reporting errors at the location of the lexeme
+ * where parsing stopped.
+ */
+fn build_interpolated_expr(lexemes: Vec<(Lexeme<'_>, Loc)>) -> Result<AstExpr<'_>, Error> {
+    let locs: Vec<Loc> = lexemes.iter().map(|(_, loc)| *loc).collect();
+    let mut parser = Parser::new(lexemes.into());
+
+    let result = parser.build_expr();
+    let loc = locs
+        .get(locs.len() - parser.lexemes.len())
+        .or(locs.last())
+        .ok_or("Empty interpolation in string")?;
+
+    let expr =
+        result.map_err(|err| format!("Error in string interpolation at {}: {}", loc, err))?;
+    if let Some(lexeme) = parser.peek() {
+        return Err(format!("Unexpected {:?} in string interpolation at {}", lexeme, loc).into());
+    }
+
+    Ok(expr)
+}
+
+/**
+ * Type names (structs) are capitalized, which lets `Name {` open a struct literal and not a block.
+ */
+fn is_type_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_uppercase())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lexer::*;
+    use crate::parser::*;
+    use crate::source_reader::*;
+
+    #[test]
+    fn build_empty_program() {
+        let root = parse_this(r#""#);
+        assert_eq!(0, root.statements.len());
+    }
+
+    #[test]
+    fn build_minimal_program() {
+        let root = parse_this(
+            r#"
+            fn main(word, second) {
+                print(word);
+                print(second);
+                print(fixed());
+                fixed();
+            }
+
+            fn fixed() {
+                123;
+            }
+
+            main(123, "hello");
+        "#,
+        );
+        assert_eq!(3, root.statements.len());
+    }
+
+    #[test]
+    fn test_expr_assignment() {
+        assert_eq!(
+            r#"
+prg
+    stmt
+        blockline
+            expr / assign
+                expr / fncall
+                "#
+            .trim()
+            .to_owned(),
+            parse_this("a = calc();").ast_dump(0)
+        );
+    }
+
+    #[test]
+    fn test_expr_op_assignment() {
+        assert_eq!(
+            r#"
+prg
+    stmt
+        blockline
+            expr / opassign
+                expr / binop
+                    expr / int
+                    expr / name
+                "#
+            .trim()
+            .to_owned(),
+            parse_this("a -= 2 * b;").ast_dump(0)
+        );
+    }
+
+    #[test]
+    fn test_import_and_module_call() {
+        assert_eq!(
+            r#"
+prg
+    stmt / import
+    stmt
+        blockline
+            expr / fncall
+                expr / int
+                expr / int
+                "#
+            .trim()
+            .to_owned(),
+            parse_this(r#"import "lib/math.l2"; math::add(1, 2);"#).ast_dump(0)
+        );
+    }
+
+    #[test]
+    fn test_type_annotations() {
+        assert_eq!(
+            r#"
+prg
+    stmt / fndef
+        blocklinelist
+            blockline
+                expr / name
+    stmt
+        blockline
+            expr / assign
+                expr / array
+                "#
+            .trim()
+            .to_owned(),
+            parse_this("fn f(a: int, b: [Point], c) -> {str} { c; } let x: [[int]] = [];")
+                .ast_dump(0)
+        );
+        let reader = Box::new(StrReader::new("let x: double = 1;"));
+        let lexemes = Lexer::new(reader).read_any().unwrap();
+        assert_eq!(
+            "Unknown type 'double'",
+            Parser::new(lexemes.into())
+                .build_ast()
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn test_const_def() {
+        assert_eq!(
+            r#"
+prg
+    stmt / constdef
+        expr / binop
+            expr / int
+            expr / name
+                "#
+            .trim()
+            .to_owned(),
+            parse_this("const LIMIT = 10 * SIZE;").ast_dump(0)
+        );
+    }
+
+    #[test]
+    fn test_expr_name() {
+        assert_eq!(
+            r#"
+prg
+    stmt
+        blockline
+            expr / name
+    stmt / fndef
+        blocklinelist
+            blockline
+                expr / name
+                "#
+            .trim()
+            .to_owned(),
+            parse_this("abc; fn main(x){ x; }").ast_dump(0)
+        );
+    }
+
+    #[test]
+    fn test_expr_int() {
+        assert_eq!(
+            r#"
+prg
+    stmt
+        blockline
+            expr / int
+                "#
+            .trim()
+            .to_owned(),
+            parse_this("2;").ast_dump(0)
+        );
+    }
+
+    #[test]
+    fn test_expr_str() {
+        assert_eq!(
+            r#"
+prg
+    stmt
+        blockline
+            expr / str
+                "#
+            .trim()
+            .to_owned(),
+            parse_this("\"hi\";").ast_dump(0)
+        );
+    }
+
+    #[test]
+    fn test_expr_str_template() {
+        assert_eq!(
+            r#"
+prg
+    stmt
+        blockline
+            expr / strtemplate
+                expr / binop
+                    expr / name
+                    expr / int
+                expr / fncall
+                    expr / str
+                "#
+            .trim()
+            .to_owned(),
+            parse_this(r#""x + 1 = {x + 1}, {len("ab")}";"#).ast_dump(0)
+        );
+
+        for (source, err) in [
+            (
+                "\"{1 +}\";",
+                "Error in string interpolation at 1:5: Cannot build expression",
+            ),
+            (
+                "\n  \"sum {a b}\";",
+                "Unexpected Name(\"b\") in string interpolation at 2:11",
+            ),
+        ] {
+            let reader = Box::new(StrReader::new(source));
+            let lexemes = Lexer::new(reader).read_any().unwrap();
+            assert_eq!(
+                err,
+                Parser::new(lexemes.into())
+                    .build_ast()
+                    .unwrap_err()
+                    .to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn test_default_and_named_args() {
+        let program = parse_this("fn f(a, b: int = 2 * 3) { a; } f(1, b: 2);");
+        match &program.statements[0] {
+            AstStatement::FnDef { args, .. } => {
+                assert!(args[0].default.is_none());
+                assert!(matches!(args[1].default, Some(AstExpr::BinOp { .. })));
+            }
+            other => panic!("Expected fn def, got {:?}", other),
+        }
+        match &program.statements[1] {
+            AstStatement::BlockLine(AstBlockLine::Expr(AstExpr::FnCall {
+                args,
+                named_args,
+                ..
+            })) => {
+                assert_eq!(1, args.len());
+                assert_eq!(
+                    vec!["b"],
+                    named_args.iter().map(|(name, _)| *name).collect::<Vec<_>>()
+                );
+            }
+            other => panic!("Expected fn call, got {:?}", other),
+        }
+
+        let reader = Box::new(StrReader::new("f(a: 1, 2);"));
+        let lexemes = Lexer::new(reader).read_any().unwrap();
+        assert_eq!(
+            "Positional argument after named arguments",
+            Parser::new(lexemes.into())
+                .build_ast()
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn test_variadic_args() {
+        let program = parse_this("fn f(a, ...rest: [int]) { a; }");
+        match &program.statements[0] {
+            AstStatement::FnDef { args, .. } => {
+                assert!(!args[0].variadic);
+                assert!(args[1].variadic);
+                assert!(matches!(args[1].ty, Some(AstType::Array(_))));
+            }
+            other => panic!("Expected fn def, got {:?}", other),
+        }
+
+        for (input, err) in [
+            (
+                "fn f(...a, b) { b; }",
+                "Only the last argument can be variadic",
+            ),
+            (
+                "fn f(...a = 1) { a; }",
+                "Variadic argument 'a' cannot have a default value",
+            ),
+        ] {
+            let reader = Box::new(StrReader::new(input));
+            let lexemes = Lexer::new(reader).read_any().unwrap();
+            assert_eq!(
+                err,
+                Parser::new(lexemes.into())
+                    .build_ast()
+                    .unwrap_err()
+                    .to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn test_tuple() {
+        assert_eq!(
+            r#"
+prg
+    stmt / fndef
+        blocklinelist
+            blockline
+                expr / tuple
+                    expr / binop
+                        expr / name
+                        expr / name
+                    expr / binop
+                        expr / name
+                        expr / name
+    stmt
+        blockline
+            expr / tupleassign
+                expr / fncall
+                    expr / int
+                    expr / int
+    stmt
+        blockline
+            expr / parenexpr
+                expr / tuple
+                    expr / int
+                "#
+            .trim()
+            .to_owned(),
+            parse_this("fn divmod(a, b) { (a / b, a % b); } (q, _) = divmod(7, 2); ((1,));")
+                .ast_dump(0)
+        );
+
+        let reader = Box::new(StrReader::new("(a, b + 1) = (1, 2);"));
+        let lexemes = Lexer::new(reader).read_any().unwrap();
+        assert_eq!(
+            "Only names can be destructured",
+            Parser::new(lexemes.into())
+                .build_ast()
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn test_expr_fn_call() {
+        assert_eq!(
+            r#"
+prg
+    stmt
+        blockline
+            expr / fncall
+                expr / int
+                "#
+            .trim()
+            .to_owned(),
+            parse_this("main(123);").ast_dump(0)
+        );
+    }
+
+    #[test]
+    fn test_expr_if() {
+        assert_eq!(
+            r#"
+prg
+    stmt
+        blockline
+            expr / if
+                blocklinelist
+                    blockline
+                        expr / fncall
+                blocklinelist
+                    blockline
+                        expr / str
+                "#
+            .trim()
+            .to_owned(),
+            parse_this("if (2) { main(); } else { \"abc\"; }").ast_dump(0)
+        );
+        assert_eq!(
+            r#"
+prg
+    stmt
+        blockline
+            expr / if
+                blocklinelist
+                    blockline
+                        expr / fncall
+                -
+                "#
+            .trim()
+            .to_owned(),
+            parse_this("if (2) { main(); }").ast_dump(0)
+        );
+    }
+
+    #[test]
+    fn test_yield() {
+        assert_eq!(
+            r#"
+prg
+    stmt / fndef
+        blocklinelist
+            blockline
+                expr / loop
+                    blocklinelist
+                        blockline / yield
+                            expr / name
+                "#
+            .trim()
+            .to_owned(),
+            parse_this("fn g() { loop { yield n; } }").ast_dump(0)
+        );
+
+        for input in [
+            "fn g() { a = loop { yield 1; break 2; }; }",
+            "fn g(x) { a = match (x) { _ => { yield 1; } }; }",
+            "fn g() { if (loop { yield 1; }) { 1; } }",
+        ] {
+            let reader = Box::new(StrReader::new(input));
+            let lexemes = Lexer::new(reader).read_any().unwrap();
+            assert_eq!(
+                "Yield can only be used as a statement, not inside an expression",
+                Parser::new(lexemes.into())
+                    .build_ast()
+                    .unwrap_err()
+                    .to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn test_try_catch() {
+        assert_eq!(
+            r#"
+prg
+    stmt
+        blockline
+            expr / try
+                blocklinelist
+                    blockline / throw
+                        expr / int
+                blocklinelist
+                    blockline
+                        expr / name
+    stmt
+        blockline
+            expr / assign
+                expr / try
+                    blocklinelist
+                        blockline
+                            expr / fncall
+                    blocklinelist
+                        blockline
+                            expr / int
+                "#
+            .trim()
+            .to_owned(),
+            parse_this("try { throw 1; } catch (e) { e; } x = try { f(); } catch (e) { 0; };")
+                .ast_dump(0)
+        );
+    }
+
+    #[test]
+    fn test_else_if_and_block() {
+        assert_eq!(
+            r#"
+prg
+    stmt
+        blockline
+            expr / if
+                blocklinelist
+                    blockline
+                        expr / int
+                blocklinelist
+                    blockline
+                        expr / if
+                            blocklinelist
+                                blockline
+                                    expr / int
+                            blocklinelist
+                                blockline
+                                    expr / int
+    stmt
+        blockline
+            expr / assign
+                expr / block
+                    blocklinelist
+                        blockline
+                            expr / assign
+                                expr / int
+                        blockline
+                            expr / name
+    stmt
+        blockline
+            expr / block
+                blocklinelist
+                    blockline
+                        expr / int
+    stmt
+        blockline
+            expr / array
+                expr / name
+                "#
+            .trim()
+            .to_owned(),
+            parse_this(
+                "if (a) { 1; } else if (b) { 2; } else { 3; } x = { y = 1; y; }; { 4; } [x];"
+            )
+            .ast_dump(0)
+        );
+    }
+
+    #[test]
+    fn test_expr_float() {
+        assert_eq!(
+            r#"
+prg
+    stmt
+        blockline
+            expr / assign
+                expr / binop
+                    expr / float
+                    expr / int
+                "#
+            .trim()
+            .to_owned(),
+            parse_this("let x: float = 1.5 * 2;").ast_dump(0)
+        );
+    }
+
+    #[test]
+    fn test_expr_bool() {
+        assert_eq!(
+            r#"
+prg
+    stmt
+        blockline
+            expr / bool
+                "#
+            .trim()
+            .to_owned(),
+            parse_this("true;").ast_dump(0)
+        );
+
+        assert_eq!(
+            r#"
+prg
+    stmt
+        blockline
+            expr / bool
+                "#
+            .trim()
+            .to_owned(),
+            parse_this("false;").ast_dump(0)
+        );
+    }
 
     #[test]
-    fn build_empty_program() {
-        let root = parse_this(r#""#);
-        assert_eq!(0, root.statements.len());
+    fn test_expr_null_and_coalesce() {
+        assert_eq!(
+            r#"
+prg
+    stmt
+        blockline
+            expr / binop
+                expr / binop
+                    expr / name
+                    expr / null
+                expr / int
+                "#
+            .trim()
+            .to_owned(),
+            parse_this("x == null ?? 1;").ast_dump(0)
+        );
     }
 
     #[test]
-    fn build_minimal_program() {
-        let root = parse_this(
+    fn test_fndef() {
+        assert_eq!(
             r#"
-            fn main(word, second) {
-                print(word);
-                print(second);
-                print(fixed());
-                fixed();
-            }
-
-            fn fixed() {
-                123;
-            }
-
-            main(123, "hello");
-        "#,
+prg
+    stmt / fndef
+        blocklinelist
+            blockline
+                expr / int
+                "#
+            .trim()
+            .to_owned(),
+            parse_this("fn main() { 0; }").ast_dump(0)
         );
-        assert_eq!(3, root.statements.len());
     }
 
     #[test]
-    fn test_expr_assignment() {
+    fn test_single_op() {
         assert_eq!(
             r#"
 prg
     stmt
         blockline
-            expr / assign
-                expr / fncall
+            expr / binop
+                expr / int
+                expr / int
                 "#
             .trim()
             .to_owned(),
-            parse_this("a = calc();").ast_dump(0)
+            parse_this("1 + 2;").ast_dump(0)
         );
     }
 
     #[test]
-    fn test_expr_name() {
+    fn test_pipe() {
         assert_eq!(
             r#"
 prg
     stmt
         blockline
-            expr / name
-    stmt / fndef
-        blocklinelist
-            blockline
-                expr / name
+            expr / fncall
+                expr / fncall
+                    expr / binop
+                        expr / name
+                        expr / int
+                    expr / int
+                expr / int
                 "#
             .trim()
             .to_owned(),
-            parse_this("abc; fn main(x){ x; }").ast_dump(0)
+            parse_this("x + 1 |> f(2) |> g(c: 3);").ast_dump(0)
         );
-    }
 
-    #[test]
-    fn test_expr_int() {
         assert_eq!(
             r#"
 prg
     stmt
         blockline
-            expr / int
+            expr / binop
+                expr / parenexpr
+                    expr / fncall
+                        expr / int
+                expr / int
                 "#
             .trim()
             .to_owned(),
-            parse_this("2;").ast_dump(0)
+            parse_this("(1 |> f()) == 2;").ast_dump(0)
         );
+
+        for (input, err) in [
+            ("x |> y;", "Expected a function call after |>"),
+            (
+                "x = 1 |> f() + 1;",
+                "Operator after a piped call, wrap the pipe in parentheses",
+            ),
+            (
+                "if (1 |> f() == 2) { 1; }",
+                "Operator after a piped call, wrap the pipe in parentheses",
+            ),
+        ] {
+            let reader = Box::new(StrReader::new(input));
+            let lexemes = Lexer::new(reader).read_any().unwrap();
+            assert_eq!(
+                err,
+                Parser::new(lexemes.into())
+                    .build_ast()
+                    .unwrap_err()
+                    .to_string()
+            );
+        }
     }
 
     #[test]
-    fn test_expr_str() {
+    fn test_multiple_op() {
         assert_eq!(
             r#"
 prg
     stmt
         blockline
-            expr / str
+            expr / binop
+                expr / int
+                expr / binop
+                    expr / binop
+                        expr / int
+                        expr / int
+                    expr / int
                 "#
             .trim()
             .to_owned(),
-            parse_this("\"hi\";").ast_dump(0)
+            parse_this("1 + 2 * 3 / 4;").ast_dump(0)
         );
     }
 
     #[test]
-    fn test_expr_fn_call() {
+    fn test_string_op() {
         assert_eq!(
             r#"
 prg
     stmt
         blockline
-            expr / fncall
+            expr / binop
+                expr / str
+                expr / str
                 "#
             .trim()
             .to_owned(),
-            parse_this("main(123);").ast_dump(0)
+            parse_this("\"a\" + \"b\";").ast_dump(0)
         );
     }
 
     #[test]
-    fn test_expr_if() {
+    fn test_expr_binop_eq() {
         assert_eq!(
             r#"
 prg
     stmt
         blockline
-            expr / if
-                blocklinelist
-                    blockline
-                        expr / fncall
-                blocklinelist
-                    blockline
-                        expr / str
+            expr / binop
+                expr / str
+                expr / str
                 "#
             .trim()
             .to_owned(),
-            parse_this("if (2) { main(); } else { \"abc\"; }").ast_dump(0)
+            parse_this("\"a\" == \"b\";").ast_dump(0)
         );
+    }
+
+    #[test]
+    fn test_expr_paren_expr() {
         assert_eq!(
             r#"
 prg
     stmt
         blockline
-            expr / if
-                blocklinelist
-                    blockline
-                        expr / fncall
-                -
+            expr / parenexpr
+                expr / binop
+                    expr / str
+                    expr / str
                 "#
             .trim()
             .to_owned(),
-            parse_this("if (2) { main(); }").ast_dump(0)
+            parse_this("(\"a\" == \"b\");").ast_dump(0)
         );
     }
 
     #[test]
-    fn test_expr_bool() {
+    fn test_op_in_argument() {
         assert_eq!(
             r#"
 prg
     stmt
         blockline
-            expr / bool
+            expr / fncall
+                expr / binop
+                    expr / name
+                    expr / binop
+                        expr / int
+                        expr / fncall
                 "#
             .trim()
             .to_owned(),
-            parse_this("true;").ast_dump(0)
+            parse_this("main(a + 3 * other());").ast_dump(0)
         );
+    }
 
+    #[test]
+    fn test_loop_and_break() {
         assert_eq!(
             r#"
 prg
     stmt
         blockline
-            expr / bool
+            expr / loop
+                blocklinelist
+                    blockline / break
                 "#
             .trim()
             .to_owned(),
-            parse_this("false;").ast_dump(0)
+            parse_this("loop { break; }").ast_dump(0)
         );
     }
 
     #[test]
-    fn test_fndef() {
+    fn test_loop_with_break_value() {
         assert_eq!(
             r#"
 prg
-    stmt / fndef
-        blocklinelist
-            blockline
-                expr / int
+    stmt
+        blockline
+            expr / assign
+                expr / loop
+                    blocklinelist
+                        blockline / break
+                            expr / binop
+                                expr / int
+                                expr / int
+    stmt
+        blockline
+            expr / loop
+                blocklinelist
+                    blockline / break
+    stmt
+        blockline
+            expr / array
+                expr / name
                 "#
             .trim()
             .to_owned(),
-            parse_this("fn main() { 0; }").ast_dump(0)
+            parse_this("a = loop { break 1 + 2; }; loop { break; } [a];").ast_dump(0)
         );
     }
 
     #[test]
-    fn test_single_op() {
+    fn test_loop_labels() {
         assert_eq!(
             r#"
 prg
     stmt
         blockline
-            expr / binop
-                expr / int
-                expr / int
+            expr / loop
+                blocklinelist
+                    blockline
+                        expr / for
+                            expr / name
+                            blocklinelist
+                                blockline / continue
+                                blockline / break
+                                    expr / int
                 "#
             .trim()
             .to_owned(),
-            parse_this("1 + 2;").ast_dump(0)
+            parse_this("'outer: loop { for x in xs { continue 'outer; break 'outer 1; } }")
+                .ast_dump(0)
         );
+        for (input, error) in [
+            ("loop { break 'outer; }", "Unknown loop label: 'outer"),
+            (
+                "'a: loop { } loop { continue 'a; }",
+                "Unknown loop label: 'a",
+            ),
+            ("'a: x = 1;", "Expected loop or for after loop label"),
+        ] {
+            let reader = Box::new(StrReader::new(input));
+            let lexemes = Lexer::new(reader).read_any().unwrap();
+            assert_eq!(
+                error,
+                Parser::new(lexemes.into())
+                    .build_ast()
+                    .unwrap_err()
+                    .to_string()
+            );
+        }
     }
 
     #[test]
-    fn test_multiple_op() {
+    fn test_for_and_range() {
         assert_eq!(
             r#"
 prg
     stmt
         blockline
-            expr / binop
-                expr / int
-                expr / binop
+            expr / for
+                expr / range
                     expr / int
                     expr / binop
+                        expr / name
                         expr / int
-                        expr / int
+                blocklinelist
+                    blockline / break
                 "#
             .trim()
             .to_owned(),
-            parse_this("1 + 2 * 3 / 4;").ast_dump(0)
+            parse_this("for i in 0..n + 1 { break; }").ast_dump(0)
         );
+        match &parse_this("r = 1..=3;").statements[0] {
+            AstStatement::BlockLine(AstBlockLine::Expr(AstExpr::Assignment { expr, .. })) => {
+                assert!(matches!(
+                    **expr,
+                    AstExpr::Range {
+                        inclusive: true,
+                        ..
+                    }
+                ));
+            }
+            other => panic!("Expected assignment, got {:?}", other),
+        }
     }
 
     #[test]
-    fn test_string_op() {
+    fn test_expr_array_and_index() {
         assert_eq!(
             r#"
 prg
+    stmt
+        blockline
+            expr / assign
+                expr / array
+                    expr / int
+                    expr / str
     stmt
         blockline
             expr / binop
-                expr / str
-                expr / str
+                expr / index
+                    expr / name
+                    expr / int
+                expr / int
+    stmt
+        blockline
+            expr / indexassign
+                expr / name
+                expr / binop
+                    expr / int
+                    expr / int
+                expr / array
                 "#
             .trim()
             .to_owned(),
-            parse_this("\"a\" + \"b\";").ast_dump(0)
+            parse_this("a = [1, \"x\"]; a[0] + 1; a[1 + 1] = [];").ast_dump(0)
         );
     }
 
     #[test]
-    fn test_expr_binop_eq() {
+    fn test_expr_map() {
         assert_eq!(
             r#"
 prg
     stmt
         blockline
-            expr / binop
-                expr / str
+            expr / assign
+                expr / map
+                    expr / str
+                    expr / int
+                    expr / int
+                    expr / array
+    stmt
+        blockline
+            expr / index
+                expr / map
                 expr / str
                 "#
             .trim()
             .to_owned(),
-            parse_this("\"a\" == \"b\";").ast_dump(0)
+            parse_this("m = {\"a\": 1, 2: []}; {}[\"a\"];").ast_dump(0)
         );
-    }
-
-    #[test]
-    fn test_expr_paren_expr() {
         assert_eq!(
             r#"
 prg
     stmt
         blockline
-            expr / parenexpr
-                expr / binop
-                    expr / str
-                    expr / str
+            expr / assign
+                expr / map
+                    expr / name
+                    expr / name
                 "#
             .trim()
             .to_owned(),
-            parse_this("(\"a\" == \"b\");").ast_dump(0)
+            parse_this("m = {k: v};").ast_dump(0)
         );
     }
 
     #[test]
-    fn test_op_in_argument() {
+    fn test_struct() {
         assert_eq!(
             r#"
 prg
+    stmt / structdef
     stmt
         blockline
-            expr / fncall
+            expr / assign
+                expr / structinit
+                    expr / int
+                    expr / int
+    stmt
+        blockline
+            expr / fieldassign
+                expr / name
+                expr / binop
+                    expr / field
+                        expr / name
+                    expr / int
+    stmt
+        blockline
+            expr / if
+                blocklinelist
+                    blockline
+                        expr / field
+                            expr / index
+                                expr / name
+                                expr / int
+                -
                 "#
             .trim()
             .to_owned(),
-            parse_this("main(a + 3 * other());").ast_dump(0)
+            parse_this(
+                r#"
+                struct Point { x, y }
+                p = Point { x: 1, y: 2 };
+                p.x = p.y + 1;
+                if (p) { a[0].x; }
+            "#
+            )
+            .ast_dump(0)
         );
     }
 
     #[test]
-    fn test_loop_and_break() {
+    fn test_enum_and_match() {
         assert_eq!(
             r#"
 prg
+    stmt / enumdef
+    stmt
+        blockline
+            expr / assign
+                expr / enumvariant
+                    expr / int
+    stmt
+        blockline
+            expr / match
+                expr / name
+                arm / variant
+                    blocklinelist
+                        blockline
+                            expr / name
+                arm / int
+                    blocklinelist
+                        blockline
+                            expr / int
+                arm / binding
+                    blocklinelist
+                        blockline
+                            expr / name
+                arm / wildcard
+                    blocklinelist
+                        blockline
+                            expr / int
     stmt
-        blockline / loop
-            blocklinelist
-                blockline / break
+        blockline
+            expr / enumvariant
                 "#
             .trim()
             .to_owned(),
-            parse_this("loop { break; }").ast_dump(0)
+            parse_this(
+                r#"
+                enum Shape { Circle(r), Rect(w, h), Empty }
+                s = Shape::Circle(2);
+                match (s) {
+                    Shape::Rect(w, _) => { w; }
+                    1 => { 2; }
+                    other => { other; }
+                    _ => { 0; }
+                }
+                Shape::Empty;
+            "#
+            )
+            .ast_dump(0)
         );
     }
 
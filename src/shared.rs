@@ -1,5 +1,70 @@
+use std::fmt;
+
+use num_bigint::BigInt;
+use num_traits::{FromPrimitive, Signed, ToPrimitive, Zero};
+
 pub type Error = Box<dyn std::error::Error + Send + Sync>;
 
+/**
+ * An error caused by the values the program works on, such as an index out of bounds, which
+ * `try` catches. Other errors are mistakes in the program itself, eg: a missing variable.
+ */
+#[derive(Debug)]
+pub struct RuntimeError(String);
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+pub fn runtime_error(err: impl ToString) -> Error {
+    Box::new(RuntimeError(err.to_string()))
+}
+
+/**
+ * Position in the source, both starting from 1.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Loc {
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Loc {
+    pub fn new(line: usize, col: usize) -> Loc {
+        Loc { line, col }
+    }
+
+    /**
+     * Reports an error raised at this location, runtime errors stay runtime errors.
+     */
+    pub fn wrap(&self, err: Error) -> Error {
+        let msg = format!("{} at {}", err, self);
+        if err.is::<RuntimeError>() {
+            return runtime_error(msg);
+        }
+        msg.into()
+    }
+
+    pub fn advance(&mut self, c: char) {
+        if c == '\n' {
+            self.line += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
+        }
+    }
+}
+
+impl fmt::Display for Loc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Op {
     Add,
@@ -12,6 +77,14 @@ pub enum Op {
     Gt,
     Lte,
     Gte,
+    Pow,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    // `a ?? b`: `b` only when `a` is null.
+    Coalesce,
 }
 
 impl Op {
@@ -20,20 +93,187 @@ impl Op {
      */
     pub fn precedence(&self) -> u8 {
         match self {
-            Op::Eq => 0,
-            Op::Gt => 0,
-            Op::Gte => 0,
-            Op::Lt => 0,
-            Op::Lte => 0,
-
-            Op::Add => 1,
-            Op::Sub => 1,
-            Op::Mod => 1,
-
-            Op::Mul => 2,
-            Op::Div => 2,
+            Op::Coalesce => 0,
+
+            Op::Eq => 1,
+            Op::Gt => 1,
+            Op::Gte => 1,
+            Op::Lt => 1,
+            Op::Lte => 1,
+
+            Op::BitOr => 2,
+            Op::BitXor => 3,
+            Op::BitAnd => 4,
+
+            Op::Shl => 5,
+            Op::Shr => 5,
+
+            Op::Add => 6,
+            Op::Sub => 6,
+
+            Op::Mul => 7,
+            Op::Div => 7,
+            Op::Mod => 7,
+
+            Op::Pow => 8,
         }
     }
+
+    /**
+     * `2 ** 3 ** 2` is `2 ** (3 ** 2)`, every other operator groups to the left.
+     */
+    pub fn is_right_assoc(&self) -> bool {
+        matches!(self, Op::Pow)
+    }
+
+    pub fn is_arith(&self) -> bool {
+        matches!(
+            self,
+            Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Mod | Op::Pow
+        )
+    }
+
+    pub fn is_bitwise(&self) -> bool {
+        matches!(
+            self,
+            Op::BitAnd | Op::BitOr | Op::BitXor | Op::Shl | Op::Shr
+        )
+    }
+}
+
+/**
+ * The result of integer arithmetic: an `i64` when it fits, a bigint otherwise.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum IntResult {
+    Int(i64),
+    Big(BigInt),
+}
+
+impl From<BigInt> for IntResult {
+    fn from(value: BigInt) -> IntResult {
+        match value.to_i64() {
+            Some(v) => IntResult::Int(v),
+            None => IntResult::Big(value),
+        }
+    }
+}
+
+/**
+ * Arithmetic on ints, promoted to bigints on overflow. `None` for the operators that are not
+ * arithmetic.
+ */
+pub fn int_arith(op: &Op, a: i64, b: i64) -> Result<Option<IntResult>, Error> {
+    if matches!(op, Op::Div | Op::Mod) && b == 0 {
+        return Err(runtime_error("Division by zero"));
+    }
+
+    let result = match op {
+        Op::Add => a.checked_add(b),
+        Op::Sub => a.checked_sub(b),
+        Op::Mul => a.checked_mul(b),
+        Op::Div => a.checked_div(b),
+        Op::Mod => a.checked_rem(b),
+        Op::Pow => u32::try_from(b).ok().and_then(|exp| a.checked_pow(exp)),
+        _ => return Ok(None),
+    };
+
+    match result {
+        Some(v) => Ok(Some(IntResult::Int(v))),
+        None => big_arith(op, &BigInt::from(a), &BigInt::from(b)),
+    }
+}
+
+pub fn big_arith(op: &Op, a: &BigInt, b: &BigInt) -> Result<Option<IntResult>, Error> {
+    if matches!(op, Op::Div | Op::Mod) && b.is_zero() {
+        return Err(runtime_error("Division by zero"));
+    }
+
+    let result = match op {
+        Op::Add => a + b,
+        Op::Sub => a - b,
+        Op::Mul => a * b,
+        Op::Div => a / b,
+        Op::Mod => a % b,
+        Op::Pow => a.pow(big_exponent(b)?),
+        _ => return Ok(None),
+    };
+
+    Ok(Some(result.into()))
+}
+
+fn big_exponent(exp: &BigInt) -> Result<u32, Error> {
+    if exp.is_negative() {
+        return Err(runtime_error(format!("Negative exponent: {}", exp)));
+    }
+    exp.to_u32()
+        .ok_or(runtime_error(format!("Exponent too large: {}", exp)))
+}
+
+/**
+ * The int closest to a whole float, bigints included.
+ */
+pub fn float_to_int(value: f64) -> Result<IntResult, Error> {
+    BigInt::from_f64(value)
+        .map(IntResult::from)
+        .ok_or(runtime_error(format!("Cannot convert {:?} to int", value)))
+}
+
+/**
+ * Arithmetic on floats, `None` for the operators that only apply to ints or compare.
+ */
+pub fn float_arith(op: &Op, a: f64, b: f64) -> Option<f64> {
+    match op {
+        Op::Add => Some(a + b),
+        Op::Sub => Some(a - b),
+        Op::Mul => Some(a * b),
+        Op::Div => Some(a / b),
+        Op::Mod => Some(a % b),
+        Op::Pow => Some(a.powf(b)),
+        _ => None,
+    }
+}
+
+/**
+ * Comparisons of two numbers of the same kind, `None` for the operators that do not compare.
+ */
+pub fn compare<T: PartialOrd>(op: &Op, a: T, b: T) -> Option<bool> {
+    match op {
+        Op::Eq => Some(a == b),
+        Op::Lt => Some(a < b),
+        Op::Lte => Some(a <= b),
+        Op::Gt => Some(a > b),
+        Op::Gte => Some(a >= b),
+        _ => None,
+    }
+}
+
+/**
+ * Bitwise operators work on 64 bits, ints promoted to bigints are rejected.
+ */
+pub fn bitwise_range_error(value: &BigInt) -> Error {
+    runtime_error(format!("Bitwise operand out of range: {}", value))
+}
+
+pub fn int_shl(value: i64, amount: i64) -> Result<i64, Error> {
+    Ok(value << shift_amount(amount)?)
+}
+
+/**
+ * Arithmetic shift: the sign bit is kept.
+ */
+pub fn int_shr(value: i64, amount: i64) -> Result<i64, Error> {
+    Ok(value >> shift_amount(amount)?)
+}
+
+fn shift_amount(amount: i64) -> Result<u32, Error> {
+    if !(0..64).contains(&amount) {
+        return Err(runtime_error(format!(
+            "Shift amount out of range: {}",
+            amount
+        )));
+    }
+    Ok(amount as u32)
 }
 
 pub fn char_n(c: char, n: usize) -> String {
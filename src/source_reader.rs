@@ -1,19 +1,40 @@
+use crate::shared::*;
+
 pub trait SourceReader<'a> {
     fn is_eof(&self) -> bool;
     fn peek(&self) -> Option<char>;
+    // The char `n` positions after the next one.
+    fn peek_nth(&self, n: usize) -> Option<char>;
     fn next(&mut self) -> Option<char>;
     fn read_until(&mut self, cond: fn(char) -> bool) -> Option<&'a str>;
+    // Same as `read_until` for conditions that need to keep state.
+    fn read_while(&mut self, cond: &mut dyn FnMut(char) -> bool) -> Option<&'a str>;
+    // Location of the next char.
+    fn loc(&self) -> Loc;
 }
 
 #[derive(Debug)]
 pub struct StrReader<'a> {
+    // Byte offset of the next char.
     ptr: usize,
     source: &'a str,
+    loc: Loc,
 }
 
 impl<'a> StrReader<'a> {
-    pub fn new(source: &'a str) -> StrReader {
-        StrReader { ptr: 0, source }
+    pub fn new(source: &'a str) -> StrReader<'a> {
+        StrReader::new_at(source, Loc::new(1, 1))
+    }
+
+    /**
+     * Reader of a part of a larger source, starting at `loc` of that source.
+     */
+    pub fn new_at(source: &'a str, loc: Loc) -> StrReader<'a> {
+        StrReader {
+            ptr: 0,
+            source,
+            loc,
+        }
     }
 }
 
@@ -23,29 +44,39 @@ impl<'a> SourceReader<'a> for StrReader<'a> {
     }
 
     fn peek(&self) -> Option<char> {
-        self.source.chars().nth(self.ptr)
+        self.source.get(self.ptr..)?.chars().next()
+    }
+
+    fn peek_nth(&self, n: usize) -> Option<char> {
+        self.source.get(self.ptr..)?.chars().nth(n)
     }
 
     fn next(&mut self) -> Option<char> {
         let out = self.peek();
-        self.ptr += 1;
+        match out {
+            Some(c) => {
+                self.ptr += c.len_utf8();
+                self.loc.advance(c);
+            }
+            None => self.ptr += 1,
+        };
         out
     }
 
     fn read_until(&mut self, cond: fn(char) -> bool) -> Option<&'a str> {
+        self.read_while(&mut |c| cond(c))
+    }
+
+    fn read_while(&mut self, cond: &mut dyn FnMut(char) -> bool) -> Option<&'a str> {
         let i = self.ptr as i64;
 
-        loop {
-            match self.peek() {
-                Some(c) => {
-                    if cond(c) {
-                        self.ptr += 1;
-                    } else {
-                        break;
-                    }
-                }
-                None => break,
-            };
+        while let Some(c) = self.peek() {
+            if cond(c) {
+                self.ptr += c.len_utf8();
+                self.loc.advance(c);
+            } else {
+                break;
+            }
         }
 
         let j = self.ptr as i64 - 1;
@@ -55,6 +86,10 @@ impl<'a> SourceReader<'a> for StrReader<'a> {
             None
         }
     }
+
+    fn loc(&self) -> Loc {
+        self.loc
+    }
 }
 
 #[cfg(test)]
@@ -101,9 +136,43 @@ mod test {
         assert_eq!(Some("12345"), reader.read_until(|c| c.is_ascii_digit()));
     }
 
+    #[test]
+    fn test_multibyte_chars() {
+        let mut reader = StrReader::new("héllo wörld");
+        assert_eq!(Some("héllo"), reader.read_until(|c| c != ' '));
+        assert_eq!(Some(' '), reader.next());
+        assert_eq!(Some('w'), reader.next());
+        assert_eq!(Some('ö'), reader.peek());
+        assert_eq!(Some("örld"), reader.read_until(|c| c != ' '));
+        assert!(reader.is_eof());
+    }
+
+    #[test]
+    fn test_loc() {
+        let mut reader = StrReader::new("ab\n c");
+        assert_eq!(Loc::new(1, 1), reader.loc());
+        reader.read_until(|c| c != '\n');
+        assert_eq!(Loc::new(1, 3), reader.loc());
+        reader.next();
+        reader.next();
+        assert_eq!(Loc::new(2, 2), reader.loc());
+
+        let reader = StrReader::new_at("x", Loc::new(3, 7));
+        assert_eq!(Loc::new(3, 7), reader.loc());
+    }
+
     #[test]
     fn test_read_until_at_end() {
         let mut reader = StrReader::new("12345");
         assert_eq!(Some("12345"), reader.read_until(|c| c.is_ascii_digit()));
     }
+
+    #[test]
+    fn test_peek_nth() {
+        let mut reader = StrReader::new("aé.c");
+        reader.next();
+        assert_eq!(Some('é'), reader.peek_nth(0));
+        assert_eq!(Some('c'), reader.peek_nth(2));
+        assert_eq!(None, reader.peek_nth(3));
+    }
 }
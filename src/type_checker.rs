@@ -0,0 +1,1309 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::ast::*;
+use crate::shared::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Int,
+    Float,
+    Str,
+    Bool,
+    Null,
+    // Not annotated and cannot be inferred: accepted anywhere and checked at runtime.
+    Any,
+    Array(Box<Type>),
+    Map(Box<Type>),
+    Tuple(Vec<Type>),
+    Range,
+    // Returned by calling a function that yields.
+    Generator,
+    Named(String),
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::Str => write!(f, "str"),
+            Type::Bool => write!(f, "bool"),
+            Type::Null => write!(f, "null"),
+            Type::Any => write!(f, "any"),
+            Type::Array(item) => write!(f, "[{}]", item),
+            Type::Map(value) => write!(f, "{{{}}}", value),
+            Type::Tuple(items) => write!(
+                f,
+                "({})",
+                items
+                    .iter()
+                    .map(|item| item.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            Type::Range => write!(f, "range"),
+            Type::Generator => write!(f, "generator"),
+            Type::Named(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl Type {
+    fn accepts(&self, other: &Type) -> bool {
+        match (self, other) {
+            (Type::Any, _) | (_, Type::Any) => true,
+            // Ints are promoted to floats.
+            (Type::Float, Type::Int) => true,
+            (Type::Array(a), Type::Array(b)) | (Type::Map(a), Type::Map(b)) => a.accepts(b),
+            (Type::Tuple(a), Type::Tuple(b)) => {
+                a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.accepts(b))
+            }
+            (a, b) => a == b,
+        }
+    }
+
+    /**
+     * The type of a value that can come from either of two expressions.
+     */
+    fn unify(self, other: Type) -> Type {
+        match (self, other) {
+            (a, b) if a == b => a,
+            (Type::Int, Type::Float) | (Type::Float, Type::Int) => Type::Float,
+            (Type::Array(a), Type::Array(b)) => Type::Array(Box::new(a.unify(*b))),
+            (Type::Map(a), Type::Map(b)) => Type::Map(Box::new(a.unify(*b))),
+            (Type::Tuple(a), Type::Tuple(b)) if a.len() == b.len() => {
+                Type::Tuple(a.into_iter().zip(b).map(|(a, b)| a.unify(b)).collect())
+            }
+            _ => Type::Any,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Signature<'s> {
+    args: Vec<(&'s str, Type)>,
+    params: Vec<FnParam>,
+    ret: Type,
+}
+
+/**
+ * Checks the optional type annotations before running a program. Where annotations are omitted
+ * the types are inferred from the values: variables keep the type of their first assignment
+ * unless assigned values of other types later (then they become `any`). Annotated variables,
+ * arguments and return values must always match their annotation.
+ */
+pub struct TypeChecker<'s> {
+    functions: HashMap<(Option<&'s str>, &'s str), Signature<'s>>,
+    structs: HashMap<&'s str, Vec<&'s str>>,
+    enums: EnumDefs,
+    constants: HashMap<String, Type>,
+    // Variables of the function being checked, and whether their type is annotated.
+    variables: HashMap<&'s str, (Type, bool)>,
+    module: Option<&'s str>,
+    // Labels of the enclosing loops and the types of their `break` values, `None` until a
+    // `break` is seen.
+    loop_breaks: Vec<(Option<&'s str>, Option<Type>)>,
+}
+
+impl<'s> TypeChecker<'s> {
+    pub fn new() -> TypeChecker<'s> {
+        TypeChecker {
+            functions: HashMap::new(),
+            structs: HashMap::new(),
+            enums: HashMap::new(),
+            constants: HashMap::new(),
+            variables: HashMap::new(),
+            module: None,
+            loop_breaks: vec![],
+        }
+    }
+
+    pub fn check(&mut self, program: &AstProgram<'s>) -> Result<(), Error> {
+        let constants = collect_constants(&program.statements)?;
+        for (name, value) in &constants {
+            self.constants.insert(name.clone(), const_type(value));
+        }
+        self.collect_types(&program.statements);
+        self.collect_functions(None, &program.statements, &constants)?;
+
+        for statement in &program.statements {
+            self.check_statement(statement)?;
+        }
+
+        Ok(())
+    }
+
+    fn collect_types(&mut self, statements: &[AstStatement<'s>]) {
+        for statement in statements {
+            match statement {
+                AstStatement::StructDef { name, fields } => {
+                    self.structs.insert(name, fields.clone());
+                }
+                AstStatement::EnumDef { name, variants } => {
+                    self.enums.insert(
+                        name.to_string(),
+                        variants
+                            .iter()
+                            .map(|variant| (variant.name.to_string(), variant.fields.len()))
+                            .collect(),
+                    );
+                }
+                AstStatement::Module { statements, .. } => self.collect_types(statements),
+                _ => {}
+            }
+        }
+    }
+
+    fn collect_functions(
+        &mut self,
+        module: Option<&'s str>,
+        statements: &[AstStatement<'s>],
+        constants: &ConstDefs,
+    ) -> Result<(), Error> {
+        for statement in statements {
+            match statement {
+                AstStatement::FnDef {
+                    name,
+                    args,
+                    ret,
+                    block,
+                } => {
+                    let params = fn_params(name, args, constants)?;
+                    let mut arg_types = vec![];
+                    for (arg, param) in args.iter().zip(&params) {
+                        let ty = match self.resolve_type(&arg.ty)? {
+                            Type::Any if arg.variadic => Type::Array(Box::new(Type::Any)),
+                            ty if arg.variadic && !matches!(ty, Type::Array(_)) => {
+                                return Err(format!(
+                                    "Variadic argument '{}' of '{}' must be an array, got {}",
+                                    arg.name, name, ty
+                                )
+                                .into())
+                            }
+                            ty => ty,
+                        };
+                        if let Some(default) = &param.default {
+                            self.check_arg_type(name, arg.name, &ty, &const_type(default))?;
+                        }
+                        arg_types.push((arg.name, ty));
+                    }
+                    let ret = match self.resolve_type(ret)? {
+                        Type::Any if is_generator(block) => Type::Generator,
+                        _ if is_generator(block) => {
+                            return Err(format!(
+                                "Generator function '{}' cannot have a return type",
+                                name
+                            )
+                            .into())
+                        }
+                        ret => ret,
+                    };
+                    let signature = Signature {
+                        args: arg_types,
+                        params,
+                        ret,
+                    };
+                    self.functions.insert((module, name), signature);
+                }
+                AstStatement::Module { name, statements } => {
+                    self.collect_functions(Some(name), statements, constants)?
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn resolve_type(&self, ty: &Option<AstType<'s>>) -> Result<Type, Error> {
+        match ty {
+            Some(ty) => self.resolve_ast_type(ty),
+            None => Ok(Type::Any),
+        }
+    }
+
+    fn resolve_ast_type(&self, ty: &AstType<'s>) -> Result<Type, Error> {
+        Ok(match ty {
+            AstType::Int => Type::Int,
+            AstType::Float => Type::Float,
+            AstType::Str => Type::Str,
+            AstType::Bool => Type::Bool,
+            AstType::Any => Type::Any,
+            AstType::Array(item) => Type::Array(Box::new(self.resolve_ast_type(item)?)),
+            AstType::Map(value) => Type::Map(Box::new(self.resolve_ast_type(value)?)),
+            AstType::Tuple(items) => Type::Tuple(
+                items
+                    .iter()
+                    .map(|item| self.resolve_ast_type(item))
+                    .collect::<Result<Vec<Type>, Error>>()?,
+            ),
+            AstType::Named(name) => {
+                if !self.structs.contains_key(name) && !self.enums.contains_key(*name) {
+                    return Err(format!("Unknown type '{}'", name).into());
+                }
+                Type::Named(name.to_string())
+            }
+        })
+    }
+
+    fn check_statement(&mut self, statement: &AstStatement<'s>) -> Result<(), Error> {
+        match statement {
+            AstStatement::FnDef { name, block, .. } => self.check_fn_def(name, block),
+            AstStatement::Module { name, statements } => {
+                self.module = Some(name);
+                for statement in statements {
+                    self.check_statement(statement)?;
+                }
+                self.module = None;
+                Ok(())
+            }
+            AstStatement::ConstDef { expr, .. } => self.check_expr(expr).map(|_| ()),
+            AstStatement::BlockLine(line) => self.check_block_line(line).map(|_| ()),
+            AstStatement::StructDef { .. }
+            | AstStatement::EnumDef { .. }
+            | AstStatement::Import(_) => Ok(()),
+        }
+    }
+
+    fn check_fn_def(&mut self, name: &'s str, block: &AstBlock<'s>) -> Result<(), Error> {
+        let signature = self.functions[&(self.module, name)].clone();
+
+        let outer_variables = std::mem::take(&mut self.variables);
+        for (arg, ty) in signature.args {
+            // Unannotated arguments are `any`, and stay so.
+            self.variables.insert(arg, (ty, true));
+        }
+
+        let result = self.check_block(block);
+        self.variables = outer_variables;
+
+        let block_type = result?;
+        if signature.ret != Type::Generator && !signature.ret.accepts(&block_type) {
+            return Err(format!(
+                "Type mismatch for return value of '{}': expected {}, got {}",
+                name, signature.ret, block_type
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+
+    fn check_block(&mut self, block: &AstBlock<'s>) -> Result<Type, Error> {
+        let mut last_type = Type::Null;
+        for line in &block.0 {
+            last_type = self.check_block_line(line)?;
+        }
+        Ok(last_type)
+    }
+
+    fn check_block_line(&mut self, line: &AstBlockLine<'s>) -> Result<Type, Error> {
+        match line {
+            AstBlockLine::Expr(expr) => self.check_expr(expr),
+            AstBlockLine::Break { label, value } => {
+                let value_type = match value {
+                    Some(value) => self.check_expr(value)?,
+                    None => Type::Null,
+                };
+                let target = self
+                    .loop_breaks
+                    .iter_mut()
+                    .rev()
+                    .find(|(loop_label, _)| label.is_none() || loop_label == label);
+                if let Some((_, break_type)) = target {
+                    *break_type = Some(match break_type.take() {
+                        Some(break_type) => break_type.unify(value_type),
+                        None => value_type,
+                    });
+                }
+                Ok(Type::Any)
+            }
+            AstBlockLine::Continue(_) => Ok(Type::Any),
+            AstBlockLine::Throw(expr) | AstBlockLine::Yield(expr) => {
+                self.check_expr(expr)?;
+                Ok(Type::Any)
+            }
+        }
+    }
+
+    /**
+     * A loop has the type of its `break` values. A `for` loop can also end without a `break`,
+     * evaluating to null.
+     */
+    fn check_loop(
+        &mut self,
+        label: Option<&'s str>,
+        for_item: Option<(&'s str, &AstExpr<'s>)>,
+        block: &AstBlock<'s>,
+    ) -> Result<Type, Error> {
+        if let Some((varname, iter)) = for_item {
+            let item_type = match self.check_expr(iter)? {
+                Type::Range => Type::Int,
+                Type::Generator => Type::Any,
+                Type::Str => Type::Str,
+                Type::Array(item) => *item,
+                Type::Any => Type::Any,
+                other => return Err(format!("Cannot iterate over {}", other).into()),
+            };
+            self.assign(varname, item_type)?;
+        }
+
+        self.loop_breaks.push((label, None));
+        let block_result = self.check_block(block);
+        let (_, break_type) = self.loop_breaks.pop().expect("Missing loop");
+        block_result?;
+
+        Ok(match (break_type, for_item) {
+            (Some(break_type), Some(_)) => break_type.unify(Type::Null),
+            (Some(break_type), None) => break_type,
+            (None, _) => Type::Null,
+        })
+    }
+
+    fn check_expr(&mut self, expr: &AstExpr<'s>) -> Result<Type, Error> {
+        match expr {
+            AstExpr::Int(_) => Ok(Type::Int),
+            AstExpr::Float(_) => Ok(Type::Float),
+            AstExpr::Null => Ok(Type::Null),
+            AstExpr::Str(_) => Ok(Type::Str),
+            AstExpr::StrTemplate(parts) => {
+                for part in parts {
+                    if let AstStrPart::Expr(expr) = part {
+                        self.check_expr(expr)?;
+                    }
+                }
+                Ok(Type::Str)
+            }
+            AstExpr::Boolean(_) => Ok(Type::Bool),
+            AstExpr::Name(name) => Ok(self
+                .variables
+                .get(name)
+                .map(|(ty, _)| ty.clone())
+                .or_else(|| self.constants.get(*name).cloned())
+                .unwrap_or(Type::Any)),
+            AstExpr::FnCall {
+                module,
+                name,
+                args,
+                named_args,
+            } => self.check_expr_fn_call(*module, name, args, named_args),
+            AstExpr::Assignment { varname, ty, expr } => {
+                let value_type = self.check_expr(expr)?;
+                self.check_not_constant(varname)?;
+                match ty {
+                    Some(ty) => {
+                        let ty = self.resolve_ast_type(ty)?;
+                        self.check_variable_type(varname, &ty, &value_type)?;
+                        self.variables.insert(varname, (ty.clone(), true));
+                        Ok(ty)
+                    }
+                    None => self.assign(varname, value_type),
+                }
+            }
+            AstExpr::OpAssignment {
+                varname, op, expr, ..
+            } => {
+                let var_type = self.check_expr(&AstExpr::Name(varname))?;
+                let expr_type = self.check_expr(expr)?;
+                let value_type = self.binop_type(op, var_type, expr_type)?;
+                self.assign(varname, value_type)
+            }
+            AstExpr::BinOp { lhs, op, rhs, .. } => {
+                let lhs_type = self.check_expr(lhs)?;
+                let rhs_type = self.check_expr(rhs)?;
+                self.binop_type(op, lhs_type, rhs_type)
+            }
+            AstExpr::Loop { label, block } => self.check_loop(*label, None, block),
+            AstExpr::For {
+                label,
+                varname,
+                iter,
+                block,
+            } => self.check_loop(*label, Some((varname, iter)), block),
+            AstExpr::Range { from, to, .. } => {
+                for bound in [from, to] {
+                    let bound_type = self.check_expr(bound)?;
+                    if !Type::Int.accepts(&bound_type) {
+                        return Err(format!(
+                            "Type mismatch: range bounds must be int, got {}",
+                            bound_type
+                        )
+                        .into());
+                    }
+                }
+                Ok(Type::Range)
+            }
+            AstExpr::BitNot(expr) => {
+                let expr_type = self.check_expr(expr)?;
+                if !Type::Int.accepts(&expr_type) {
+                    return Err(format!("Type mismatch: cannot apply ~ to {}", expr_type).into());
+                }
+                Ok(Type::Int)
+            }
+            AstExpr::If {
+                cond,
+                true_block,
+                false_block,
+            } => {
+                self.check_expr(cond)?;
+                let true_type = self.check_block(true_block)?;
+                let false_type = match false_block {
+                    Some(false_block) => self.check_block(false_block)?,
+                    None => Type::Null,
+                };
+                Ok(true_type.unify(false_type))
+            }
+            AstExpr::Block(block) => {
+                let outer: HashSet<&'s str> = self.variables.keys().copied().collect();
+                let block_result = self.check_block(block);
+                self.variables.retain(|name, _| outer.contains(name));
+                block_result
+            }
+            AstExpr::Try {
+                block,
+                catch_var,
+                catch_block,
+            } => {
+                let block_type = self.check_block(block)?;
+                // Runtime errors are caught too, so the value can be anything.
+                self.assign(catch_var, Type::Any)?;
+                let catch_type = self.check_block(catch_block)?;
+                Ok(block_type.unify(catch_type))
+            }
+            AstExpr::ParenExpr(expr) => self.check_expr(expr),
+            AstExpr::Tuple(items) => Ok(Type::Tuple(
+                items
+                    .iter()
+                    .map(|item| self.check_expr(item))
+                    .collect::<Result<Vec<Type>, Error>>()?,
+            )),
+            AstExpr::TupleAssignment { varnames, expr } => {
+                let value_type = self.check_expr(expr)?;
+                let item_types = match &value_type {
+                    Type::Tuple(items) if items.len() == varnames.len() => items.clone(),
+                    Type::Any => vec![Type::Any; varnames.len()],
+                    other => {
+                        return Err(format!(
+                            "Cannot destructure {} into {} variables",
+                            other,
+                            varnames.len()
+                        )
+                        .into())
+                    }
+                };
+                for (varname, ty) in varnames.iter().zip(item_types) {
+                    if *varname != "_" {
+                        self.assign(varname, ty)?;
+                    }
+                }
+                Ok(value_type)
+            }
+            AstExpr::Array(items) => {
+                let mut item_type = None;
+                for item in items {
+                    let ty = self.check_expr(item)?;
+                    item_type = Some(match item_type {
+                        Some(item_type) => ty.unify(item_type),
+                        None => ty,
+                    });
+                }
+                Ok(Type::Array(Box::new(item_type.unwrap_or(Type::Any))))
+            }
+            AstExpr::Map(entries) => {
+                let mut value_type = None;
+                for (key, value) in entries {
+                    self.check_map_key(key)?;
+                    let ty = self.check_expr(value)?;
+                    value_type = Some(match value_type {
+                        Some(value_type) => ty.unify(value_type),
+                        None => ty,
+                    });
+                }
+                Ok(Type::Map(Box::new(value_type.unwrap_or(Type::Any))))
+            }
+            AstExpr::Index { expr, index } => self.check_expr_index(expr, index),
+            AstExpr::IndexAssignment { expr, index, value } => {
+                let item_type = self.check_expr_index(expr, index)?;
+                let value_type = self.check_expr(value)?;
+                if !item_type.accepts(&value_type) {
+                    return Err(format!(
+                        "Type mismatch for element: expected {}, got {}",
+                        item_type, value_type
+                    )
+                    .into());
+                }
+                Ok(value_type)
+            }
+            AstExpr::StructInit { name, fields } => {
+                if !self.structs.contains_key(name) {
+                    return Err(format!("Missing struct '{}'", name).into());
+                }
+                for (_, value) in fields {
+                    self.check_expr(value)?;
+                }
+                Ok(Type::Named(name.to_string()))
+            }
+            AstExpr::Field { expr, field } => self.check_expr_field(expr, field),
+            AstExpr::FieldAssignment { expr, field, value } => {
+                self.check_expr_field(expr, field)?;
+                self.check_expr(value)
+            }
+            AstExpr::EnumVariant {
+                enum_name, args, ..
+            } => {
+                if !self.enums.contains_key(*enum_name) {
+                    return Err(format!("Missing enum '{}'", enum_name).into());
+                }
+                for arg in args {
+                    self.check_expr(arg)?;
+                }
+                Ok(Type::Named(enum_name.to_string()))
+            }
+            AstExpr::Match { expr, arms } => {
+                check_match_exhaustive(arms, &self.enums)?;
+                let expr_type = self.check_expr(expr)?;
+
+                let mut match_type = None;
+                for arm in arms {
+                    self.check_pattern(&arm.pattern, &expr_type)?;
+                    let ty = self.check_block(&arm.block)?;
+                    match_type = Some(match match_type {
+                        Some(match_type) => ty.unify(match_type),
+                        None => ty,
+                    });
+                }
+                Ok(match_type.unwrap_or(Type::Null))
+            }
+        }
+    }
+
+    fn check_variable_type(&self, varname: &str, expected: &Type, got: &Type) -> Result<(), Error> {
+        if !expected.accepts(got) {
+            return Err(format!(
+                "Type mismatch for '{}': expected {}, got {}",
+                varname, expected, got
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    fn check_not_constant(&self, varname: &str) -> Result<(), Error> {
+        if self.constants.contains_key(varname) {
+            return Err(format!("Cannot assign to constant '{}'", varname).into());
+        }
+        Ok(())
+    }
+
+    fn assign(&mut self, varname: &'s str, value_type: Type) -> Result<Type, Error> {
+        self.check_not_constant(varname)?;
+        match self.variables.remove(varname) {
+            Some((ty, true)) => {
+                self.check_variable_type(varname, &ty, &value_type)?;
+                self.variables.insert(varname, (ty, true));
+            }
+            Some((ty, false)) => {
+                self.variables
+                    .insert(varname, (ty.unify(value_type.clone()), false));
+            }
+            None => {
+                self.variables.insert(varname, (value_type.clone(), false));
+            }
+        };
+        Ok(value_type)
+    }
+
+    fn binop_type(&self, op: &Op, lhs: Type, rhs: Type) -> Result<Type, Error> {
+        let is_str = lhs == Type::Str || rhs == Type::Str;
+        let is_float = lhs == Type::Float || rhs == Type::Float;
+        let (operand_type, result_type) = match op {
+            Op::Eq => return Ok(Type::Bool),
+            Op::Coalesce if lhs == Type::Null => return Ok(rhs),
+            Op::Coalesce => return Ok(lhs.unify(rhs)),
+            Op::Add if is_str => (Type::Str, Type::Str),
+            Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Mod | Op::Pow if is_float => {
+                (Type::Float, Type::Float)
+            }
+            Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Mod | Op::Pow => (Type::Int, Type::Int),
+            Op::BitAnd | Op::BitOr | Op::BitXor | Op::Shl | Op::Shr => (Type::Int, Type::Int),
+            Op::Lt | Op::Lte | Op::Gt | Op::Gte if is_str => (Type::Str, Type::Bool),
+            Op::Lt | Op::Lte | Op::Gt | Op::Gte if is_float => (Type::Float, Type::Bool),
+            Op::Lt | Op::Lte | Op::Gt | Op::Gte => (Type::Int, Type::Bool),
+        };
+
+        if !operand_type.accepts(&lhs) || !operand_type.accepts(&rhs) {
+            return Err(format!(
+                "Type mismatch: cannot apply {:?} to {} and {}",
+                op, lhs, rhs
+            )
+            .into());
+        }
+
+        Ok(result_type)
+    }
+
+    fn check_map_key(&mut self, key: &AstExpr<'s>) -> Result<Type, Error> {
+        let key_type = self.check_expr(key)?;
+        match key_type {
+            Type::Int | Type::Str | Type::Any => Ok(key_type),
+            other => Err(format!("Invalid map key type: {}", other).into()),
+        }
+    }
+
+    fn check_expr_index(&mut self, expr: &AstExpr<'s>, index: &AstExpr<'s>) -> Result<Type, Error> {
+        match self.check_expr(expr)? {
+            Type::Array(item_type) => {
+                let index_type = self.check_expr(index)?;
+                if !Type::Int.accepts(&index_type) {
+                    return Err(format!("Array index must be int, got {}", index_type).into());
+                }
+                Ok(*item_type)
+            }
+            Type::Map(value_type) => {
+                self.check_map_key(index)?;
+                Ok(*value_type)
+            }
+            Type::Any => {
+                self.check_expr(index)?;
+                Ok(Type::Any)
+            }
+            other => Err(format!("Cannot index {}", other).into()),
+        }
+    }
+
+    fn check_expr_field(&mut self, expr: &AstExpr<'s>, field: &str) -> Result<Type, Error> {
+        match self.check_expr(expr)? {
+            Type::Named(name) if self.structs.contains_key(name.as_str()) => {
+                if !self.structs[name.as_str()].contains(&field) {
+                    return Err(format!("Struct '{}' has no field '{}'", name, field).into());
+                }
+                Ok(Type::Any)
+            }
+            Type::Any => Ok(Type::Any),
+            other => Err(format!("Cannot access field '{}' of {}", field, other).into()),
+        }
+    }
+
+    fn check_pattern(&mut self, pattern: &AstPattern<'s>, expr_type: &Type) -> Result<(), Error> {
+        let pattern_type = match pattern {
+            AstPattern::Wildcard => return Ok(()),
+            AstPattern::Binding(name) => {
+                self.variables.insert(name, (expr_type.clone(), false));
+                return Ok(());
+            }
+            AstPattern::Int(_) => Type::Int,
+            AstPattern::Str(_) => Type::Str,
+            AstPattern::Boolean(_) => Type::Bool,
+            AstPattern::Variant {
+                enum_name,
+                bindings,
+                ..
+            } => {
+                for binding in bindings {
+                    self.variables.insert(binding, (Type::Any, false));
+                }
+                Type::Named(enum_name.to_string())
+            }
+        };
+
+        if !expr_type.accepts(&pattern_type) {
+            return Err(format!(
+                "Type mismatch for match pattern: expected {}, got {}",
+                expr_type, pattern_type
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+
+    fn check_expr_fn_call(
+        &mut self,
+        module: Option<&'s str>,
+        name: &'s str,
+        args: &[AstExpr<'s>],
+        named_args: &[(&'s str, AstExpr<'s>)],
+    ) -> Result<Type, Error> {
+        let mut arg_types = vec![];
+        for arg in args {
+            arg_types.push(self.check_expr(arg)?);
+        }
+        let mut named_arg_types = vec![];
+        for (arg_name, arg) in named_args {
+            named_arg_types.push((*arg_name, self.check_expr(arg)?));
+        }
+
+        if module.is_none() {
+            if let Some(result) = builtin_call_type(name, &arg_types) {
+                if !named_arg_types.is_empty() {
+                    return Err(format!("Function '{}' does not take named arguments", name).into());
+                }
+                return result;
+            }
+        }
+
+        let candidates = match module {
+            Some(_) => vec![(module, name)],
+            None => vec![(self.module, name), (None, name)],
+        };
+        let signature = candidates
+            .into_iter()
+            .find_map(|key| self.functions.get(&key))
+            .ok_or(match module {
+                Some(module) => format!("Missing function '{}::{}'", module, name),
+                None => format!("Missing function '{}'", name),
+            })?;
+
+        let call_args = resolve_call_args(name, &signature.params, arg_types, named_arg_types)?;
+        for ((arg_name, expected), call_arg) in signature.args.iter().zip(call_args) {
+            match (call_arg, expected) {
+                (CallArg::Given(got), _) => self.check_arg_type(name, arg_name, expected, &got)?,
+                // Defaults were checked with the definition.
+                (CallArg::Default(_), _) => {}
+                (CallArg::Rest(items), Type::Array(item)) => {
+                    for got in items {
+                        self.check_arg_type(name, arg_name, item, &got)?;
+                    }
+                }
+                (CallArg::Rest(_), _) => unreachable!("Variadic arguments are arrays"),
+            }
+        }
+
+        Ok(signature.ret.clone())
+    }
+
+    fn check_arg_type(
+        &self,
+        fn_name: &str,
+        arg_name: &str,
+        expected: &Type,
+        got: &Type,
+    ) -> Result<(), Error> {
+        if !expected.accepts(got) {
+            return Err(format!(
+                "Type mismatch for argument '{}' of '{}': expected {}, got {}",
+                arg_name, fn_name, expected, got
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+fn const_type(value: &ConstValue) -> Type {
+    match value {
+        ConstValue::Int(_) | ConstValue::BigInt(_) => Type::Int,
+        ConstValue::Float(_) => Type::Float,
+        ConstValue::Str(_) => Type::Str,
+        ConstValue::Bool(_) => Type::Bool,
+    }
+}
+
+/**
+ * Signatures of the builtin functions, `None` when `name` is not a builtin.
+ */
+fn builtin_call_type(name: &str, args: &[Type]) -> Option<Result<Type, Error>> {
+    let any = || Box::new(Type::Any);
+    let (expected, ret): (Vec<Type>, Type) = match (name, args) {
+        // Any number of values of any type.
+        ("print" | "println", _) => return Some(Ok(Type::Null)),
+        ("len", [Type::Map(_)]) => (vec![Type::Map(any())], Type::Int),
+        ("len", [Type::Str]) => (vec![Type::Str], Type::Int),
+        ("len", _) => (vec![Type::Array(any())], Type::Int),
+        ("push", [Type::Array(item), _]) => (vec![Type::Array(any()), *item.clone()], Type::Int),
+        ("push", _) => (vec![Type::Array(any()), Type::Any], Type::Int),
+        ("pop", [Type::Array(item)]) => (vec![Type::Array(any())], *item.clone()),
+        ("pop", _) => (vec![Type::Array(any())], Type::Any),
+        ("keys", _) => (vec![Type::Map(any())], Type::Array(any())),
+        ("next", _) => (vec![Type::Generator], Type::Any),
+        ("has", _) => (vec![Type::Map(any()), Type::Any], Type::Bool),
+        ("substr", _) => (vec![Type::Str, Type::Int, Type::Int], Type::Str),
+        ("index_of", _) => (vec![Type::Str, Type::Str], Type::Int),
+        ("upper" | "lower" | "trim", _) => (vec![Type::Str], Type::Str),
+        ("split", _) => (vec![Type::Str, Type::Str], Type::Array(Box::new(Type::Str))),
+        ("to_int", _) => (vec![Type::Str], Type::Int),
+        ("str", _) => (vec![Type::Any], Type::Str),
+        ("sqrt", _) => (vec![Type::Float], Type::Float),
+        ("floor" | "round", _) => (vec![Type::Float], Type::Int),
+        ("is_null", _) => (vec![Type::Any], Type::Bool),
+        _ => return None,
+    };
+
+    if expected.len() != args.len() {
+        return Some(Err(format!(
+            "Function '{}' expects {} arguments, got {}",
+            name,
+            expected.len(),
+            args.len()
+        )
+        .into()));
+    }
+    for (i, (expected, got)) in expected.iter().zip(args).enumerate() {
+        if !expected.accepts(got) {
+            return Some(Err(format!(
+                "Type mismatch for argument {} of '{}': expected {}, got {}",
+                i + 1,
+                name,
+                expected,
+                got
+            )
+            .into()));
+        }
+    }
+
+    Some(Ok(ret))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lexer::*;
+    use crate::parser::*;
+    use crate::source_reader::*;
+    use crate::type_checker::*;
+
+    #[test]
+    fn test_annotations() {
+        assert!(check_this(
+            r#"
+            struct Point { x, y }
+            fn add(a: int, b: int) -> int { a + b; }
+            fn origin() -> Point { Point { x: 0, y: 0 }; }
+            fn names(n: {str}) -> [str] { keys(n); }
+            let x: int = add(1, 2);
+            let s: str = "hi";
+            let xs: [int] = [1, 2];
+            let p: Point = origin();
+            x = x + pop(xs);
+            p.x = 3;
+            names({"a": "b"});
+            "#
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_mismatches() {
+        assert_eq!(
+            "Type mismatch for argument 'b' of 'add': expected int, got str",
+            check_error(r#"fn add(a: int, b: int) -> int { a + b; } add(1, "2");"#)
+        );
+        assert_eq!(
+            "Type mismatch for return value of 'f': expected int, got str",
+            check_error(r#"fn f() -> int { "one"; }"#)
+        );
+        assert_eq!(
+            "Type mismatch for 's': expected str, got int",
+            check_error("let s: str = 1;")
+        );
+        assert_eq!(
+            "Type mismatch for 's': expected str, got bool",
+            check_error(r#"let s: str = "a"; s = true;"#)
+        );
+        assert_eq!(
+            "Type mismatch: cannot apply Add to int and str",
+            check_error(r#"1 + "a";"#)
+        );
+        assert_eq!(
+            "Type mismatch: cannot apply Shl to int and bool",
+            check_error("1 << true;")
+        );
+        assert_eq!(
+            "Type mismatch: cannot apply ~ to str",
+            check_error(r#"~"a";"#)
+        );
+        assert_eq!(
+            "Type mismatch for element: expected int, got str",
+            check_error(r#"xs = [1, 2]; xs[0] = "a";"#)
+        );
+        assert_eq!(
+            "Struct 'Point' has no field 'z'",
+            check_error("struct Point { x, y } p = Point { x: 1, y: 2 }; p.z;")
+        );
+        assert_eq!("Unknown type 'Foo'", check_error("let f: Foo = 1;"));
+        assert_eq!("Missing function 'f'", check_error("f();"));
+        assert_eq!(
+            "Missing argument 'b' of 'add'",
+            check_error("fn add(a, b) { a + b; } add(1);")
+        );
+    }
+
+    #[test]
+    fn test_inference() {
+        // Inferred types flow through unannotated variables and function results.
+        assert_eq!(
+            "Type mismatch for argument 'n' of 'f': expected int, got [str]",
+            check_error(r#"fn f(n: int) { n; } a = ["x"]; b = a; f(b);"#)
+        );
+        assert_eq!(
+            "Type mismatch for argument 'n' of 'f': expected int, got str",
+            check_error(r#"fn f(n: int) { n; } fn g() -> str { "g"; } f(g());"#)
+        );
+        // Values of different types make the variable `any`.
+        assert!(check_this(r#"fn f(n: int) { n; } a = 1; a = "x"; f(a);"#).is_ok());
+        assert!(check_this("fn f(n: int) { n; } fn g(v) { v; } f(g(true));").is_ok());
+        assert_eq!(
+            "Type mismatch: cannot apply Lt to bool and int",
+            check_error("x = if (1) { true; } else { false; }; x < 1;")
+        );
+    }
+
+    #[test]
+    fn test_default_and_named_args() {
+        assert!(check_this(
+            r#"
+            const STEP = 2;
+            fn range(from: int, to: int, step: int = STEP * 5, name: str = "r") { from; }
+            range(1, 2);
+            range(to: 3, from: 1, name: "x");
+        "#
+        )
+        .is_ok());
+        assert_eq!(
+            "Type mismatch for argument 'step' of 'f': expected int, got str",
+            check_error(r#"fn f(step: int = "x") { step; }"#)
+        );
+        assert_eq!(
+            "Type mismatch for argument 'b' of 'f': expected bool, got int",
+            check_error("fn f(a, b: bool = true) { a; } f(b: 1, a: 2);")
+        );
+        assert_eq!(
+            "Default value of argument 'b' of 'f' must be a constant: 'x' is not a constant",
+            check_error("x = 1; fn f(a, b = x) { a; }")
+        );
+        assert_eq!(
+            "Function 'f' has no argument 'c'",
+            check_error("fn f(a, b = 1) { a; } f(1, c: 2);")
+        );
+        assert_eq!(
+            "Argument 'a' of 'f' is given more than once",
+            check_error("fn f(a, b = 1) { a; } f(1, a: 2);")
+        );
+        assert_eq!(
+            "Function 'f' expects 2 arguments, got 3",
+            check_error("fn f(a, b = 1) { a; } f(1, 2, 3);")
+        );
+        assert_eq!(
+            "Function 'len' does not take named arguments",
+            check_error("len(a: [1]);")
+        );
+    }
+
+    #[test]
+    fn test_variadic_args() {
+        assert!(check_this(
+            r#"
+            fn sum(base: int, ...xs: [int]) -> int { base + len(xs); }
+            fn first(...xs) { xs[0]; }
+            let total: int = sum(1, 2, 3);
+            sum(1);
+            first(1, "a");
+            print();
+            println(1, "a", [true]);
+        "#
+        )
+        .is_ok());
+        assert_eq!(
+            "Type mismatch for argument 'xs' of 'f': expected int, got str",
+            check_error(r#"fn f(...xs: [int]) { xs; } f(1, "a");"#)
+        );
+        assert_eq!(
+            "Variadic argument 'xs' of 'f' must be an array, got int",
+            check_error("fn f(...xs: int) { xs; }")
+        );
+    }
+
+    #[test]
+    fn test_for_in() {
+        assert!(check_this(
+            r#"
+            fn sum(items: [int]) -> int {
+                total = 0;
+                for item in items { total += item; }
+                for i in 0..len(items) { total += items[i]; }
+                total;
+            }
+            for c in "abc" { let s: str = c; }
+        "#
+        )
+        .is_ok());
+        assert_eq!(
+            "Type mismatch for 'c': expected int, got str",
+            check_error(r#"let c: int = 0; for c in "ab" { c; }"#)
+        );
+        assert_eq!(
+            "Cannot iterate over bool",
+            check_error("for x in true { x; }")
+        );
+        assert_eq!(
+            "Type mismatch: range bounds must be int, got str",
+            check_error(r#"r = 0.."a";"#)
+        );
+    }
+
+    #[test]
+    fn test_generators() {
+        assert!(check_this(
+            r#"
+            fn count(n: int) {
+                for i in 0..n { yield i; }
+            }
+            for x in count(3) { x; }
+            first = next(count(1));
+        "#
+        )
+        .is_ok());
+        assert_eq!(
+            "Generator function 'g' cannot have a return type",
+            check_error("fn g() -> int { yield 1; }")
+        );
+        assert_eq!(
+            "Type mismatch for argument 1 of 'next': expected generator, got int",
+            check_error("next(1);")
+        );
+    }
+
+    #[test]
+    fn test_try_catch() {
+        assert!(check_this(
+            r#"
+            fn check(n: int) -> int {
+                if (n > 10) { throw "too big"; }
+                n;
+            }
+            let n: int = try { check(1); } catch (e) { 0; };
+        "#
+        )
+        .is_ok());
+        assert_eq!(
+            "Type mismatch for 's': expected str, got int",
+            check_error("let s: str = try { 1; } catch (e) { 2; };")
+        );
+    }
+
+    #[test]
+    fn test_float() {
+        assert!(check_this(
+            r#"
+            fn area(r: float) -> float { 3.14 * r * r; }
+            let a: float = area(2) + 1;
+            let b: int = floor(a) + round(sqrt(2));
+            let c: bool = a > b;
+        "#
+        )
+        .is_ok());
+        assert_eq!(
+            "Type mismatch for 'n': expected int, got float",
+            check_error("let n: int = 1 + 0.5;")
+        );
+        assert_eq!(
+            "Type mismatch: cannot apply BitOr to float and int",
+            check_error("1.5 | 1;")
+        );
+        assert_eq!(
+            "Type mismatch for argument 1 of 'sqrt': expected float, got str",
+            check_error("sqrt(\"4\");")
+        );
+    }
+
+    #[test]
+    fn test_null() {
+        assert!(check_this(
+            r#"
+            x = null;
+            let n: int = x ?? 1;
+            let b: bool = is_null(x) == (n == 1);
+            let f: float = 1 ?? 0.5;
+        "#
+        )
+        .is_ok());
+        assert_eq!(
+            "Type mismatch for 'n': expected int, got null",
+            check_error("let n: int = null;")
+        );
+    }
+
+    #[test]
+    fn test_block_expr() {
+        assert!(check_this(
+            r#"
+            let n: int = { a = 1; a + 1; };
+            { let s: str = "a"; }
+            let s: int = if (n > 1) { 1; } else if (n > 0) { 2; } else { 3; };
+        "#
+        )
+        .is_ok());
+        assert_eq!(
+            "Type mismatch for 's': expected str, got int",
+            check_error("let s: str = { 1; };")
+        );
+    }
+
+    #[test]
+    fn test_loop_labels() {
+        assert!(check_this(
+            r#"
+            let n: int = 'outer: loop {
+                for i in 0..10 {
+                    if (i == 2) { continue; }
+                    if (i == 5) { break 'outer i; }
+                    break;
+                }
+            };
+        "#
+        )
+        .is_ok());
+        assert_eq!(
+            "Type mismatch for 's': expected str, got int",
+            check_error("let s: str = 'a: loop { loop { break 'a 1; } };")
+        );
+    }
+
+    #[test]
+    fn test_loop_break_value() {
+        assert!(check_this(
+            r#"
+            fn first_even(items: [int]) -> int {
+                i = 0;
+                loop {
+                    if (items[i] % 2 == 0) { break items[i]; }
+                    i += 1;
+                }
+            }
+            let found: int = loop { break 1; };
+        "#
+        )
+        .is_ok());
+        assert_eq!(
+            "Type mismatch for 's': expected str, got int",
+            check_error("let s: str = loop { break 1; };")
+        );
+        assert_eq!(
+            "Type mismatch for return value of 'f': expected int, got null",
+            check_error("fn f() -> int { loop { break; } }")
+        );
+    }
+
+    #[test]
+    fn test_tuples() {
+        assert!(check_this(
+            r#"
+            fn divmod(a: int, b: int) -> (int, int) { (a / b, a % b); }
+            (q, r) = divmod(7, 2);
+            let n: int = q + r;
+            (s, _) = ("a", 1);
+            let t: str = s;
+        "#
+        )
+        .is_ok());
+        assert_eq!(
+            "Type mismatch for return value of 'f': expected (int, str), got (int, int)",
+            check_error("fn f() -> (int, str) { (1, 2); }")
+        );
+        assert_eq!(
+            "Cannot destructure (int, int) into 3 variables",
+            check_error("(a, b, c) = (1, 2);")
+        );
+        assert_eq!(
+            "Type mismatch for 'n': expected int, got str",
+            check_error(r#"let n: int = 0; (n, m) = ("x", 1);"#)
+        );
+    }
+
+    #[test]
+    fn test_builtins() {
+        assert_eq!(
+            "Type mismatch for argument 1 of 'len': expected [any], got int",
+            check_error("len(1);")
+        );
+        assert_eq!(
+            "Type mismatch for argument 2 of 'push': expected int, got bool",
+            check_error("push([1], true);")
+        );
+        assert_eq!(
+            "Type mismatch for 's': expected str, got int",
+            check_error("let s: str = pop([1]);")
+        );
+        assert_eq!(
+            "Function 'has' expects 2 arguments, got 1",
+            check_error("has({});")
+        );
+    }
+
+    #[test]
+    fn test_strings() {
+        assert!(check_this(
+            r#"
+            fn greet(name: str) -> str { "hi " + upper(name); }
+            let parts: [str] = split(greet("a") + str(1), " ");
+            let n: int = len("abc") + to_int(trim(" 2 ")) + index_of("ab", "b");
+            let less: bool = "a" < substr("abc", 1, 1);
+            "#
+        )
+        .is_ok());
+        assert_eq!(
+            "Type mismatch: cannot apply Add to str and int",
+            check_error(r#""a" + 1;"#)
+        );
+        assert_eq!(
+            "Type mismatch: cannot apply Lt to str and int",
+            check_error(r#""a" < 1;"#)
+        );
+        assert_eq!(
+            "Type mismatch for argument 2 of 'substr': expected int, got str",
+            check_error(r#"substr("abc", "1", 1);"#)
+        );
+    }
+
+    #[test]
+    fn test_match_exhaustiveness() {
+        assert_eq!(
+            "Non-exhaustive match: missing Shape::Empty",
+            check_error(
+                r#"
+                enum Shape { Circle(r), Empty }
+                fn area(s) {
+                    match (s) {
+                        Shape::Circle(r) => { r; }
+                    }
+                }
+            "#
+            )
+        );
+        assert_eq!(
+            "Non-exhaustive match: missing wildcard arm",
+            check_error("match (1) { 1 => { 1; } 2 => { 2; } }")
+        );
+        assert_eq!(
+            "Pattern Shape::Circle expects 1 bindings, got 2",
+            check_error(
+                r#"
+                enum Shape { Circle(r), Empty }
+                match (Shape::Empty) {
+                    Shape::Circle(a, b) => { a; }
+                    _ => { 0; }
+                }
+            "#
+            )
+        );
+        assert_eq!(
+            "Non-exhaustive match: missing E::B",
+            check_error("enum E { A, B } fn f(x) { match (x) { E::A => { 1; } } } 5;")
+        );
+        assert!(check_this("match (false) { true => { 1; } false => { 0; } }").is_ok());
+    }
+
+    fn parse_this(input: &'static str) -> AstProgram<'static> {
+        let reader = Box::new(StrReader::new(input));
+        let lexemes = Lexer::new(reader).read_any().unwrap();
+        Parser::new(lexemes.into()).build_ast().unwrap()
+    }
+
+    fn check_this(input: &'static str) -> Result<(), Error> {
+        TypeChecker::new().check(&parse_this(input))
+    }
+
+    fn check_error(input: &'static str) -> String {
+        check_this(input).unwrap_err().to_string()
+    }
+}
@@ -1,153 +1,418 @@
 use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
 
 use crate::ir::*;
+use crate::shared::*;
 
 const STACK_SIZE: usize = 256;
 
+/**
+ * A register, stack or heap cell. Addresses, jump targets and booleans are ints.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    // Ints that do not fit in an i64, never holds one that does.
+    BigInt(Rc<BigInt>),
+    Float(f64),
+    Null,
+    // A runtime error caught by `try`, holding its message.
+    Error(Rc<str>),
+}
+
+impl Value {
+    fn as_int(&self) -> Result<i64, Error> {
+        match self {
+            Value::Int(v) => Ok(*v),
+            other => Err(format!("Expected an int, got {}", other).into()),
+        }
+    }
+
+    fn as_float(&self) -> f64 {
+        match self {
+            Value::Int(v) => *v as f64,
+            Value::BigInt(v) => v.to_f64().unwrap_or(f64::NAN),
+            Value::Float(v) => *v,
+            Value::Null | Value::Error(_) => f64::NAN,
+        }
+    }
+
+    /**
+     * Same as in the interpreter for numbers, null and caught errors.
+     */
+    fn is_truthy(&self) -> bool {
+        match self {
+            Value::Int(v) => *v != 0,
+            Value::BigInt(_) => true,
+            Value::Float(v) => *v != 0.0,
+            Value::Null => false,
+            Value::Error(_) => true,
+        }
+    }
+
+    fn as_big_int(&self) -> Option<BigInt> {
+        match self {
+            Value::Int(v) => Some(BigInt::from(*v)),
+            Value::BigInt(v) => Some(v.as_ref().clone()),
+            Value::Float(_) | Value::Null | Value::Error(_) => None,
+        }
+    }
+}
+
+impl From<IntResult> for Value {
+    fn from(value: IntResult) -> Value {
+        match value {
+            IntResult::Int(v) => Value::Int(v),
+            IntResult::Big(v) => Value::BigInt(Rc::new(v)),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(v) => write!(f, "{}", v),
+            Value::BigInt(v) => write!(f, "{}", v),
+            Value::Float(v) => write!(f, "{:?}", v),
+            Value::Null => write!(f, "null"),
+            Value::Error(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
 struct Scope {
-    registers: [i32; STACK_SIZE],
+    registers: [Value; STACK_SIZE],
 }
 
 impl Scope {
     fn new() -> Scope {
         Scope {
-            registers: [0; STACK_SIZE],
+            registers: std::array::from_fn(|_| Value::Int(0)),
         }
     }
 }
 
+/**
+ * Where to continue after a throw or a runtime error inside `try`, and the state to unwind to.
+ */
+struct Handler {
+    label: Label,
+    reg: Reg,
+    frame_count: usize,
+    return_ip_count: usize,
+    stack_len: usize,
+}
+
 pub struct VM {
     ir: IR,
-    stack: Vec<i32>,
+    stack: Vec<Value>,
+    heap: Vec<Value>,
     // Instruction pointer.
     ip: usize,
     label_map: HashMap<Label, usize>,
     frames: Vec<Scope>,
     return_ips: Vec<usize>,
+    handlers: Vec<Handler>,
 }
 
 impl VM {
     pub fn new(ir: IR) -> VM {
         let mut label_map = HashMap::new();
         for i in 0..ir.instructions.len() {
-            match &ir.instructions[i] {
-                Operation::Label(label) => {
-                    label_map.insert(label.clone(), i);
-                }
-                _ => {}
-            };
+            if let Operation::Label(label) = &ir.instructions[i] {
+                label_map.insert(label.clone(), i);
+            }
         }
 
         VM {
             ir,
             stack: vec![],
+            heap: vec![],
             ip: 0,
             label_map,
             frames: vec![Scope::new()],
             return_ips: vec![],
+            handlers: vec![],
         }
     }
 
-    pub fn run(&mut self) {
+    pub fn run(&mut self) -> Result<Option<Value>, Error> {
         loop {
             if self.ip >= self.ir.instructions.len() {
                 break;
             }
 
-            match &self.ir.instructions[self.ip] {
-                Operation::Label(_) => {} // Skip.
-                Operation::Call(label) => {
-                    self.return_ips.push(self.ip);
-                    self.frames.push(Scope::new());
-                    self.ip = self.label_map[label];
-                }
-                Operation::Return => {
-                    self.ip = self.return_ips.pop().expect("Missing return IP");
-                    self.frames.pop().expect("Cannot pop more frames");
-                }
-                Operation::Push(reg) => {
-                    let value = self.reg_get(reg);
-                    self.stack.push(value);
-                }
-                Operation::Pop(reg) => {
-                    let value = self.stack.pop().expect("Empty stack");
-                    self.reg_set(*reg, value);
-                }
-                Operation::Add { lhs, rhs, out } => {
-                    let lhs_val = self.reg_get(lhs);
-                    let rhs_val = self.reg_get(rhs);
-                    self.reg_set(*out, lhs_val + rhs_val);
-                }
-                Operation::Sub { lhs, rhs, out } => {
-                    let lhs_val = self.reg_get(lhs);
-                    let rhs_val = self.reg_get(rhs);
-                    self.reg_set(*out, lhs_val - rhs_val);
-                }
-                Operation::Mul { lhs, rhs, out } => {
-                    let lhs_val = self.reg_get(lhs);
-                    let rhs_val = self.reg_get(rhs);
-                    self.reg_set(*out, lhs_val * rhs_val);
-                }
-                Operation::Div { lhs, rhs, out } => {
-                    let lhs_val = self.reg_get(lhs);
-                    let rhs_val = self.reg_get(rhs);
-                    self.reg_set(*out, lhs_val / rhs_val);
-                }
-                Operation::LoadI { val, out } => {
-                    self.reg_set(*out, *val);
-                }
-                Operation::I2i { lhs, rhs } => {
-                    self.reg_set(*rhs, self.reg_get(lhs));
-                }
-                Operation::JumpI(label) => {
-                    self.ip = self.label_map[label];
+            if let Err(err) = self.step() {
+                let err = match self.ir.locs.get(&self.ip) {
+                    Some(loc) => loc.wrap(err),
+                    None => err,
+                };
+                // Runtime errors are caught as their message, other errors are bugs of the program.
+                if !err.is::<RuntimeError>() || !self.unwind(Value::Error(err.to_string().into())) {
+                    return Err(err);
                 }
-                Operation::CmpEq { lhs, rhs, out } => {
-                    let lhs_val = self.reg_get(lhs);
-                    let rhs_val = self.reg_get(rhs);
-                    self.reg_set(*out, if lhs_val == rhs_val { 1 } else { 0 });
-                }
-                Operation::CmpLt { lhs, rhs, out } => {
-                    let lhs_val = self.reg_get(lhs);
-                    let rhs_val = self.reg_get(rhs);
-                    self.reg_set(*out, if lhs_val < rhs_val { 1 } else { 0 });
-                }
-                Operation::CmpLte { lhs, rhs, out } => {
-                    let lhs_val = self.reg_get(lhs);
-                    let rhs_val = self.reg_get(rhs);
-                    self.reg_set(*out, if lhs_val <= rhs_val { 1 } else { 0 });
-                }
-                Operation::CmpGt { lhs, rhs, out } => {
-                    let lhs_val = self.reg_get(lhs);
-                    let rhs_val = self.reg_get(rhs);
-                    self.reg_set(*out, if lhs_val > rhs_val { 1 } else { 0 });
+            }
+
+            self.ip += 1;
+        }
+
+        Ok(self.ir.return_reg.map(|reg| self.reg_get(&reg)))
+    }
+
+    /**
+     * Executes the instruction at `ip`. Jumps leave `ip` at the target label, which `run` skips.
+     */
+    fn step(&mut self) -> Result<(), Error> {
+        match &self.ir.instructions[self.ip] {
+            Operation::Label(_) => {} // Skip.
+            Operation::Call(label) => {
+                self.return_ips.push(self.ip);
+                self.frames.push(Scope::new());
+                self.ip = self.label_map[label];
+            }
+            Operation::Return => {
+                self.ip = self.return_ips.pop().expect("Missing return IP");
+                self.frames.pop().expect("Cannot pop more frames");
+            }
+            Operation::Push(reg) => {
+                let value = self.reg_get(reg);
+                self.stack.push(value);
+            }
+            Operation::PushI(value) => self.stack.push(Value::Int(*value)),
+            Operation::Pop(reg) => {
+                let value = self.stack.pop().expect("Empty stack");
+                self.reg_set(*reg, value);
+            }
+            Operation::Alloc { size, out } => {
+                let addr = self.heap.len();
+                let size = self.reg_int(size)?;
+                if size < 0 {
+                    return Err(format!("Invalid allocation size: {}", size).into());
                 }
-                Operation::CmpGte { lhs, rhs, out } => {
-                    let lhs_val = self.reg_get(lhs);
-                    let rhs_val = self.reg_get(rhs);
-                    self.reg_set(*out, if lhs_val >= rhs_val { 1 } else { 0 });
+                self.heap.resize(addr + size as usize, Value::Int(0));
+                self.reg_set(*out, Value::Int(addr as i64));
+            }
+            Operation::Abort { msg, args } => {
+                let mut msg = msg.clone();
+                for arg in args {
+                    msg = msg.replacen("{}", &self.reg_get(arg).to_string(), 1);
                 }
-                Operation::CondBranch {
-                    cond,
-                    label_true,
-                    label_false,
-                } => {
-                    let val = self.reg_get(cond);
-                    self.ip = if val == 1 {
-                        self.label_map[label_true]
-                    } else {
-                        self.label_map[label_false]
-                    };
+                return Err(runtime_error(msg));
+            }
+            Operation::PushHandler { label, reg } => {
+                self.handlers.push(Handler {
+                    label: label.clone(),
+                    reg: *reg,
+                    frame_count: self.frames.len(),
+                    return_ip_count: self.return_ips.len(),
+                    stack_len: self.stack.len(),
+                });
+            }
+            Operation::PopHandler => {
+                self.handlers.pop().expect("Missing handler");
+            }
+            Operation::Throw(reg) => {
+                let value = self.reg_get(reg);
+                if !self.unwind(value.clone()) {
+                    return Err(format!("Uncaught exception: {}", value).into());
                 }
-                op => unimplemented!("Operation {:?} not implemented.", op),
             }
-
-            self.ip += 1;
+            Operation::Add { lhs, rhs, out } => self.arith_op(&Op::Add, *lhs, *rhs, *out)?,
+            Operation::Sub { lhs, rhs, out } => self.arith_op(&Op::Sub, *lhs, *rhs, *out)?,
+            Operation::Mul { lhs, rhs, out } => self.arith_op(&Op::Mul, *lhs, *rhs, *out)?,
+            Operation::Div { lhs, rhs, out } => self.arith_op(&Op::Div, *lhs, *rhs, *out)?,
+            Operation::Mod { lhs, rhs, out } => self.arith_op(&Op::Mod, *lhs, *rhs, *out)?,
+            Operation::Pow { lhs, rhs, out } => self.arith_op(&Op::Pow, *lhs, *rhs, *out)?,
+            Operation::And { lhs, rhs, out } => {
+                let lhs_val = self.reg_bits(lhs)?;
+                let rhs_val = self.reg_bits(rhs)?;
+                self.reg_set(*out, Value::Int(lhs_val & rhs_val));
+            }
+            Operation::Or { lhs, rhs, out } => {
+                let lhs_val = self.reg_bits(lhs)?;
+                let rhs_val = self.reg_bits(rhs)?;
+                self.reg_set(*out, Value::Int(lhs_val | rhs_val));
+            }
+            Operation::Xor { lhs, rhs, out } => {
+                let lhs_val = self.reg_bits(lhs)?;
+                let rhs_val = self.reg_bits(rhs)?;
+                self.reg_set(*out, Value::Int(lhs_val ^ rhs_val));
+            }
+            Operation::LShift { lhs, rhs, out } => {
+                let lhs_val = self.reg_bits(lhs)?;
+                let rhs_val = self.reg_bits(rhs)?;
+                self.reg_set(*out, Value::Int(int_shl(lhs_val, rhs_val)?));
+            }
+            Operation::RShift { lhs, rhs, out } => {
+                let lhs_val = self.reg_bits(lhs)?;
+                let rhs_val = self.reg_bits(rhs)?;
+                self.reg_set(*out, Value::Int(int_shr(lhs_val, rhs_val)?));
+            }
+            Operation::AddI { lhs, rhs, out } => self.arith_op_i(&Op::Add, *lhs, *rhs, *out)?,
+            Operation::SubI { lhs, rhs, out } => self.arith_op_i(&Op::Sub, *lhs, *rhs, *out)?,
+            Operation::MulI { lhs, rhs, out } => self.arith_op_i(&Op::Mul, *lhs, *rhs, *out)?,
+            Operation::DivI { lhs, rhs, out } => self.arith_op_i(&Op::Div, *lhs, *rhs, *out)?,
+            Operation::PowI { lhs, rhs, out } => self.arith_op_i(&Op::Pow, *lhs, *rhs, *out)?,
+            Operation::AndI { lhs, rhs, out } => {
+                let lhs_val = self.reg_bits(lhs)?;
+                self.reg_set(*out, Value::Int(lhs_val & *rhs));
+            }
+            Operation::OrI { lhs, rhs, out } => {
+                let lhs_val = self.reg_bits(lhs)?;
+                self.reg_set(*out, Value::Int(lhs_val | *rhs));
+            }
+            Operation::XorI { lhs, rhs, out } => {
+                let lhs_val = self.reg_bits(lhs)?;
+                self.reg_set(*out, Value::Int(lhs_val ^ *rhs));
+            }
+            Operation::LShiftI { lhs, rhs, out } => {
+                let lhs_val = self.reg_bits(lhs)?;
+                self.reg_set(*out, Value::Int(int_shl(lhs_val, *rhs)?));
+            }
+            Operation::RShiftI { lhs, rhs, out } => {
+                let lhs_val = self.reg_bits(lhs)?;
+                self.reg_set(*out, Value::Int(int_shr(lhs_val, *rhs)?));
+            }
+            Operation::Sqrt { reg, out } => {
+                let value = self.reg_get(reg).as_float();
+                self.reg_set(*out, Value::Float(value.sqrt()));
+            }
+            Operation::Floor { reg, out } => {
+                let value = self.reg_get(reg).as_float();
+                self.reg_set(*out, float_to_int(value.floor())?.into());
+            }
+            Operation::Round { reg, out } => {
+                let value = self.reg_get(reg).as_float();
+                self.reg_set(*out, float_to_int(value.round())?.into());
+            }
+            Operation::LoadI { val, out } => {
+                self.reg_set(*out, Value::Int(*val));
+            }
+            Operation::LoadBigI { val, out } => {
+                self.reg_set(*out, Value::BigInt(Rc::new(val.clone())));
+            }
+            Operation::LoadF { val, out } => {
+                self.reg_set(*out, Value::Float(*val));
+            }
+            Operation::LoadNull { out } => {
+                self.reg_set(*out, Value::Null);
+            }
+            Operation::Load { addr, out } => {
+                let value = self.heap_get(self.reg_int(addr)?)?;
+                self.reg_set(*out, value);
+            }
+            Operation::LoadAI { addr, offs, out } => {
+                let value = self.heap_get(self.reg_int(addr)? + offs)?;
+                self.reg_set(*out, value);
+            }
+            Operation::LoadAO { addr, offs, out } => {
+                let value = self.heap_get(self.reg_int(addr)? + self.reg_int(offs)?)?;
+                self.reg_set(*out, value);
+            }
+            Operation::Store { reg, addr } => {
+                self.heap_set(self.reg_int(addr)?, self.reg_get(reg))?;
+            }
+            Operation::StoreAI { reg, addr, offs } => {
+                self.heap_set(self.reg_int(addr)? + offs, self.reg_get(reg))?;
+            }
+            Operation::StoreAO { reg, addr, offs } => {
+                self.heap_set(self.reg_int(addr)? + self.reg_int(offs)?, self.reg_get(reg))?;
+            }
+            Operation::I2i { lhs, rhs } => {
+                self.reg_set(*rhs, self.reg_get(lhs));
+            }
+            Operation::JumpI(label) => {
+                self.ip = self.label_map[label];
+            }
+            Operation::Jump(reg) => {
+                let addr = self.reg_int(reg)?;
+                self.ip = usize::try_from(addr)
+                    .ok()
+                    .filter(|addr| *addr < self.ir.instructions.len())
+                    .ok_or(format!("Invalid jump address: {}", addr))?;
+            }
+            Operation::Tbl { reg, label } => {
+                self.reg_set(*reg, Value::Int(self.label_map[label] as i64));
+            }
+            Operation::CmpEq { lhs, rhs, out } => self.cmp_op(&Op::Eq, *lhs, *rhs, *out),
+            Operation::CmpLt { lhs, rhs, out } => self.cmp_op(&Op::Lt, *lhs, *rhs, *out),
+            Operation::CmpLte { lhs, rhs, out } => self.cmp_op(&Op::Lte, *lhs, *rhs, *out),
+            Operation::CmpGt { lhs, rhs, out } => self.cmp_op(&Op::Gt, *lhs, *rhs, *out),
+            Operation::CmpGte { lhs, rhs, out } => self.cmp_op(&Op::Gte, *lhs, *rhs, *out),
+            Operation::CondBranch {
+                cond,
+                label_true,
+                label_false,
+            } => {
+                self.ip = if self.reg_get(cond).is_truthy() {
+                    self.label_map[label_true]
+                } else {
+                    self.label_map[label_false]
+                };
+            }
+            op => unimplemented!("Operation {:?} not implemented.", op),
         }
+
+        Ok(())
+    }
+
+    fn arith_op(&mut self, op: &Op, lhs: Reg, rhs: Reg, out: Reg) -> Result<(), Error> {
+        let value = arith(op, self.reg_get(&lhs), self.reg_get(&rhs))?;
+        self.reg_set(out, value);
+        Ok(())
+    }
+
+    fn arith_op_i(&mut self, op: &Op, lhs: Reg, rhs: ImmVal, out: Reg) -> Result<(), Error> {
+        let value = arith(op, self.reg_get(&lhs), Value::Int(rhs))?;
+        self.reg_set(out, value);
+        Ok(())
+    }
+
+    fn cmp_op(&mut self, op: &Op, lhs: Reg, rhs: Reg, out: Reg) {
+        let value = compare_values(op, self.reg_get(&lhs), self.reg_get(&rhs));
+        self.reg_set(out, Value::Int(value as i64));
+    }
+
+    /**
+     * Jumps to the innermost handler, dropping the frames, return addresses and stack values
+     * pushed since it was set up. Returns false when there is no handler.
+     */
+    fn unwind(&mut self, value: Value) -> bool {
+        let Some(handler) = self.handlers.pop() else {
+            return false;
+        };
+
+        self.frames.truncate(handler.frame_count);
+        self.return_ips.truncate(handler.return_ip_count);
+        self.stack.truncate(handler.stack_len);
+        self.ip = self.label_map[&handler.label];
+        self.reg_set(handler.reg, value);
+        true
+    }
+
+    fn heap_get(&self, addr: i64) -> Result<Value, Error> {
+        usize::try_from(addr)
+            .ok()
+            .and_then(|addr| self.heap.get(addr))
+            .cloned()
+            .ok_or(format!("Invalid heap address: {}", addr).into())
+    }
+
+    fn heap_set(&mut self, addr: i64, value: Value) -> Result<(), Error> {
+        let cell = usize::try_from(addr)
+            .ok()
+            .and_then(|addr| self.heap.get_mut(addr))
+            .ok_or::<Error>(format!("Invalid heap address: {}", addr).into())?;
+        *cell = value;
+        Ok(())
     }
 
     #[inline]
-    fn reg_set(&mut self, reg: Reg, value: i32) {
+    fn reg_set(&mut self, reg: Reg, value: Value) {
         match reg {
             Reg::Arp(arp_offs) => {
                 self.frames.last_mut().expect("Missing frame").registers[arp_offs] = value
@@ -159,18 +424,71 @@ impl VM {
     }
 
     #[inline]
-    fn reg_get(&self, reg: &Reg) -> i32 {
+    fn reg_get(&self, reg: &Reg) -> Value {
         match reg {
             Reg::Arp(arp_offs) => {
-                self.frames.last().expect("Missing last frame").registers[*arp_offs]
+                self.frames.last().expect("Missing last frame").registers[*arp_offs].clone()
+            }
+            Reg::Global(offs) => {
+                self.frames.first().expect("Missing first frame").registers[*offs].clone()
             }
-            Reg::Global(offs) => self.frames.first().expect("Missing first frame").registers[*offs],
         }
     }
+
+    fn reg_int(&self, reg: &Reg) -> Result<i64, Error> {
+        self.reg_get(reg).as_int()
+    }
+
+    /**
+     * An operand of the bitwise operators, which do not apply to bigints.
+     */
+    fn reg_bits(&self, reg: &Reg) -> Result<i64, Error> {
+        match self.reg_get(reg) {
+            Value::BigInt(v) => Err(bitwise_range_error(&v)),
+            other => other.as_int(),
+        }
+    }
+}
+
+/**
+ * Ints are promoted to bigints on overflow, anything involving a float is computed on floats.
+ */
+fn arith(op: &Op, lhs: Value, rhs: Value) -> Result<Value, Error> {
+    let result = match (&lhs, &rhs) {
+        (Value::Null | Value::Error(_), _) | (_, Value::Null | Value::Error(_)) => {
+            return Err(format!("Incompatible binop types: {} {:?} {}", lhs, op, rhs).into())
+        }
+        (Value::Int(a), Value::Int(b)) => int_arith(op, *a, *b)?.map(Value::from),
+        (Value::Float(_), _) | (_, Value::Float(_)) => {
+            float_arith(op, lhs.as_float(), rhs.as_float()).map(Value::Float)
+        }
+        _ => match (lhs.as_big_int(), rhs.as_big_int()) {
+            (Some(a), Some(b)) => big_arith(op, &a, &b)?.map(Value::from),
+            _ => None,
+        },
+    };
+
+    result.ok_or(format!("Not an arithmetic operator: {:?}", op).into())
+}
+
+fn compare_values(op: &Op, lhs: Value, rhs: Value) -> bool {
+    let result = match (&lhs, &rhs) {
+        // Null and errors only equal themselves and have no order.
+        (Value::Null | Value::Error(_), _) | (_, Value::Null | Value::Error(_)) => {
+            Some(*op == Op::Eq && lhs == rhs)
+        }
+        (Value::Int(a), Value::Int(b)) => compare(op, a, b),
+        (Value::Float(_), _) | (_, Value::Float(_)) => compare(op, lhs.as_float(), rhs.as_float()),
+        _ => compare(op, lhs.as_big_int(), rhs.as_big_int()),
+    };
+
+    result.unwrap_or(false)
 }
 
 #[cfg(test)]
 mod test {
+    use crate::ast::*;
+    use crate::interpreter::{ExprResult, Interpreter};
     use crate::ir_builder::*;
     use crate::lexer::*;
     use crate::parser::*;
@@ -230,6 +548,21 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_pipe() {
+        let input = r#"
+            fn powadd(a, b) { a * a + b * b; }
+            fn half(x) { x / 2; }
+            fn sub(a, b) { a - b; }
+            2 |> powadd(4) |> half() |> sub(3);
+        "#;
+        assert_eq!(Some(7), vm_this(input));
+        assert_eq!(
+            Some(ExprResult::Int(7)),
+            Interpreter::new().interpret(parse(input)).unwrap()
+        );
+    }
+
     #[test]
     fn test_if() {
         assert_eq!(
@@ -259,8 +592,8 @@ mod test {
         );
 
         assert_eq!(
-            Some(0),
-            vm_this(
+            Some(Value::Null),
+            vm_value(
                 r#"
                 if (3 == 2) {
                     3;
@@ -299,19 +632,640 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_op_assignment() {
+        assert_eq!(
+            Some(4),
+            vm_this("a = 10; a += 4; a -= 2; a *= 3; a /= 4; a %= 5; a;")
+        );
+        assert_eq!(
+            Some(55),
+            vm_this("i = 0; sum = 0; loop { if (i >= 10) { break; } i += 1; sum += i; } sum;")
+        );
+    }
+
+    #[test]
+    fn test_constants() {
+        assert_eq!(
+            Some(29),
+            vm_this(
+                r#"
+                const SIZE = 4;
+                const AREA = SIZE * (SIZE + 1);
+                i = 0;
+                loop { if (i >= AREA) { break; } i += SIZE; }
+                i + AREA / SIZE + SIZE;
+            "#
+            )
+        );
+
+        // Arguments shadow constants of the same name.
+        let input = "const N = 4; fn scale(N) { N * 10 + N; } scale(2) + N;";
+        assert_eq!(Some(26), vm_this(input));
+        assert_eq!(
+            Some(ExprResult::Int(26)),
+            Interpreter::new().interpret(parse(input)).unwrap()
+        );
+    }
+
     #[test]
     fn test_expr_binop_mod() {
         assert_eq!(Some(3), vm_this("23 % 5;"));
         assert_eq!(Some(0), vm_this("25 % 5;"));
     }
 
-    fn vm_this(input: &'static str) -> Option<i32> {
+    #[test]
+    fn test_bitwise_ops() {
+        assert_eq!(
+            Some(186),
+            vm_this(
+                r#"
+                const MASK = 255;
+                const BITS = 2 ** 3;
+                fn checksum(a, b) { (a << BITS | b) & MASK ^ (a >> 1); }
+                x = ~5 & MASK;
+                x >>= 1;
+                checksum(3, 7) * 10 + x ** 1 + (3 ** 2 & MASK) - BITS;
+            "#
+            )
+        );
+        assert_eq!(
+            "Shift amount out of range: 70 at 1:11",
+            vm_error("a = 70; 1 << a;")
+        );
+    }
+
+    #[test]
+    fn test_for_in() {
+        assert_eq!(
+            Some(1_050),
+            vm_this(
+                r#"
+                fn sum_to(n) {
+                    sum = 0;
+                    for i in 1..=n { sum += i; i = 0; }
+                    sum;
+                }
+                squares = 0;
+                for i in 0..4 { squares += i * i; if (i == 2) { break; } }
+                for i in 5..0 { squares = 100; }
+                sum_to(10) * 10 + squares * 100 + sum_to(0);
+            "#
+            )
+        );
+    }
+
+    #[test]
+    fn test_try_catch() {
+        assert_eq!(
+            Some(4_400_433),
+            vm_this(
+                r#"
+                fn check(n) {
+                    if (n > 10) { throw n * 100; }
+                    n;
+                }
+                fn deep(n) {
+                    if (n == 0) { throw 42; }
+                    deep(n - 1) + 1;
+                }
+                a = try { check(3); } catch (e) { 0; };
+                b = try { check(14); } catch (e) { e; };
+                c = try { deep(5); } catch (e) { e + 1; };
+                sum = 0;
+                for i in 0..5 {
+                    try {
+                        if (i == 3) { throw i; }
+                        sum += i;
+                    } catch (stop) { break; }
+                }
+                a * 1000000 + b * 1000 + c * 10 + sum;
+            "#
+            )
+        );
+        let input = "try { [1][5]; } catch (e) { e; }";
+        assert_eq!(
+            Some(Value::Error("Index out of bounds: 5 (length 1)".into())),
+            vm_value(input)
+        );
+        assert_eq!(
+            Some(ExprResult::Str(
+                "Index out of bounds: 5 (length 1)".to_string()
+            )),
+            Interpreter::new().interpret(parse(input)).unwrap()
+        );
+        assert_eq!(
+            Some(Value::Error("Division by zero at 1:9".into())),
+            vm_value("try { 1 / 0; } catch (e) { e; }")
+        );
+        assert_eq!(
+            Some(Value::Int(2)),
+            vm_value("a = try { [1][5]; } catch (e) { e; }; b = try { throw 0; } catch (e) { e; }; if (a == b) { 1; } else { 2; }")
+        );
+        assert_eq!(
+            "Uncaught exception: 7",
+            vm_error("fn f(n) { if (n > 0) { throw n; } n; } f(7);")
+        );
+        assert_eq!(
+            Some(4),
+            vm_this("fn f() { throw 3; } try { f(); } catch (e) { e + 1; }")
+        );
+        assert_eq!(
+            "Incompatible binop types: null Add 1 at 1:13",
+            vm_error("fn f(a) { a + 1; } x = try { f(null); } catch (e) { 42; }; x;")
+        );
+        assert_eq!(
+            "Invalid heap address: 5",
+            vm_error("fn f(a) { a[0]; } x = try { f(5); } catch (e) { 42; }; x;")
+        );
+    }
+
+    #[test]
+    fn test_else_if_and_block() {
+        assert_eq!(
+            Some(3_125),
+            vm_this(
+                r#"
+                fn grade(n) {
+                    if (n > 90) { 1; } else if (n > 50) { 2; } else if (n > 10) { 3; } else { 4; }
+                }
+                x = 1;
+                y = {
+                    x = 2;
+                    z = 3;
+                    x + z;
+                };
+                grade(30) * 1000 + grade(95) * 100 + x * 10 + y;
+            "#
+            )
+        );
+    }
+
+    #[test]
+    fn test_loop_labels() {
+        assert_eq!(
+            Some(1_272),
+            vm_this(
+                r#"
+                sum = 0;
+                'outer: for i in 0..5 {
+                    for j in 0..5 {
+                        if (j == 2) { continue 'outer; }
+                        if (i == 3) { break 'outer; }
+                        sum += i * 10 + j;
+                    }
+                }
+                found = 'search: loop {
+                    i = 0;
+                    loop {
+                        i += 1;
+                        if (i % 2 == 1) { continue; }
+                        if (i > 10) { break 'search i; }
+                    }
+                };
+                sum * 20 + found;
+            "#
+            )
+        );
+    }
+
+    #[test]
+    fn test_loop_break_value() {
+        assert_eq!(
+            Some(1_685),
+            vm_this(
+                r#"
+                fn first_power_over(limit) {
+                    p = 1;
+                    loop {
+                        if (p > limit) { break p; }
+                        p *= 2;
+                    }
+                }
+                fn first_square_over(limit) {
+                    for i in 0..limit { if (i * i > limit) { break i; } }
+                }
+                a = loop {
+                    b = loop { break 3; };
+                    break b * 2;
+                };
+                first_power_over(10) * 100 + first_square_over(50) * 10 + a - 1 + (first_square_over(0) ?? 0);
+            "#
+            )
+        );
+    }
+
+    #[test]
+    fn test_array() {
+        assert_eq!(
+            Some(7),
+            vm_this("a = [3, 4, [5, 6]]; a[0] + a[1 + 1][1] - 2;")
+        );
+        assert_eq!(
+            Some(21),
+            vm_this("a = [1, 2]; b = a; b[1] = 20; a[0] + a[1];")
+        );
+    }
+
+    #[test]
+    fn test_array_builtins() {
+        assert_eq!(
+            Some(3),
+            vm_this("a = []; push(a, 1); push(a, 2); push(a, 3); len(a);")
+        );
+        assert_eq!(Some(12), vm_this("a = [5, 7]; pop(a) + pop(a) + len(a);"));
+        assert_eq!(
+            Some(45),
+            vm_this(
+                r#"
+                a = [];
+                i = 0;
+                loop {
+                    if (i == 10) {
+                        break;
+                    }
+                    push(a, i);
+                    i = i + 1;
+                }
+                sum = 0;
+                loop {
+                    if (len(a) == 0) {
+                        break;
+                    }
+                    sum = sum + pop(a);
+                }
+                sum;
+            "#
+            )
+        );
+    }
+
+    #[test]
+    fn test_array_bounds_check() {
+        assert_eq!(
+            "Index out of bounds: 2 (length 2)",
+            vm_error("a = [1, 2]; a[2];")
+        );
+        assert_eq!(
+            "Index out of bounds: -1 (length 2)",
+            vm_error("a = [1, 2]; a[0 - 1] = 3;")
+        );
+        assert_eq!("Pop from empty array", vm_error("pop([]);"));
+    }
+
+    #[test]
+    fn test_struct() {
+        assert_eq!(
+            Some(13),
+            vm_this(
+                r#"
+                struct Point { x, y }
+                struct Line { from, to }
+                fn move_up(p) {
+                    p.y = 9;
+                }
+                start = Point { x: 1, y: 2 };
+                line = Line { from: start, to: Point { x: 3, y: 4 } };
+                move_up(start);
+                line.from.y + line.to.y;
+            "#
+            )
+        );
+        assert_eq!(
+            Some(321),
+            vm_this(
+                r#"
+                struct A { x, y }
+                struct B { y, z }
+                fn get_y(r) { r.y; }
+                fn set_y(r, v) { r.y = v; }
+                a = A { x: 1, y: 2 };
+                b = B { y: 20, z: 3 };
+                set_y(b, 300);
+                get_y(a) * 10 + get_y(b) + a.x + b.z - 3;
+            "#
+            )
+        );
+    }
+
+    #[test]
+    fn test_enum_and_match() {
+        assert_eq!(
+            Some(89),
+            vm_this(
+                r#"
+                enum Shape { Circle(r), Rect(w, h), Empty }
+                fn area(s) {
+                    match (s) {
+                        Shape::Circle(r) => { 3 * r * r; }
+                        Shape::Rect(w, h) => { w * h; }
+                        Shape::Empty => { 0; }
+                    }
+                }
+                area(Shape::Circle(5)) + area(Shape::Rect(2, 7)) + area(Shape::Empty);
+            "#
+            )
+        );
+        assert_eq!(
+            Some(7),
+            vm_this(
+                r#"
+                enum Shape { Circle(r), Rect(w, h), Empty }
+                match (Shape::Rect(3, 4)) {
+                    Shape::Circle(r) => { r; }
+                    other => { match (other) { Shape::Rect(w, h) => { w + h; } _ => { 0; } } }
+                }
+            "#
+            )
+        );
+        assert_eq!(
+            Some(20),
+            vm_this(
+                r#"
+                match (2 * 3) {
+                    5 => { 10; }
+                    6 => { 20; }
+                    _ => { 30; }
+                }
+            "#
+            )
+        );
+        assert_eq!(
+            Some(9),
+            vm_this("match (4) { 1 => { 2; } n => { n + 5; } }")
+        );
+    }
+
+    #[test]
+    fn test_match_in_loop_does_not_allocate() {
+        let heap_len = |input: &'static str| {
+            let reader = Box::new(StrReader::new(input));
+            let lexemes = Lexer::new(reader).read_any().unwrap();
+            let ast_root = Parser::new(lexemes.into()).build_ast().unwrap();
+            let mut vm = VM::new(IRBuilder::new().build(ast_root).unwrap());
+            vm.run().unwrap();
+            vm.heap.len()
+        };
+        assert_eq!(
+            heap_len("enum Shape { Circle(r), Empty } s = Shape::Circle(2);"),
+            heap_len(
+                r#"
+                enum Shape { Circle(r), Empty }
+                s = Shape::Circle(2);
+                sum = 0;
+                i = 0;
+                loop {
+                    if (i == 50) { break; }
+                    sum = sum + match (s) { Shape::Circle(r) => { r; } Shape::Empty => { 0; } };
+                    i = i + 1;
+                }
+                sum;
+            "#
+            )
+        );
+    }
+
+    #[test]
+    fn test_default_and_named_args() {
+        assert_eq!(
+            Some(1_210_703),
+            vm_this(
+                r#"
+                const TEN = 10;
+                fn f(a, b = TEN * 2, c = true) { a * 100 + b + c; }
+                fn g(x = 1) { x; }
+                f(1) * 10000 + f(c: 3, b: 0, a: 7) + g() - 1;
+            "#
+            )
+        );
+    }
+
+    #[test]
+    fn test_variadic_args() {
+        assert_eq!(
+            Some(1_007_000),
+            vm_this(
+                r#"
+                fn sum(base, ...xs) {
+                    i = 0;
+                    loop { if (i >= len(xs)) { break; } base += xs[i]; i += 1; }
+                    base;
+                }
+                fn count(...xs) { len(xs); }
+                sum(1) * 1000000 + sum(1, 2, 4) * 1000 + count();
+            "#
+            )
+        );
+    }
+
+    #[test]
+    fn test_tuple() {
+        assert_eq!(
+            Some(3121),
+            vm_this(
+                r#"
+                fn divmod(a, b) { (a / b, a % b); }
+                fn first(a, b) { (_, x) = (0, a); x; }
+                (q, r) = divmod(7, 2);
+                (x, y) = (1, 2);
+                (x, y) = (y, x);
+                (_, z) = divmod(first(9, 0), 5);
+                q * 1000 + r * 100 + x * 10 + y + z - 4;
+            "#
+            )
+        );
+    }
+
+    #[test]
+    fn test_float() {
+        assert_eq!(
+            Some(Value::Float(7.75)),
+            vm_value(
+                r#"
+                const HALF = 1.0 / 2;
+                fn area(r) { r * r; }
+                x = area(1.5) + HALF;
+                x += 1;
+                if (x > 3) { x += 2.5; }
+                x + 7.5 % 2;
+            "#
+            )
+        );
+        assert_eq!(
+            Some(Value::Float(3_323.0)),
+            vm_value(
+                r#"
+                ten = if (sqrt(16) == 4) { 10; } else { 0; };
+                floor(3.7) * 1000 + round(2.5) * 100 + ten * 2 + 5 / 2 + 0.5 * 2;
+            "#
+            )
+        );
+        assert_eq!(
+            "Expected an int, got 0.5",
+            vm_error("fn at(a, i) { a[i]; } at([1], 0.5);")
+        );
+
+        let input = "a = 0; if (0.5) { a += 1; } if (2) { a += 10; } if (0.0) { a += 100; } a;";
+        assert_eq!(Some(11), vm_this(input));
+        assert_eq!(
+            Some(ExprResult::Int(11)),
+            Interpreter::new().interpret(parse(input)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_null() {
+        assert_eq!(Some(Value::Null), vm_value("null;"));
+        assert_eq!(
+            Some(Value::Null),
+            vm_value("x = 0; loop { if (x > 2) { break; } x += 1; }")
+        );
+        assert_eq!(
+            Some(43),
+            vm_this(
+                r#"
+                fn maybe(n) { if (n > 0) { n; } }
+                fn count(n) { loop { break n; } }
+                a = maybe(0) ?? maybe(0) ?? 40;
+                b = maybe(1) ?? count(5);
+                c = if (is_null(maybe(0))) { 1; } else { 100; };
+                d = if (is_null(maybe(7))) { 100; } else { 1; };
+                a + b + c + d;
+            "#
+            )
+        );
+    }
+
+    #[test]
+    fn test_null_agrees_with_interpreter() {
+        let values = [
+            ("if (3 == 2) { 3; }", "null"),
+            ("x = if (3 == 2) { 3; }; x == 0;", "0"),
+            ("x = if (3 == 2) { 3; }; (x ?? 5) + 1;", "6"),
+            ("match (2) { 1 => { 3; } _ => {} }", "null"),
+            ("try { 1 / 0; } catch (e) {}", "null"),
+            ("if (1 == 1) {} else { 2; }", "null"),
+            ("a = 0; if (null) { a += 1; } a;", "0"),
+        ];
+        for (input, expected) in values {
+            assert_eq!(expected, vm_value(input).unwrap().to_string());
+            let result = Interpreter::new().interpret(parse(input)).unwrap().unwrap();
+            let result = match result {
+                ExprResult::Bool(b) => (b as i64).to_string(),
+                other => other.to_string(),
+            };
+            assert_eq!(expected, result);
+        }
+        assert_eq!(
+            "Incompatible binop types: null Add 1 at 1:27",
+            vm_error("x = if (0 == 1) { 1; }; x + 1;")
+        );
+    }
+
+    #[test]
+    fn test_big_int() {
+        let big: BigInt = "15511210043330985984000000".parse().unwrap();
+        assert_eq!(
+            Some(Value::BigInt(Rc::new(big))),
+            vm_value(
+                r#"
+                fn fact(n) { if (n <= 1) { 1; } else { n * fact(n - 1); } }
+                fact(25);
+            "#
+            )
+        );
+        assert_eq!(
+            Some(9_223_372_036_854_775_807),
+            vm_this(
+                r#"
+                max = 9223372036854775807;
+                big = max * 4;
+                if (big > max) { big / 2 - max; } else { 0; }
+            "#
+            )
+        );
+        assert_eq!(
+            "Bitwise operand out of range: 9223372036854775808 at 1:32",
+            vm_error("a = 9223372036854775807 + 1; a & 1;")
+        );
+        assert_eq!(Some(2), vm_this("const A = 2 ** 70; A / 2 ** 69;"));
+
+        let input = "const A = 2 ** 70; try { A >> 1; } catch (e) { e; }";
+        let msg = "Bitwise operand out of range: 1180591620717411303424 at 1:28";
+        assert_eq!(Some(Value::Error(msg.into())), vm_value(input));
+        assert_eq!(
+            Some(ExprResult::Str(msg.to_string())),
+            Interpreter::new().interpret(parse(input)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_arithmetic_agrees_with_interpreter() {
+        let errors = [
+            ("1 / 0;", "Division by zero at 1:3"),
+            ("a = 5;\nb = 0;\na % b;", "Division by zero at 3:3"),
+            (
+                "const Z = 0;\nfn f(x) { x /= Z; } f(1);",
+                "Division by zero at 2:13",
+            ),
+            (
+                "a = 9223372036854775807 * 2; a / (a - a);",
+                "Division by zero at 1:32",
+            ),
+            ("2 ** (0 - 1);", "Negative exponent: -1 at 1:3"),
+            ("1 << 64;", "Shift amount out of range: 64 at 1:3"),
+        ];
+        for (input, expected) in errors {
+            assert_eq!(expected, vm_error(input));
+            assert_eq!(
+                expected,
+                Interpreter::new()
+                    .interpret(parse(input))
+                    .unwrap_err()
+                    .to_string()
+            );
+        }
+
+        let values = [
+            ("9223372036854775807 + 1;", "9223372036854775808"),
+            (
+                "a = 0 - 9223372036854775807 - 1; a / (0 - 1);",
+                "9223372036854775808",
+            ),
+            ("a = 9223372036854775807 * 3; a % 2 + a / 3 - a / 3;", "1"),
+            ("7 / 2 * 2 + 7 % 2 - 0 - 7;", "0"),
+            ("try { 1 / 0; } catch (e) { 7; }", "7"),
+        ];
+        for (input, expected) in values {
+            assert_eq!(expected, vm_value(input).unwrap().to_string());
+            assert_eq!(
+                expected,
+                Interpreter::new()
+                    .interpret(parse(input))
+                    .unwrap()
+                    .unwrap()
+                    .to_string()
+            );
+        }
+    }
+
+    fn vm_this(input: &'static str) -> Option<i64> {
+        vm_value(input).map(|value| value.as_int().unwrap())
+    }
+
+    fn vm_value(input: &'static str) -> Option<Value> {
+        let ir = IRBuilder::new().build(parse(input)).unwrap();
+        VM::new(ir).run().unwrap()
+    }
+
+    fn vm_error(input: &'static str) -> String {
+        let ir = IRBuilder::new().build(parse(input)).unwrap();
+        VM::new(ir).run().unwrap_err().to_string()
+    }
+
+    fn parse(input: &str) -> AstProgram<'_> {
         let reader = Box::new(StrReader::new(input));
         let lexemes = Lexer::new(reader).read_any().unwrap();
-        let ast_root = Parser::new(lexemes.into()).build_ast().unwrap();
-        let ir = IRBuilder::new().build(ast_root).unwrap();
-        let mut vm = VM::new(ir);
-        vm.run();
-        vm.ir.return_reg.map(|reg| vm.reg_get(&reg))
+        Parser::new(lexemes.into()).build_ast().unwrap()
     }
 }